@@ -1,11 +1,12 @@
 use crate::typechecker::TaggedType;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunctionArg {
     pub name: String,
     pub name_loc: (usize, usize),
     pub dtype: String,
     pub size: usize,
+    pub tipe: TaggedType,
 }
 
 impl FunctionArg {
@@ -15,17 +16,19 @@ impl FunctionArg {
             name_loc,
             dtype,
             size: 0,
+            tipe: Default::default(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Node {
     Number(String, TaggedType, usize, usize),
     Float(String, TaggedType, usize, usize),
     StringLiteral(String, usize, usize),
     BoolLiteral(bool, TaggedType, usize, usize),
     ArrayLiteral(Vec<Box<Node>>, TaggedType, usize, usize),
+    ArrayRepeat(ArrayRepeat),
     VarGet(String, usize, usize),
     Binary(Binary),
     Function(Function),
@@ -42,6 +45,46 @@ pub enum Node {
     Block(Block),
     ExprStmt(ExprStmt),
     GetPtr(GetPtr),
+    Deref(Deref),
+    Store(Store),
+    Index(Index),
+    Slice(Slice),
+    Len(Len),
+    Cast(Cast),
+}
+
+impl Node {
+    /// Location of the first token that has one
+    pub fn loc(&self) -> Option<(usize, usize)> {
+        match self {
+            Node::Number(_, _, line, column)
+            | Node::Float(_, _, line, column)
+            | Node::StringLiteral(_, line, column)
+            | Node::BoolLiteral(_, _, line, column)
+            | Node::ArrayLiteral(_, _, line, column)
+            | Node::VarGet(_, line, column) => Some((*line, *column)),
+            Node::ArrayRepeat(repeat) => Some(repeat.loc),
+            Node::Binary(binary) => binary.lhs.loc(),
+            Node::Function(func) => Some(func.loc),
+            Node::VarDecl(decl) => Some(decl.name_loc),
+            Node::Grouping(grouping) => grouping.expr.loc(),
+            Node::Unary(unary) => Some(unary.op_loc),
+            Node::Logical(logical) => logical.lhs.loc(),
+            Node::Assign(assign) => Some(assign.name_loc),
+            Node::For(fr) => Some(fr.name_loc),
+            Node::If(ief) => ief.condition.loc(),
+            Node::Ret(ret) => Some(ret.loc),
+            Node::Block(block) => block.statements.iter().find_map(|stmt| stmt.loc()),
+            Node::ExprStmt(expr_stmt) => expr_stmt.expr.loc(),
+            Node::GetPtr(get_ptr) => get_ptr.expr.loc(),
+            Node::Deref(deref) => Some(deref.loc),
+            Node::Store(store) => store.target.loc(),
+            Node::Index(index) => index.expr.loc(),
+            Node::Slice(slice) => slice.expr.loc(),
+            Node::Len(len) => Some(len.loc),
+            Node::Cast(cast) => cast.expr.loc(),
+        }
+    }
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -50,6 +93,12 @@ pub enum BinaryOp {
     Sub,
     Mul,
     Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
     Greater,
     GreaterEq,
     Less,
@@ -65,6 +114,12 @@ impl std::fmt::Display for BinaryOp {
             Self::Sub => write!(f, "-"),
             Self::Mul => write!(f, "*"),
             Self::Div => write!(f, "/"),
+            Self::Rem => write!(f, "%"),
+            Self::BitAnd => write!(f, "&"),
+            Self::BitOr => write!(f, "|"),
+            Self::BitXor => write!(f, "^"),
+            Self::Shl => write!(f, "<<"),
+            Self::Shr => write!(f, ">>"),
             Self::Greater => write!(f, ">"),
             Self::GreaterEq => write!(f, ">="),
             Self::Less => write!(f, "<"),
@@ -75,6 +130,21 @@ impl std::fmt::Display for BinaryOp {
     }
 }
 
+impl BinaryOp {
+    /// Whether the operator compares its operands, giving a bool
+    pub fn is_comparison(&self) -> bool {
+        std::matches!(
+            self,
+            Self::Greater
+                | Self::GreaterEq
+                | Self::Less
+                | Self::LessEq
+                | Self::Equal
+                | Self::NotEqual
+        )
+    }
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum LogicalOp {
     And,
@@ -94,6 +164,7 @@ impl std::fmt::Display for LogicalOp {
 pub enum UnaryOp {
     Not,
     Negate,
+    BitNot,
     None,
 }
 
@@ -102,25 +173,88 @@ impl std::fmt::Display for UnaryOp {
         match self {
             Self::Not => write!(f, "!"),
             Self::Negate => write!(f, "-"),
+            Self::BitNot => write!(f, "~"),
             _ => unreachable!(),
         }
     }
 }
 
-#[derive(Debug)]
+/// What integer `+`, `-` and `*` do when the result doesn't fit
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Overflow {
+    /// Wraps around, unless compiled with `--overflow-checks`
+    Unchecked,
+    Wrapping,
+    /// Clamps to the smallest or largest value of the type
+    Saturating,
+    /// Panics
+    Checked,
+}
+
+impl Overflow {
+    /// Behaviour and operator of the arithmetic builtin `name`, like
+    /// `wrapping_add` or `saturating_mul`
+    pub fn builtin(name: &str) -> Option<(Overflow, BinaryOp)> {
+        let (overflow, op) = name.split_once('_')?;
+        let overflow = match overflow {
+            "wrapping" => Overflow::Wrapping,
+            "saturating" => Overflow::Saturating,
+            "checked" => Overflow::Checked,
+            _ => return None,
+        };
+        let op = match op {
+            "add" => BinaryOp::Add,
+            "sub" => BinaryOp::Sub,
+            "mul" => BinaryOp::Mul,
+            _ => return None,
+        };
+        Some((overflow, op))
+    }
+}
+
+impl std::fmt::Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unchecked => write!(f, "unchecked"),
+            Self::Wrapping => write!(f, "wrapping"),
+            Self::Saturating => write!(f, "saturating"),
+            Self::Checked => write!(f, "checked"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Binary {
     pub lhs: Box<Node>,
     pub rhs: Box<Node>,
     pub op: BinaryOp,
+    pub overflow: Overflow,
+    /// Type of the operands, filled in by the typechecker
+    pub dtype: TaggedType,
 }
 
 impl Binary {
     pub fn new(lhs: Box<Node>, rhs: Box<Node>, op: BinaryOp) -> Box<Node> {
-        Box::new(Node::Binary(Binary { lhs, rhs, op }))
+        Binary::with_overflow(lhs, rhs, op, Overflow::Unchecked)
+    }
+
+    pub fn with_overflow(
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+        op: BinaryOp,
+        overflow: Overflow,
+    ) -> Box<Node> {
+        Box::new(Node::Binary(Binary {
+            lhs,
+            rhs,
+            op,
+            overflow,
+            dtype: Default::default(),
+        }))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Function {
     pub name: String,
     pub loc: (usize, usize),
@@ -162,7 +296,7 @@ impl Function {
 //     }
 // }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VarDecl {
     pub name: String,
     pub name_loc: (usize, usize),
@@ -189,7 +323,7 @@ impl VarDecl {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Grouping {
     pub expr: Box<Node>,
 }
@@ -200,7 +334,7 @@ impl Grouping {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Unary {
     pub op: UnaryOp,
     pub op_loc: (usize, usize),
@@ -213,7 +347,7 @@ impl Unary {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Logical {
     pub lhs: Box<Node>,
     pub rhs: Box<Node>,
@@ -226,7 +360,7 @@ impl Logical {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Assign {
     pub name: String,
     pub name_loc: (usize, usize),
@@ -243,12 +377,15 @@ impl Assign {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct For {
     pub name: String,
     pub name_loc: (usize, usize),
     pub target: Box<Node>,
     pub body: Box<Node>,
+    /// Element type and array length, filled in like for an `Index`
+    pub dtype: TaggedType,
+    pub length: Option<usize>,
 }
 
 impl For {
@@ -263,11 +400,13 @@ impl For {
             name_loc,
             target,
             body,
+            dtype: Default::default(),
+            length: None,
         }))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct If {
     pub condition: Box<Node>,
     pub then_block: Box<Node>,
@@ -301,7 +440,7 @@ impl If {
 //     }
 // }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ret {
     pub value: Option<Box<Node>>,
     pub loc: (usize, usize),
@@ -313,7 +452,7 @@ impl Ret {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Block {
     pub statements: Vec<Box<Node>>,
 }
@@ -324,7 +463,7 @@ impl Block {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ExprStmt {
     pub expr: Box<Node>,
 }
@@ -335,7 +474,7 @@ impl ExprStmt {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GetPtr {
     pub expr: Box<Node>,
 }
@@ -345,3 +484,162 @@ impl GetPtr {
         Box::new(Node::GetPtr(GetPtr { expr }))
     }
 }
+
+/// Read of the value a pointer points to, `*p`
+#[derive(Debug, Clone)]
+pub struct Deref {
+    pub expr: Box<Node>,
+    pub loc: (usize, usize),
+    pub dtype: TaggedType,
+}
+
+impl Deref {
+    pub fn new(expr: Box<Node>, loc: (usize, usize)) -> Box<Node> {
+        Box::new(Node::Deref(Deref {
+            expr,
+            loc,
+            dtype: Default::default(),
+        }))
+    }
+}
+
+/// Assignment to memory instead of a variable, like `*p = v`
+#[derive(Debug, Clone)]
+pub struct Store {
+    pub target: Box<Node>,
+    pub value: Box<Node>,
+    /// `*p += 1`, the value is a `Binary` reading the target, whose address
+    /// is only computed once
+    pub compound: bool,
+}
+
+impl Store {
+    pub fn new(target: Box<Node>, value: Box<Node>) -> Box<Node> {
+        Box::new(Node::Store(Store {
+            target,
+            value,
+            compound: false,
+        }))
+    }
+
+    pub fn compound(target: Box<Node>, value: Box<Node>, op: BinaryOp) -> Box<Node> {
+        Box::new(Node::Store(Store {
+            value: Binary::new(target.clone(), value, op),
+            target,
+            compound: true,
+        }))
+    }
+}
+
+/// Array literal holding `count` times the same value, `{0; 16}`
+#[derive(Debug, Clone)]
+pub struct ArrayRepeat {
+    pub value: Box<Node>,
+    pub count: usize,
+    pub loc: (usize, usize),
+    pub element: TaggedType,
+}
+
+impl ArrayRepeat {
+    pub fn new(value: Box<Node>, count: usize, loc: (usize, usize)) -> Box<Node> {
+        Box::new(Node::ArrayRepeat(ArrayRepeat {
+            value,
+            count,
+            loc,
+            element: Default::default(),
+        }))
+    }
+}
+
+/// Element of an array or a slice, `a[i]`. The typechecker fills in the
+/// element type and the length of an array, a slice has its own.
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub expr: Box<Node>,
+    pub index: Box<Node>,
+    pub loc: (usize, usize),
+    pub dtype: TaggedType,
+    pub length: Option<usize>,
+}
+
+impl Index {
+    pub fn new(expr: Box<Node>, index: Box<Node>, loc: (usize, usize)) -> Box<Node> {
+        Box::new(Node::Index(Index {
+            expr,
+            index,
+            loc,
+            dtype: Default::default(),
+            length: None,
+        }))
+    }
+}
+
+/// Slice of the elements of an array or a slice from `start` up to `end`,
+/// `a[1..3]`. Left out bounds are the first and the last element.
+#[derive(Debug, Clone)]
+pub struct Slice {
+    pub expr: Box<Node>,
+    pub start: Option<Box<Node>>,
+    pub end: Option<Box<Node>>,
+    pub loc: (usize, usize),
+    /// Element type and array length, filled in like for an `Index`
+    pub dtype: TaggedType,
+    pub length: Option<usize>,
+}
+
+impl Slice {
+    pub fn new(
+        expr: Box<Node>,
+        start: Option<Box<Node>>,
+        end: Option<Box<Node>>,
+        loc: (usize, usize),
+    ) -> Box<Node> {
+        Box::new(Node::Slice(Slice {
+            expr,
+            start,
+            end,
+            loc,
+            dtype: Default::default(),
+            length: None,
+        }))
+    }
+}
+
+/// Number of elements of an array or a slice, `len(a)`
+#[derive(Debug, Clone)]
+pub struct Len {
+    pub expr: Box<Node>,
+    pub loc: (usize, usize),
+}
+
+impl Len {
+    pub fn new(expr: Box<Node>, loc: (usize, usize)) -> Box<Node> {
+        Box::new(Node::Len(Len { expr, loc }))
+    }
+}
+
+/// Conversion of a value to another type, written as `expr as type` or
+/// inserted by the typechecker for implicit widening
+#[derive(Debug, Clone)]
+pub struct Cast {
+    pub expr: Box<Node>,
+    pub loc: (usize, usize),
+    pub dtype_str: String,
+    pub dtype: TaggedType,
+}
+
+impl Cast {
+    pub fn new(
+        expr: Box<Node>,
+        loc: (usize, usize),
+        dtype_str: String,
+        dtype: TaggedType,
+    ) -> Box<Node> {
+        Box::new(Node::Cast(Cast {
+            expr,
+            loc,
+            dtype_str,
+            dtype,
+        }))
+    }
+}
@@ -25,6 +25,24 @@ impl Config {
         config
             .value_options
             .insert("rt".to_string(), "false".to_string());
+        config
+            .value_options
+            .insert("O0".to_string(), "false".to_string());
+        config
+            .value_options
+            .insert("O1".to_string(), "false".to_string());
+        config
+            .value_options
+            .insert("O2".to_string(), "false".to_string());
+        config
+            .value_options
+            .insert("time-passes".to_string(), "false".to_string());
+        config
+            .value_options
+            .insert("regalloc-stats".to_string(), "false".to_string());
+        config
+            .value_options
+            .insert("overflow-checks".to_string(), "false".to_string());
 
         let options = parse_options();
         for option in options {
@@ -51,4 +69,8 @@ impl Config {
             .parse::<bool>()
             .unwrap()
     }
+
+    pub fn get_value<S: Into<String>>(&self, name: S) -> Option<&String> {
+        self.value_options.get(&name.into())
+    }
 }
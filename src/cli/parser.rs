@@ -13,10 +13,12 @@ pub fn parse_options() -> Vec<CliOption> {
     loop {
         if let Some(current) = args.next() {
             if current.starts_with("--") {
-                let delimeter = current
-                    .chars()
-                    .position(|x| x == '=')
-                    .expect("CLI: Expected a '=' after option name");
+                // `--name` without a value is a flag, like `-name`
+                let Some(delimeter) = current.chars().position(|x| x == '=') else {
+                    let name = current.chars().skip(2).collect::<String>();
+                    options.push(CliOption::TrueBool(name));
+                    continue;
+                };
                 let name = current
                     .chars()
                     .skip(2)
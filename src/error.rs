@@ -1,3 +1,5 @@
+use colored::Colorize;
+
 pub fn panic(message: String) -> ! {
     log::error!("{}", message);
     std::process::exit(1)
@@ -7,3 +9,7 @@ pub fn panic_str(message: &str) -> ! {
     log::error!("{}", message);
     std::process::exit(1)
 }
+
+pub fn warn(message: String) {
+    eprintln!("{}: {}", "warning".yellow(), message);
+}
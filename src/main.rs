@@ -3,7 +3,15 @@ extern crate lazy_static;
 use cli::config::Config;
 use parser::Parser;
 use reg::RegisterManager;
-use ssir::{print_functions, transform::RegisterLabeler, SSir};
+use ssir::{
+    cfg::{self, Cfg},
+    codegen, frame,
+    opt::{dce, fold},
+    pass::{OptLevel, PassManager},
+    print_functions,
+    regalloc::{coloring, linear},
+    SSir,
+};
 use tokenizer::Tokenizer;
 use typechecker::TypeCheck;
 
@@ -16,6 +24,15 @@ mod ssir;
 mod tokenizer;
 mod typechecker;
 
+/// The highest `-O` flag given, -O1 by default
+fn opt_level(config: &Config) -> OptLevel {
+    ["O2", "O1", "O0"]
+        .into_iter()
+        .find(|flag| config.get_bool(*flag))
+        .and_then(OptLevel::from_flag)
+        .unwrap_or(OptLevel::O1)
+}
+
 fn main() {
     env_logger::init();
 
@@ -50,20 +67,72 @@ fn main() {
     for decl in &mut parser.declarations {
         typecheck.check(decl);
     }
+    if typecheck.has_errors() {
+        error::panic_str("Aborting because of the previous errors");
+    }
 
     if config.get_bool("pat") {
         println!("{:#?}", parser.declarations);
     }
 
     let mut ssir = SSir::new();
+    ssir.overflow_checks(config.get_bool("overflow-checks"));
     ssir.generate(&mut parser.declarations);
 
     drop(parser);
 
-    let mut labeler = RegisterLabeler::new();
-    let functions = labeler.assign_labels(ssir.get_functions());
+    let mut cfgs = ssir
+        .get_functions()
+        .into_iter()
+        .map(Cfg::build)
+        .collect::<Vec<Cfg>>();
+
+    for cfg in &cfgs {
+        dce::report_dead_code(cfg);
+        fold::report_constant_errors(cfg);
+    }
+
+    let opt_level = opt_level(&config);
+    let mut passes = PassManager::new(opt_level);
+    if let Some(names) = config.get_value("enable-pass") {
+        names.split(',').for_each(|name| passes.enable(name));
+    }
+    if let Some(names) = config.get_value("disable-pass") {
+        names.split(',').for_each(|name| passes.disable(name));
+    }
+    if let Some(names) = config.get_value("print-after") {
+        names.split(',').for_each(|name| passes.print_after(name));
+    }
+    passes.time_passes(config.get_bool("time-passes"));
+    passes.run(&mut cfgs);
+
+    if config.get_value("emit").map(String::as_str) == Some("cfg-dot") {
+        print!("{}", cfg::to_dot(&cfgs));
+    }
+
+    // The slower graph coloring allocator is only worth it at -O2
+    let allocate = match opt_level {
+        OptLevel::O2 => coloring::allocate,
+        _ => linear::allocate,
+    };
+    let functions = cfgs
+        .into_iter()
+        .map(|mut cfg| {
+            let stats = allocate(&mut cfg);
+            frame::layout(&mut cfg);
+            if config.get_bool("regalloc-stats") {
+                println!("func {}:", cfg.name);
+                stats.table(None);
+            }
+            cfg.into_function()
+        })
+        .collect();
 
     if config.get_bool("ssir") {
         print_functions(&functions);
     }
+
+    if config.get_value("emit").map(String::as_str) == Some("asm") {
+        print!("{}", codegen::emit(&functions));
+    }
 }
@@ -1,17 +1,18 @@
 use crate::{
     ast::{
-        Assign, Binary, BinaryOp, Block, ExprStmt, For, Function, FunctionArg, GetPtr, Grouping,
-        If, Logical, LogicalOp, Node, Ret, Unary, UnaryOp, VarDecl,
+        ArrayRepeat, Assign, Binary, BinaryOp, Block, Cast, Deref, ExprStmt, For, Function,
+        FunctionArg, GetPtr, Grouping, If, Index, Len, Logical, LogicalOp, Node, Overflow, Ret,
+        Slice, Store, Unary, UnaryOp, VarDecl,
     },
     tokenizer::{get_tok_len, get_tok_loc, TokenKind, Tokenizer},
+    typechecker::array_bracket,
 };
 use colored::Colorize;
 use log::error;
 
 enum ParserContext {
     None,
-    ArrayParse(Option<u32>),
-    ArrayLiteral,
+    ArrayParse,
 }
 
 macro_rules! matches {
@@ -35,6 +36,31 @@ macro_rules! consume {
 
 type ParseResult<T> = Result<T, String>;
 
+/// Operator a compound assignment applies, `x += 1` is `x = x + 1`
+fn compound_op(token: &TokenKind) -> Option<BinaryOp> {
+    let op = match token {
+        TokenKind::PlusEq(_, _) => BinaryOp::Add,
+        TokenKind::MinusEq(_, _) => BinaryOp::Sub,
+        TokenKind::StarEq(_, _) => BinaryOp::Mul,
+        TokenKind::SlashEq(_, _) => BinaryOp::Div,
+        TokenKind::PercentEq(_, _) => BinaryOp::Rem,
+        TokenKind::AmpersandEq(_, _) => BinaryOp::BitAnd,
+        TokenKind::PipeEq(_, _) => BinaryOp::BitOr,
+        TokenKind::CaretEq(_, _) => BinaryOp::BitXor,
+        TokenKind::ShiftLeftEq(_, _) => BinaryOp::Shl,
+        TokenKind::ShiftRightEq(_, _) => BinaryOp::Shr,
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn negate(literal: String) -> String {
+    match literal.strip_prefix('-') {
+        Some(positive) => positive.to_string(),
+        None => format!("-{}", literal),
+    }
+}
+
 pub struct Parser<'a> {
     tokenizer: Tokenizer<'a>,
     current: TokenKind,
@@ -172,32 +198,9 @@ impl<'a> Parser<'a> {
 
         self.advance();
         consume!(self, "expected ':'", self.current, TokenKind::Colon(_, _));
-
-        let dtype;
-        if let TokenKind::IdenLiteral(t, _, _) = &self.current {
-            dtype = t.clone();
-        } else {
-            return Err(self.error("expected an identifier", &self.current));
-        }
-
-        self.advance();
-
-        if matches!(self, self.current, TokenKind::LeftBracket(_, _)) {
-            let size;
-            if let TokenKind::IntLiteral(t, _, _) = &self.current {
-                size = t.clone().parse::<u32>().unwrap();
-                self.advance();
-                self.ctx = ParserContext::ArrayParse(Some(size));
-            } else {
-                self.ctx = ParserContext::ArrayParse(None);
-            }
-
-            consume!(
-                self,
-                "expected ']'",
-                self.current,
-                TokenKind::RightBracket(_, _)
-            );
+        let dtype = self.type_name()?;
+        if dtype.ends_with(']') {
+            self.ctx = ParserContext::ArrayParse;
         }
 
         consume!(self, "expected '='", self.current, TokenKind::Equal(_, _));
@@ -243,6 +246,87 @@ impl<'a> Parser<'a> {
         ))
     }
 
+    /// A type, either a name, a pointer type like `ptr<ptr<i32>>`, an array
+    /// type like `i32[3]` or `[i32; 3]`, or a slice type like `[]i32`. The
+    /// name is kept as written for the typechecker to resolve, `[T; N]` is
+    /// written as `T[N]`.
+    fn type_name(&mut self) -> ParseResult<String> {
+        if matches!(self, self.current, TokenKind::LeftBracket(_, _)) {
+            if matches!(self, self.current, TokenKind::RightBracket(_, _)) {
+                return Ok(format!("[]{}", self.type_name()?));
+            }
+
+            let element = self.type_name()?;
+            if element.starts_with("[]") {
+                return Err(self.error("arrays of slices aren't supported", &self.current));
+            }
+            consume!(
+                self,
+                "expected a ';'",
+                self.current,
+                TokenKind::ExprDelimiter(_, _)
+            );
+            let length = match &self.current {
+                TokenKind::IntLiteral(literal, _, _) => literal.clone(),
+                _ => return Err(self.error("expected the length of the array", &self.current)),
+            };
+            self.advance();
+            consume!(
+                self,
+                "expected a ']'",
+                self.current,
+                TokenKind::RightBracket(_, _)
+            );
+
+            // The outer length comes first, `[[i32; 3]; 2]` is `i32[2][3]`
+            return Ok(match array_bracket(&element) {
+                Some(bracket) => {
+                    format!("{}[{}]{}", &element[..bracket], length, &element[bracket..])
+                }
+                None => format!("{}[{}]", element, length),
+            });
+        }
+
+        let mut name = match &self.current {
+            TokenKind::IdenLiteral(name, _, _) => name.clone(),
+            _ => return Err(self.error("expected a type", &self.current)),
+        };
+        self.advance();
+
+        if name == "ptr" && matches!(self, self.current, TokenKind::Less(_, _)) {
+            let pointee = self.type_name()?;
+            // `>>` closes two pointer types at once
+            if let TokenKind::ShiftRight(line, column) = self.current {
+                self.current = TokenKind::Greater(line, column);
+            } else {
+                consume!(
+                    self,
+                    "expected a '>'",
+                    self.current,
+                    TokenKind::Greater(_, _)
+                );
+            }
+            name = format!("ptr<{}>", pointee);
+        }
+
+        // Without a length, `i32[]` takes the one of the array literal
+        while matches!(self, self.current, TokenKind::LeftBracket(_, _)) {
+            let mut length = String::new();
+            if let TokenKind::IntLiteral(literal, _, _) = &self.current {
+                length = literal.clone();
+                self.advance();
+            }
+            consume!(
+                self,
+                "expected a ']'",
+                self.current,
+                TokenKind::RightBracket(_, _)
+            );
+            name = format!("{}[{}]", name, length);
+        }
+        Ok(name)
+    }
+
     fn func_decl(&mut self) -> ParseResult<Box<Node>> {
         let name;
         let name_loc;
@@ -272,15 +356,8 @@ impl<'a> Parser<'a> {
                 self.advance();
                 consume!(self, "expected a ':'", self.current, TokenKind::Colon(_, _));
 
-                let arg_type;
-                if let TokenKind::IdenLiteral(literal, _, _) = &self.current {
-                    arg_type = literal.clone();
-                } else {
-                    return Err(self.error("expected an identifier", &self.current));
-                }
-
+                let arg_type = self.type_name()?;
                 args.push(FunctionArg::new(arg_name, arg_name_loc, arg_type));
-                self.advance();
 
                 if !matches!(self, self.current, TokenKind::Comma(_, _)) {
                     break;
@@ -297,12 +374,7 @@ impl<'a> Parser<'a> {
         let mut ret_type = None;
         if let TokenKind::Arrow(_, _) = &self.current {
             self.advance();
-            if let TokenKind::IdenLiteral(iden, _, _) = &self.current {
-                ret_type = Some(iden.clone());
-            } else {
-                return Err(self.error("expected an identifier", &self.current));
-            }
-            self.advance();
+            ret_type = Some(self.type_name()?);
         }
 
         consume!(
@@ -335,13 +407,17 @@ impl<'a> Parser<'a> {
         let then_branch = self.block()?;
         let mut else_branch = None;
         if matches!(self, self.current, TokenKind::Else(_, _)) {
-            consume!(
-                self,
-                "expected a '{'",
-                self.current,
-                TokenKind::LeftBrace(_, _)
-            );
-            else_branch = Some(Block::new(self.block()?));
+            if matches!(self, self.current, TokenKind::If(_, _)) {
+                else_branch = Some(self.if_stmt()?);
+            } else {
+                consume!(
+                    self,
+                    "expected a '{' or 'if'",
+                    self.current,
+                    TokenKind::LeftBrace(_, _)
+                );
+                else_branch = Some(Block::new(self.block()?));
+            }
         }
 
         Ok(If::new(cond, Block::new(then_branch), else_branch))
@@ -437,6 +513,22 @@ impl<'a> Parser<'a> {
                 Node::VarGet(name, line, column) => {
                     return Ok(Assign::new(name.to_string(), (*line, *column), value));
                 }
+                Node::Deref(_) | Node::Index(_) => return Ok(Store::new(expr, value)),
+                _ => return Err("Invalid target for assignment".to_string()),
+            }
+        }
+
+        if let Some(op) = compound_op(&self.current) {
+            self.advance();
+            let value = self.assignment()?;
+
+            match expr.as_ref() {
+                Node::VarGet(name, line, column) => {
+                    let current = Box::new(Node::VarGet(name.to_string(), *line, *column));
+                    let value = Binary::new(current, value, op);
+                    return Ok(Assign::new(name.to_string(), (*line, *column), value));
+                }
+                Node::Deref(_) | Node::Index(_) => return Ok(Store::compound(expr, value, op)),
                 _ => return Err("Invalid target for assignment".to_string()),
             }
         }
@@ -498,7 +590,7 @@ impl<'a> Parser<'a> {
     }
 
     fn comparison(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.term()?;
+        let mut expr = self.bit_or()?;
         loop {
             let bop;
 
@@ -514,6 +606,54 @@ impl<'a> Parser<'a> {
                 break;
             }
 
+            let right = self.bit_or()?;
+            expr = Binary::new(expr, right, bop);
+        }
+        Ok(expr)
+    }
+
+    // The bitwise operators bind tighter than comparisons, `a & 1 == 0`
+    // tests the lowest bit
+    fn bit_or(&mut self) -> ParseResult<Box<Node>> {
+        let mut expr = self.bit_xor()?;
+        while matches!(self, self.current, TokenKind::Pipe(_, _)) {
+            let right = self.bit_xor()?;
+            expr = Binary::new(expr, right, BinaryOp::BitOr);
+        }
+        Ok(expr)
+    }
+
+    fn bit_xor(&mut self) -> ParseResult<Box<Node>> {
+        let mut expr = self.bit_and()?;
+        while matches!(self, self.current, TokenKind::Caret(_, _)) {
+            let right = self.bit_and()?;
+            expr = Binary::new(expr, right, BinaryOp::BitXor);
+        }
+        Ok(expr)
+    }
+
+    fn bit_and(&mut self) -> ParseResult<Box<Node>> {
+        let mut expr = self.shift()?;
+        while matches!(self, self.current, TokenKind::Ampersand(_, _)) {
+            let right = self.shift()?;
+            expr = Binary::new(expr, right, BinaryOp::BitAnd);
+        }
+        Ok(expr)
+    }
+
+    fn shift(&mut self) -> ParseResult<Box<Node>> {
+        let mut expr = self.term()?;
+        loop {
+            let bop;
+
+            if matches!(self, self.current, TokenKind::ShiftLeft(_, _)) {
+                bop = BinaryOp::Shl;
+            } else if matches!(self, self.current, TokenKind::ShiftRight(_, _)) {
+                bop = BinaryOp::Shr;
+            } else {
+                break;
+            }
+
             let right = self.term()?;
             expr = Binary::new(expr, right, bop);
         }
@@ -540,7 +680,7 @@ impl<'a> Parser<'a> {
     }
 
     fn factor(&mut self) -> ParseResult<Box<Node>> {
-        let mut expr = self.get_ptr()?;
+        let mut expr = self.cast()?;
         loop {
             let bop;
 
@@ -548,18 +688,34 @@ impl<'a> Parser<'a> {
                 bop = BinaryOp::Div;
             } else if matches!(self, self.current, TokenKind::Star(_, _)) {
                 bop = BinaryOp::Mul;
+            } else if matches!(self, self.current, TokenKind::Percent(_, _)) {
+                bop = BinaryOp::Rem;
             } else {
                 break;
             }
 
-            let right = self.get_ptr()?;
+            let right = self.cast()?;
             expr = Binary::new(expr, right, bop);
         }
         Ok(expr)
     }
 
+    fn cast(&mut self) -> ParseResult<Box<Node>> {
+        let mut expr = self.get_ptr()?;
+        while let TokenKind::As(line, column) = self.current {
+            self.advance();
+            if !std::matches!(self.current, TokenKind::IdenLiteral(_, _, _)) {
+                return Err(self.error("expected a type after 'as'", &self.current));
+            }
+            let dtype = self.type_name()?;
+
+            expr = Cast::new(expr, (line, column), dtype, Default::default());
+        }
+        Ok(expr)
+    }
+
     fn get_ptr(&mut self) -> ParseResult<Box<Node>> {
-        if matches!(self, self.current, TokenKind::GetPtr(_, _)) {
+        if matches!(self, self.current, TokenKind::Ampersand(_, _)) {
             let expr = self.get_ptr()?;
             Ok(GetPtr::new(expr))
         } else {
@@ -568,6 +724,12 @@ impl<'a> Parser<'a> {
     }
 
     fn unary(&mut self) -> ParseResult<Box<Node>> {
+        if let TokenKind::Star(line, column) = self.current {
+            self.advance();
+            let expr = self.get_ptr()?;
+            return Ok(Deref::new(expr, (line, column)));
+        }
+
         let mut uop = UnaryOp::None;
         let mut loc = (0, 0);
 
@@ -577,14 +739,106 @@ impl<'a> Parser<'a> {
         } else if matches!(self, self.current, TokenKind::Minus(_, _)) {
             uop = UnaryOp::Negate;
             loc = get_tok_loc(&self.current);
+        } else if matches!(self, self.current, TokenKind::Tilde(_, _)) {
+            uop = UnaryOp::BitNot;
+            loc = get_tok_loc(&self.current);
         }
 
         if uop != UnaryOp::None {
             let expr = self.unary()?;
+
+            // A negative literal is a literal of its own, so that its range can
+            // be checked as a whole
+            if uop == UnaryOp::Negate {
+                match *expr {
+                    Node::Number(literal, tipe, _, _) => {
+                        return Ok(Box::new(Node::Number(negate(literal), tipe, loc.0, loc.1)))
+                    }
+                    Node::Float(literal, tipe, _, _) => {
+                        return Ok(Box::new(Node::Float(negate(literal), tipe, loc.0, loc.1)))
+                    }
+                    expr => return Ok(Unary::new(uop, loc, Box::new(expr))),
+                }
+            }
             return Ok(Unary::new(uop, loc, expr));
         }
 
-        Ok(self.primary()?)
+        self.index()
+    }
+
+    /// `a[i]` or `a[start..end]`, binds tighter than the prefix operators
+    fn index(&mut self) -> ParseResult<Box<Node>> {
+        let mut expr = self.primary()?;
+        while let TokenKind::LeftBracket(line, column) = self.current {
+            self.advance();
+            let start = match self.current {
+                TokenKind::Dot(_, _) => None,
+                _ => Some(self.expr()?),
+            };
+
+            // `..` is two dots
+            if matches!(self, self.current, TokenKind::Dot(_, _)) {
+                consume!(self, "expected a '..'", self.current, TokenKind::Dot(_, _));
+                let end = match self.current {
+                    TokenKind::RightBracket(_, _) => None,
+                    _ => Some(self.expr()?),
+                };
+                consume!(
+                    self,
+                    "expected a ']'",
+                    self.current,
+                    TokenKind::RightBracket(_, _)
+                );
+                expr = Slice::new(expr, start, end, (line, column));
+                continue;
+            }
+
+            consume!(
+                self,
+                "expected a ']'",
+                self.current,
+                TokenKind::RightBracket(_, _)
+            );
+            expr = Index::new(expr, start.unwrap(), (line, column));
+        }
+        Ok(expr)
+    }
+
+    /// `len(a)`, or arithmetic with explicit overflow behaviour, like
+    /// `wrapping_add(a, b)`
+    fn builtin(&mut self, name: &str) -> ParseResult<Box<Node>> {
+        let loc = get_tok_loc(&self.current);
+        if name == "len" {
+            self.advance();
+            self.advance();
+            let expr = self.expr()?;
+            consume!(
+                self,
+                "expected a ')'",
+                self.current,
+                TokenKind::RightParen(_, _)
+            );
+            return Ok(Len::new(expr, loc));
+        }
+
+        let (overflow, op) = match Overflow::builtin(name) {
+            Some(builtin) => builtin,
+            None => return Err(self.error("unknown builtin", &self.current)),
+        };
+        self.advance();
+        self.advance();
+
+        let lhs = self.expr()?;
+        consume!(self, "expected a ','", self.current, TokenKind::Comma(_, _));
+        let rhs = self.expr()?;
+        consume!(
+            self,
+            "expected a ')'",
+            self.current,
+            TokenKind::RightParen(_, _)
+        );
+
+        Ok(Binary::with_overflow(lhs, rhs, op, overflow))
     }
 
     fn primary(&mut self) -> ParseResult<Box<Node>> {
@@ -605,6 +859,12 @@ impl<'a> Parser<'a> {
                 Node::StringLiteral(string.clone(), line, column)
             }
             TokenKind::IdenLiteral(ident, line, column) => {
+                if std::matches!(
+                    self.tokenizer.peek_ahead(),
+                    Some(TokenKind::LeftParen(_, _))
+                ) {
+                    return self.builtin(&ident);
+                }
                 Node::VarGet(ident.clone(), line, column)
             }
             TokenKind::LeftParen(_, _) => {
@@ -619,52 +879,7 @@ impl<'a> Parser<'a> {
                 return Ok(Grouping::new(expr));
             }
             TokenKind::LeftBrace(line, column) => match self.ctx {
-                ParserContext::ArrayParse(size) => {
-                    self.ctx = ParserContext::ArrayLiteral;
-                    let size = match size {
-                        Some(s) => s as usize,
-                        None => 0,
-                    };
-                    let mut items = Vec::with_capacity(size);
-
-                    self.advance();
-                    loop {
-                        let expr = self.expr()?;
-                        items.push(expr);
-
-                        if !matches!(self, self.current, TokenKind::Comma(_, _)) {
-                            break;
-                        }
-                    }
-
-                    consume!(
-                        self,
-                        "expected a '}'",
-                        self.current,
-                        TokenKind::RightBrace(_, _)
-                    );
-
-                    if size != 0 && items.len() != size {
-                        return Err(self.error(
-                            format!(
-                                "Declared array size is {} while the array literal size is {}",
-                                size,
-                                items.len()
-                            )
-                            .as_str(),
-                            &self.current,
-                        ));
-                    }
-
-                    if items.len() == 0 {
-                        return Err(self.error("Empty arrays are disallowed", &self.current));
-                    }
-
-                    Node::ArrayLiteral(items, Default::default(), line, column)
-                }
-                ParserContext::ArrayLiteral => {
-                    return Err(self.error("Nested arrays are not supported", &self.current));
-                }
+                ParserContext::ArrayParse => return self.array_literal((line, column)),
                 ParserContext::None => {
                     return Err(self.error(
                         "can only use '{' in expressions where variable is an array",
@@ -681,6 +896,51 @@ impl<'a> Parser<'a> {
         Ok(Box::new(node))
     }
 
+    /// `{1, 2, 3}`, items can be array literals themselves, or `{0; 16}`
+    /// repeating a value
+    fn array_literal(&mut self, loc: (usize, usize)) -> ParseResult<Box<Node>> {
+        self.advance();
+        let mut items = Vec::new();
+        if !std::matches!(self.current, TokenKind::RightBrace(_, _)) {
+            let value = self.expr()?;
+            if matches!(self, self.current, TokenKind::ExprDelimiter(_, _)) {
+                let count = match &self.current {
+                    TokenKind::IntLiteral(count, _, _) => count.parse::<usize>().ok(),
+                    _ => None,
+                };
+                let Some(count) = count else {
+                    return Err(self.error("expected the number of repetitions", &self.current));
+                };
+                self.advance();
+                consume!(
+                    self,
+                    "expected a '}'",
+                    self.current,
+                    TokenKind::RightBrace(_, _)
+                );
+                return Ok(ArrayRepeat::new(value, count, loc));
+            }
+
+            items.push(value);
+            while matches!(self, self.current, TokenKind::Comma(_, _)) {
+                items.push(self.expr()?);
+            }
+        }
+
+        consume!(
+            self,
+            "expected a '}'",
+            self.current,
+            TokenKind::RightBrace(_, _)
+        );
+        Ok(Box::new(Node::ArrayLiteral(
+            items,
+            Default::default(),
+            loc.0,
+            loc.1,
+        )))
+    }
+
     fn advance(&mut self) {
         self.current = self.tokenizer.next().unwrap_or_else(|| TokenKind::Eof);
     }
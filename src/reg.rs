@@ -1,7 +1,8 @@
 use crate::error;
+use crate::typechecker::{TaggedType, TypeKind};
 use log::debug;
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum RegisterSize {
     Byte,
     Word,
@@ -10,6 +11,20 @@ pub enum RegisterSize {
     Oword,
 }
 
+/// Register file a value lives in, floats never share registers with integers
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum RegisterClass {
+    General,
+    Float,
+}
+
+pub fn register_class(tipe: &TaggedType) -> RegisterClass {
+    match tipe.kind {
+        TypeKind::Float => RegisterClass::Float,
+        _ => RegisterClass::General,
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum RegisterLabel {
     // 128bit XMM registers
@@ -21,6 +36,14 @@ pub enum RegisterLabel {
     Xmm5,
     Xmm6,
     Xmm7,
+    Xmm8,
+    Xmm9,
+    Xmm10,
+    Xmm11,
+    Xmm12,
+    Xmm13,
+    Xmm14,
+    Xmm15,
     // 64bit
     Rax,
     Rbx,
@@ -98,163 +121,338 @@ impl std::fmt::Display for RegisterLabel {
     }
 }
 
+/// System V AMD64 integer argument registers, in argument order
+pub const INT_ARGUMENTS: [RegisterLabel; 6] = [
+    RegisterLabel::Rdi,
+    RegisterLabel::Rsi,
+    RegisterLabel::Rdx,
+    RegisterLabel::Rcx,
+    RegisterLabel::R8,
+    RegisterLabel::R9,
+];
+
+/// System V AMD64 floating point argument registers, in argument order
+pub const FLOAT_ARGUMENTS: [RegisterLabel; 8] = [
+    RegisterLabel::Xmm0,
+    RegisterLabel::Xmm1,
+    RegisterLabel::Xmm2,
+    RegisterLabel::Xmm3,
+    RegisterLabel::Xmm4,
+    RegisterLabel::Xmm5,
+    RegisterLabel::Xmm6,
+    RegisterLabel::Xmm7,
+];
+
+/// Left out of allocation so the code generator always has a register to
+/// stage operands in
+pub const SCRATCH: RegisterLabel = RegisterLabel::R11;
+pub const FLOAT_SCRATCH: RegisterLabel = RegisterLabel::Xmm15;
+
+/// A physical register and every name it can be accessed by. The names overlap,
+/// so the register is either free or used as a whole.
 pub struct Register {
-    label: RegisterLabel,
-    size: RegisterSize,
+    /// Size views of the register, from the widest to the narrowest
+    views: Vec<(RegisterLabel, RegisterSize)>,
+    class: RegisterClass,
+    /// Number of temporaries the allocator put in each view
+    assigned: Vec<usize>,
     used: bool,
+    /// Never handed out by the allocator, e.g. the stack and frame pointers
+    reserved: bool,
+    /// Preserved across calls by the System V ABI, a function using one has
+    /// to save and restore it
+    callee_saved: bool,
 }
 
 impl Register {
-    fn new(label: RegisterLabel, size: RegisterSize) -> Register {
+    fn new(views: Vec<(RegisterLabel, RegisterSize)>, class: RegisterClass) -> Register {
         Register {
-            label,
-            size,
+            assigned: vec![0; views.len()],
+            views,
+            class,
             used: false,
+            reserved: false,
+            callee_saved: false,
         }
     }
+
+    fn general(
+        qword: RegisterLabel,
+        dword: RegisterLabel,
+        word: RegisterLabel,
+        byte: RegisterLabel,
+    ) -> Register {
+        Register::new(
+            vec![
+                (qword, RegisterSize::Qword),
+                (dword, RegisterSize::Dword),
+                (word, RegisterSize::Word),
+                (byte, RegisterSize::Byte),
+            ],
+            RegisterClass::General,
+        )
+    }
+
+    /// Scalar doubles live in the low quadword and singles in the low
+    /// doubleword, both under the same name
+    fn xmm(label: RegisterLabel) -> Register {
+        Register::new(
+            vec![
+                (label.clone(), RegisterSize::Qword),
+                (label, RegisterSize::Dword),
+            ],
+            RegisterClass::Float,
+        )
+    }
+
+    fn reserved(mut self) -> Register {
+        self.reserved = true;
+        self
+    }
+
+    fn callee_saved(mut self) -> Register {
+        self.callee_saved = true;
+        self
+    }
+
+    fn view(&self, size: &RegisterSize) -> Option<&RegisterLabel> {
+        self.views
+            .iter()
+            .find(|(_, view_size)| view_size == size)
+            .map(|(label, _)| label)
+    }
+
+    fn has_label(&self, label: &RegisterLabel) -> bool {
+        self.views.iter().any(|(view, _)| view == label)
+    }
+
+    fn is_free(&self) -> bool {
+        !self.used && !self.reserved
+    }
 }
 
 pub struct RegisterManager {
     registers: Vec<Register>,
+    /// Temporaries that didn't get a register and live on the stack
+    spilled: usize,
 }
 
 impl RegisterManager {
     pub fn new() -> RegisterManager {
-        let mut registers: Vec<Register> = Vec::new();
-        // 128 BIT
-        registers.push(Register::new(RegisterLabel::Xmm0, RegisterSize::Oword));
-        registers.push(Register::new(RegisterLabel::Xmm1, RegisterSize::Oword));
-        registers.push(Register::new(RegisterLabel::Xmm2, RegisterSize::Oword));
-        registers.push(Register::new(RegisterLabel::Xmm3, RegisterSize::Oword));
-        registers.push(Register::new(RegisterLabel::Xmm4, RegisterSize::Oword));
-        registers.push(Register::new(RegisterLabel::Xmm5, RegisterSize::Oword));
-        registers.push(Register::new(RegisterLabel::Xmm6, RegisterSize::Oword));
-        registers.push(Register::new(RegisterLabel::Xmm7, RegisterSize::Oword));
-        // 64 BIT
-        registers.push(Register::new(RegisterLabel::Rax, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::Rbx, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::Rcx, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::Rdx, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::Rdi, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::Rsi, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::Rbp, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::Rsp, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::R8, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::R9, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::R10, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::R11, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::R12, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::R13, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::R14, RegisterSize::Qword));
-        registers.push(Register::new(RegisterLabel::R15, RegisterSize::Qword));
-        // 32 BIT
-        registers.push(Register::new(RegisterLabel::Eax, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::Ebx, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::Ecx, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::Edx, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::Esi, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::Edi, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::Ebp, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::Esp, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::R8d, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::R9d, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::R10d, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::R11d, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::R12d, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::R13d, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::R14d, RegisterSize::Dword));
-        registers.push(Register::new(RegisterLabel::R15d, RegisterSize::Dword));
-        // 16 BIT
-        registers.push(Register::new(RegisterLabel::Ax, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::Bx, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::Cx, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::Dx, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::Si, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::Di, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::Bp, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::Sp, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::R8w, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::R9w, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::R10w, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::R11w, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::R12w, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::R13w, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::R14w, RegisterSize::Word));
-        registers.push(Register::new(RegisterLabel::R15w, RegisterSize::Word));
-        // 8 BITS
-        registers.push(Register::new(RegisterLabel::Al, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::Bl, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::Cl, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::Dl, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::Sil, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::Dil, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::Bpl, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::Spl, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::R8b, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::R9b, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::R10b, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::R11b, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::R12b, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::R13b, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::R14b, RegisterSize::Byte));
-        registers.push(Register::new(RegisterLabel::R15b, RegisterSize::Byte));
-        RegisterManager { registers }
+        use RegisterLabel::*;
+
+        // In allocation order, caller-saved registers come first so that
+        // short-lived temporaries don't cost a save in the prologue
+        let registers = vec![
+            // 128 BIT, all caller-saved
+            Register::xmm(Xmm0),
+            Register::xmm(Xmm1),
+            Register::xmm(Xmm2),
+            Register::xmm(Xmm3),
+            Register::xmm(Xmm4),
+            Register::xmm(Xmm5),
+            Register::xmm(Xmm6),
+            Register::xmm(Xmm7),
+            Register::xmm(Xmm8),
+            Register::xmm(Xmm9),
+            Register::xmm(Xmm10),
+            Register::xmm(Xmm11),
+            Register::xmm(Xmm12),
+            Register::xmm(Xmm13),
+            Register::xmm(Xmm14),
+            Register::xmm(Xmm15).reserved(),
+            // 64 BIT and their lower 32, 16 and 8 bits, caller-saved
+            Register::general(Rax, Eax, Ax, Al),
+            Register::general(Rcx, Ecx, Cx, Cl),
+            Register::general(Rdx, Edx, Dx, Dl),
+            Register::general(Rsi, Esi, Si, Sil),
+            Register::general(Rdi, Edi, Di, Dil),
+            Register::general(R8, R8d, R8w, R8b),
+            Register::general(R9, R9d, R9w, R9b),
+            Register::general(R10, R10d, R10w, R10b),
+            Register::general(R11, R11d, R11w, R11b).reserved(),
+            // Callee-saved
+            Register::general(Rbx, Ebx, Bx, Bl).callee_saved(),
+            Register::general(R12, R12d, R12w, R12b).callee_saved(),
+            Register::general(R13, R13d, R13w, R13b).callee_saved(),
+            Register::general(R14, R14d, R14w, R14b).callee_saved(),
+            Register::general(R15, R15d, R15w, R15b).callee_saved(),
+            Register::general(Rbp, Ebp, Bp, Bpl)
+                .callee_saved()
+                .reserved(),
+            Register::general(Rsp, Esp, Sp, Spl)
+                .callee_saved()
+                .reserved(),
+        ];
+        RegisterManager {
+            registers,
+            spilled: 0,
+        }
+    }
+
+    fn find(&self, label: &RegisterLabel) -> &Register {
+        self.registers
+            .iter()
+            .find(|reg| reg.has_label(label))
+            .unwrap()
+    }
+
+    fn find_mut(&mut self, label: &RegisterLabel) -> &mut Register {
+        self.registers
+            .iter_mut()
+            .find(|reg| reg.has_label(label))
+            .unwrap()
     }
 
-    pub fn allocate(&mut self, size: RegisterSize) -> RegisterLabel {
+    pub fn try_allocate(
+        &mut self,
+        class: RegisterClass,
+        size: RegisterSize,
+    ) -> Option<RegisterLabel> {
         for reg in &mut self.registers {
-            if !reg.used && reg.size == size {
+            if !reg.is_free() || reg.class != class {
+                continue;
+            }
+
+            if let Some(label) = reg.view(&size).cloned() {
                 reg.used = true;
-                debug!("Allocated a register: {:#?}", reg.label);
-                return reg.label.clone();
+                debug!("Allocated a register: {:#?}", label);
+                return Some(label);
             }
         }
-
-        error::panic(format!(
-            "Couldn't find a free register of size: {:#?}",
-            size
-        ))
+        None
     }
 
-    #[allow(dead_code)]
-    pub fn deallocate(&mut self, label: RegisterLabel) {
-        for reg in &mut self.registers {
-            if reg.used && reg.label == label {
-                reg.used = false;
-                debug!("Deallocated a register: {:#?}", reg.label);
-                return;
-            }
+    /// Allocates `label` itself, if its register is free
+    pub fn allocate_label(&mut self, label: &RegisterLabel) -> bool {
+        let reg = self.find_mut(label);
+        if !reg.is_free() {
+            return false;
         }
-        error::panic(format!(
-            "Tried to deallocate a non-used register: {:#?}",
-            label
-        ))
+
+        reg.used = true;
+        debug!("Allocated a register: {:#?}", label);
+        true
     }
 
-    pub fn deallocate_all(&mut self) {
-        for reg in &mut self.registers {
-            if reg.used {
-                reg.used = false;
-            }
+    /// The view of the register behind `label` with the given size
+    pub fn view(&self, label: &RegisterLabel, size: &RegisterSize) -> Option<RegisterLabel> {
+        self.find(label).view(size).cloned()
+    }
+
+    pub fn is_callee_saved(&self, label: &RegisterLabel) -> bool {
+        self.find(label).callee_saved
+    }
+
+    pub fn class(&self, label: &RegisterLabel) -> RegisterClass {
+        self.find(label).class
+    }
+
+    /// Views of the given size of every register of the class the allocator
+    /// can hand out, in allocation order
+    pub fn allocatable(&self, class: RegisterClass, size: &RegisterSize) -> Vec<RegisterLabel> {
+        self.registers
+            .iter()
+            .filter(|reg| !reg.reserved && reg.class == class)
+            .filter_map(|reg| reg.view(size).cloned())
+            .collect()
+    }
+
+    pub fn record_assignment(&mut self, label: &RegisterLabel) {
+        let reg = self.find_mut(label);
+        let view = reg
+            .views
+            .iter()
+            .position(|(view, _)| view == label)
+            .unwrap();
+        reg.assigned[view] += 1;
+    }
+
+    pub fn record_spills(&mut self, count: usize) {
+        self.spilled += count;
+    }
+
+    /// Full names of the callee-saved registers the allocator can hand out
+    pub fn callee_saved_registers(&self) -> Vec<RegisterLabel> {
+        self.registers
+            .iter()
+            .filter(|reg| reg.callee_saved && !reg.reserved)
+            .map(|reg| reg.views[0].0.clone())
+            .collect()
+    }
+
+    /// Registers the parameters of a function are passed in, `None` for the
+    /// ones that go on the stack. Integer and float parameters take their
+    /// registers independently of each other.
+    pub fn argument_registers(&self, params: &[TaggedType]) -> Vec<Option<RegisterLabel>> {
+        let mut ints = INT_ARGUMENTS.iter();
+        let mut floats = FLOAT_ARGUMENTS.iter();
+        params
+            .iter()
+            .map(|param| {
+                let label = match register_class(param) {
+                    RegisterClass::General => ints.next()?,
+                    RegisterClass::Float => floats.next()?,
+                };
+                self.view(label, &size_to_reg_size(param.size))
+            })
+            .collect()
+    }
+
+    pub fn return_register(
+        &self,
+        class: RegisterClass,
+        size: &RegisterSize,
+    ) -> Option<RegisterLabel> {
+        let label = match class {
+            RegisterClass::General => &RegisterLabel::Rax,
+            RegisterClass::Float => &RegisterLabel::Xmm0,
+        };
+        self.view(label, size)
+    }
+
+    pub fn deallocate(&mut self, label: RegisterLabel) {
+        let reg = self.find_mut(&label);
+        if !reg.used {
+            error::panic(format!(
+                "Tried to deallocate a non-used register: {:#?}",
+                label
+            ))
         }
+
+        reg.used = false;
+        debug!("Deallocated a register: {:#?}", label);
     }
 
-    #[allow(dead_code)]
     pub fn table(&self, size: Option<RegisterSize>) {
-        println!("| {: <10} | {: <10} | {: <6}|", "Label:", "Size:", "Used:");
+        println!(
+            "| {: <10} | {: <10} | {: <9}| {: <7}| {: <6}|",
+            "Label:", "Size:", "Used:", "Saved:", "Temps:"
+        );
 
         for reg in &self.registers {
-            if let Some(s) = &size {
-                if reg.size != *s {
-                    continue;
+            for ((label, reg_size), assigned) in reg.views.iter().zip(&reg.assigned) {
+                if let Some(s) = &size {
+                    if reg_size != s {
+                        continue;
+                    }
                 }
-            }
 
-            let label = format!("{:#?}", reg.label);
-            let size = format!("{:#?}", reg.size);
-            let used = format!("{:#?}", reg.used);
-            println!("| {: <10} | {: <10} | {: <6}|", label, size, used);
+                let label = format!("{:#?}", label);
+                let size = format!("{:#?}", reg_size);
+                let used = if reg.reserved {
+                    "reserved".to_string()
+                } else {
+                    format!("{:#?}", reg.used)
+                };
+                let saved = if reg.callee_saved { "callee" } else { "caller" };
+                println!(
+                    "| {: <10} | {: <10} | {: <9}| {: <7}| {: <6}|",
+                    label, size, used, saved, assigned
+                );
+            }
         }
+        println!("Spilled temporaries: {}", self.spilled);
     }
 }
 
@@ -279,3 +477,52 @@ pub fn reg_size_to_str(size: RegisterSize) -> &'static str {
         RegisterSize::Oword => "oword",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use RegisterLabel::*;
+
+    #[test]
+    fn views_of_a_register_are_allocated_together() {
+        let mut rmgr = RegisterManager::new();
+        assert!(rmgr.allocate_label(&Eax));
+        assert!(!rmgr.allocate_label(&Rax));
+        assert!(!rmgr.allocate_label(&Al));
+        assert!(rmgr.allocate_label(&Bl));
+
+        assert_eq!(rmgr.view(&Al, &RegisterSize::Qword), Some(Rax));
+        assert_eq!(rmgr.view(&R12, &RegisterSize::Word), Some(R12w));
+        assert_eq!(rmgr.view(&Sil, &RegisterSize::Dword), Some(Esi));
+        assert_eq!(rmgr.view(&Xmm3, &RegisterSize::Word), None);
+
+        rmgr.deallocate(Eax);
+        assert!(rmgr.allocate_label(&Ax));
+    }
+
+    #[test]
+    fn stack_pointers_and_scratch_registers_are_never_handed_out() {
+        let reserved = [Rsp, Rbp, SCRATCH, FLOAT_SCRATCH];
+
+        let mut rmgr = RegisterManager::new();
+        let mut handed_out = Vec::new();
+        for class in [RegisterClass::General, RegisterClass::Float] {
+            while let Some(label) = rmgr.try_allocate(class, RegisterSize::Qword) {
+                handed_out.push(label);
+            }
+        }
+        assert_eq!(handed_out.len(), 13 + 15);
+
+        for label in &reserved {
+            assert!(!handed_out.contains(label), "{}", label);
+            assert!(!rmgr.allocate_label(label), "{}", label);
+            for class in [RegisterClass::General, RegisterClass::Float] {
+                assert!(!rmgr
+                    .allocatable(class, &RegisterSize::Qword)
+                    .contains(label));
+            }
+        }
+        assert!(!rmgr.callee_saved_registers().contains(&Rbp));
+        assert!(!rmgr.callee_saved_registers().contains(&Rsp));
+    }
+}
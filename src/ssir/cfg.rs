@@ -0,0 +1,420 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::typechecker::TaggedType;
+
+use super::{
+    format_instruction,
+    ins::{Function, Instruction, Label},
+    tmp::{TmpChild, TmpNode},
+};
+
+/// The instruction that ends a basic block and decides where control goes next
+#[derive(Debug, Clone)]
+pub enum Terminator {
+    Jump(usize),
+    Branch(TmpChild, usize, usize),
+    Ret(Option<TmpChild>),
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<usize> {
+        match self {
+            Self::Jump(target) => vec![*target],
+            Self::Branch(_, then_block, else_block) if then_block == else_block => {
+                vec![*then_block]
+            }
+            Self::Branch(_, then_block, else_block) => vec![*then_block, *else_block],
+            Self::Ret(_) => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut TmpChild> {
+        match self {
+            Self::Branch(cond, _, _) => vec![cond],
+            Self::Ret(value) => value.iter_mut().collect(),
+            Self::Jump(_) => vec![],
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&TmpChild> {
+        match self {
+            Self::Branch(cond, _, _) => vec![cond],
+            Self::Ret(value) => value.iter().collect(),
+            Self::Jump(_) => vec![],
+        }
+    }
+
+    /// Redirects every edge going to `old` towards `new`
+    pub fn replace_target(&mut self, old: usize, new: usize) {
+        match self {
+            Self::Jump(target) => {
+                if *target == old {
+                    *target = new;
+                }
+            }
+            Self::Branch(_, then_block, else_block) => {
+                if *then_block == old {
+                    *then_block = new;
+                }
+                if *else_block == old {
+                    *else_block = new;
+                }
+            }
+            Self::Ret(_) => (),
+        }
+    }
+
+    fn into_instruction(self) -> Instruction {
+        match self {
+            Self::Jump(target) => Instruction::Jump(target),
+            Self::Branch(cond, then_block, else_block) => {
+                Instruction::Branch(cond, then_block, else_block)
+            }
+            Self::Ret(value) => Instruction::Ret(value),
+        }
+    }
+}
+
+impl std::fmt::Display for Terminator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Jump(target) => write!(f, "jump BB{}", target),
+            Self::Branch(cond, then_block, else_block) => {
+                write!(f, "branch {} BB{} BB{}", cond, then_block, else_block)
+            }
+            Self::Ret(Some(value)) => write!(f, "ret {}", value),
+            Self::Ret(None) => write!(f, "ret"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub id: usize,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+    pub preds: Vec<usize>,
+    pub succs: Vec<usize>,
+}
+
+impl BasicBlock {
+    pub fn new(id: usize, instructions: Vec<Instruction>, terminator: Terminator) -> BasicBlock {
+        BasicBlock {
+            id,
+            instructions,
+            terminator,
+            preds: Vec::new(),
+            succs: Vec::new(),
+        }
+    }
+}
+
+/// Control-flow graph of a single function, `blocks[0]` is the entry block
+/// and the id of a block is always its index in `blocks`
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub name: String,
+    pub params: Vec<TaggedType>,
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    /// Splits the function into basic blocks. A label without a terminator falls
+    /// through into the next one, and instructions following a terminator start
+    /// a new block without predecessors.
+    pub fn build(func: Function) -> Cfg {
+        let mut raw_blocks: Vec<(Vec<Instruction>, Option<Instruction>)> = Vec::new();
+        let mut label_map: HashMap<usize, usize> = HashMap::new();
+
+        let sections = std::iter::once((None, func.instructions)).chain(
+            func.labels
+                .into_iter()
+                .map(|label| (Some(label.id), label.instructions)),
+        );
+
+        for (label_id, instructions) in sections {
+            if let Some(id) = label_id {
+                label_map.insert(id, raw_blocks.len());
+            }
+
+            let mut current = Vec::new();
+            let mut open = true;
+            for ins in instructions {
+                match ins {
+                    Instruction::Jump(_) | Instruction::Branch(_, _, _) | Instruction::Ret(_) => {
+                        raw_blocks.push((std::mem::take(&mut current), Some(ins)));
+                        open = false;
+                    }
+                    _ => {
+                        current.push(ins);
+                        open = true;
+                    }
+                }
+            }
+
+            if open {
+                raw_blocks.push((current, None));
+            }
+        }
+
+        let block_count = raw_blocks.len();
+        let blocks = raw_blocks
+            .into_iter()
+            .enumerate()
+            .map(|(id, (instructions, last))| {
+                let terminator = match last {
+                    Some(Instruction::Jump(target)) => Terminator::Jump(label_map[&target]),
+                    Some(Instruction::Branch(cond, then_lc, else_lc)) => {
+                        Terminator::Branch(cond, label_map[&then_lc], label_map[&else_lc])
+                    }
+                    Some(Instruction::Ret(value)) => Terminator::Ret(value),
+                    _ if id + 1 < block_count => Terminator::Jump(id + 1),
+                    _ => Terminator::Ret(None),
+                };
+                BasicBlock::new(id, instructions, terminator)
+            })
+            .collect();
+
+        let mut cfg = Cfg {
+            name: func.name,
+            params: func.params,
+            blocks,
+        };
+        cfg.compute_edges();
+        cfg
+    }
+
+    /// Variables whose address is taken. Pointers can read and write them at
+    /// any point, so they stay in memory under their own name.
+    pub fn address_taken(&self) -> HashSet<String> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.instructions.iter())
+            .filter_map(|ins| match ins {
+                Instruction::TmpNode(TmpNode::AddressTmp(address), _, _) => {
+                    Some(address.var.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Recomputes predecessors and successors from the terminators
+    pub fn compute_edges(&mut self) {
+        for block in &mut self.blocks {
+            block.preds.clear();
+            block.succs = block.terminator.successors();
+        }
+
+        for id in 0..self.blocks.len() {
+            for succ in self.blocks[id].succs.clone() {
+                self.blocks[succ].preds.push(id);
+            }
+        }
+    }
+
+    /// Drops every block for which `keep` is false and renumbers the rest.
+    /// Phi operands coming from dropped blocks are removed as well.
+    pub fn retain_blocks(&mut self, keep: &[bool]) {
+        let mut new_ids = vec![usize::MAX; self.blocks.len()];
+        let mut next_id = 0;
+        for (id, kept) in keep.iter().enumerate() {
+            if *kept {
+                new_ids[id] = next_id;
+                next_id += 1;
+            }
+        }
+
+        let blocks = std::mem::take(&mut self.blocks);
+        for mut block in blocks.into_iter().filter(|block| keep[block.id]) {
+            block.id = new_ids[block.id];
+            match &mut block.terminator {
+                Terminator::Jump(target) => *target = new_ids[*target],
+                Terminator::Branch(_, then_block, else_block) => {
+                    *then_block = new_ids[*then_block];
+                    *else_block = new_ids[*else_block];
+                }
+                Terminator::Ret(_) => (),
+            }
+
+            for ins in &mut block.instructions {
+                if let Instruction::Phi(_, incoming, _) = ins {
+                    incoming.retain(|(pred, _)| keep[*pred]);
+                    for (pred, _) in incoming.iter_mut() {
+                        *pred = new_ids[*pred];
+                    }
+                }
+            }
+
+            self.blocks.push(block);
+        }
+
+        self.compute_edges();
+    }
+
+    /// Flattens the graph back into a function, every block becomes the label `LC{id}`
+    pub fn into_function(self) -> Function {
+        let mut func = Function::new(self.name, self.params);
+        for block in self.blocks {
+            let mut instructions = block.instructions;
+            instructions.push(block.terminator.into_instruction());
+
+            if block.id == 0 {
+                func.instructions = instructions;
+            } else {
+                let mut label = Label::new(block.id);
+                label.instructions = instructions;
+                func.add_label(label);
+            }
+        }
+        func
+    }
+
+    fn write_dot(&self, out: &mut String) {
+        out.push_str(&format!("    subgraph \"cluster_{}\" {{\n", self.name));
+        out.push_str(&format!("        label=\"func {}\";\n", self.name));
+
+        for block in &self.blocks {
+            let mut text = format!("BB{}:\\l", block.id);
+            for ins in &block.instructions {
+                text.push_str(&escape_dot(&format_instruction(ins)));
+                text.push_str("\\l");
+            }
+            text.push_str(&escape_dot(&block.terminator.to_string()));
+            text.push_str("\\l");
+
+            out.push_str(&format!(
+                "        \"{}_BB{}\" [label=\"{}\"];\n",
+                self.name, block.id, text
+            ));
+        }
+
+        for block in &self.blocks {
+            let edges = match &block.terminator {
+                Terminator::Branch(_, then_block, else_block) => {
+                    vec![
+                        (*then_block, " [label=\"T\"]"),
+                        (*else_block, " [label=\"F\"]"),
+                    ]
+                }
+                terminator => terminator
+                    .successors()
+                    .into_iter()
+                    .map(|succ| (succ, ""))
+                    .collect(),
+            };
+
+            for (succ, attrs) in edges {
+                out.push_str(&format!(
+                    "        \"{}_BB{}\" -> \"{}_BB{}\"{};\n",
+                    self.name, block.id, self.name, succ, attrs
+                ));
+            }
+        }
+
+        out.push_str("    }\n");
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Renders the control-flow graphs as a Graphviz digraph, one cluster per function
+pub fn to_dot(cfgs: &Vec<Cfg>) -> String {
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+    for cfg in cfgs {
+        cfg.write_dot(&mut out);
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typechecker::TypeKind;
+
+    /// `entry: branch 10 20; pop`, `10: pop` falling through, `20: ret`
+    fn function() -> Function {
+        let bool_type = TaggedType::new(1, TypeKind::Bool, None);
+        let mut func = Function::new("f".to_string(), Vec::new());
+        func.add_ins(Instruction::Loc(1, 1));
+        func.add_ins(Instruction::Branch(
+            TmpChild::Literal("true".to_string(), bool_type),
+            10,
+            20,
+        ));
+        func.add_ins(Instruction::Pop);
+
+        let mut then_label = Label::new(10);
+        then_label.add_ins(Instruction::Pop);
+        func.add_label(then_label);
+
+        let mut join_label = Label::new(20);
+        join_label.add_ins(Instruction::Ret(None));
+        func.add_label(join_label);
+        func
+    }
+
+    #[test]
+    fn blocks_end_at_terminators_and_labels() {
+        let cfg = Cfg::build(function());
+        assert_eq!(cfg.blocks.len(), 4, "{:#?}", cfg.blocks);
+
+        // Code after the branch is a block of its own that nothing reaches
+        assert_eq!(cfg.blocks[0].instructions.len(), 1);
+        assert!(std::matches!(
+            cfg.blocks[0].terminator,
+            Terminator::Branch(_, 2, 3)
+        ));
+        assert!(std::matches!(
+            cfg.blocks[1].instructions[..],
+            [Instruction::Pop]
+        ));
+
+        // A label without a terminator falls through into the next one
+        assert!(std::matches!(cfg.blocks[2].terminator, Terminator::Jump(3)));
+        assert!(std::matches!(
+            cfg.blocks[3].terminator,
+            Terminator::Ret(None)
+        ));
+    }
+
+    #[test]
+    fn edges_follow_the_terminators() {
+        let cfg = Cfg::build(function());
+        let succs = cfg
+            .blocks
+            .iter()
+            .map(|block| &block.succs)
+            .collect::<Vec<_>>();
+        let preds = cfg
+            .blocks
+            .iter()
+            .map(|block| &block.preds)
+            .collect::<Vec<_>>();
+        assert_eq!(succs, [&vec![2, 3], &vec![2], &vec![3], &vec![]]);
+        assert_eq!(preds, [&vec![], &vec![], &vec![0, 1], &vec![0, 2]]);
+    }
+
+    #[test]
+    fn dot_output_has_a_cluster_per_function_and_labeled_branches() {
+        let dot = to_dot(&vec![Cfg::build(function())]);
+        assert!(dot.starts_with("digraph cfg {\n"), "{}", dot);
+        assert!(dot.contains("subgraph \"cluster_f\" {"), "{}", dot);
+        assert!(dot.contains("\"f_BB0\" [label=\"BB0:\\l"), "{}", dot);
+        assert!(
+            dot.contains("\"f_BB0\" -> \"f_BB2\" [label=\"T\"];"),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains("\"f_BB0\" -> \"f_BB3\" [label=\"F\"];"),
+            "{}",
+            dot
+        );
+        assert!(dot.contains("\"f_BB2\" -> \"f_BB3\";"), "{}", dot);
+        assert!(dot.ends_with("    }\n}\n"), "{}", dot);
+    }
+}
@@ -0,0 +1,217 @@
+//! Conversions between the integer widths and between integers and floats.
+//! Float to integer conversions truncate towards zero, like the constant
+//! folding in `opt::fold::cast_literal`.
+
+use crate::{
+    reg::{self, RegisterLabel, RegisterSize},
+    ssir::opt::fold,
+    typechecker::{TaggedType, TypeKind},
+};
+
+use super::{ptr, tipe_size, Emitter, TmpChild};
+
+fn cvt_suffix(tipe: &TaggedType) -> &'static str {
+    if tipe.size == 4 {
+        "ss"
+    } else {
+        "sd"
+    }
+}
+
+impl Emitter {
+    /// The value read at another width: the low part of its register, or the
+    /// first bytes of its memory
+    pub(super) fn resized(&mut self, value: &TmpChild, size: &RegisterSize) -> String {
+        match value {
+            TmpChild::TmpRef(_, _, Some(label)) => self.view(label, size).to_string(),
+            TmpChild::LoadVar(_, _, Some(slot)) => format!("{} {}", ptr(size), slot),
+            TmpChild::Param(index, _) => match self.params[*index].clone() {
+                Ok(label) => self.view(&label, size).to_string(),
+                Err(offset) => format!("{} [rbp+{}]", ptr(size), offset),
+            },
+            _ => self.operand(value),
+        }
+    }
+
+    pub(super) fn local_label(&mut self) -> String {
+        self.local_labels += 1;
+        format!(".L{}_LOCAL{}", self.name, self.local_labels - 1)
+    }
+
+    pub(super) fn cast(&mut self, value: &TmpChild, to: &TaggedType, dest: &RegisterLabel) {
+        let from = crate::ssir::get_child_type(value);
+        if let TmpChild::Literal(literal, _) = value {
+            let literal = fold::cast_literal(literal, &from, to).unwrap();
+            return self.move_into(dest, &TmpChild::Literal(literal, to.clone()), to);
+        }
+
+        match (from.kind == TypeKind::Float, to.kind == TypeKind::Float) {
+            (true, true) if from.size == to.size => self.move_into(dest, value, to),
+            (true, true) => {
+                let operand = self.operand(value);
+                self.line(format!(
+                    "cvt{}2{} {}, {}",
+                    cvt_suffix(&from),
+                    cvt_suffix(to),
+                    dest,
+                    operand
+                ));
+            }
+            (true, false) => self.float_to_int(value, &from, to, dest),
+            (false, true) => self.int_to_float(value, &from, to, dest),
+            (false, false) => self.int_to_int(value, &from, to, dest),
+        }
+    }
+
+    /// Narrowing keeps the low bits, widening sign extends signed values and
+    /// zero extends the others
+    pub(super) fn int_to_int(
+        &mut self,
+        value: &TmpChild,
+        from: &TaggedType,
+        to: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        if to.size <= from.size {
+            let operand = self.resized(value, &tipe_size(to));
+            if operand != dest.to_string() {
+                self.line(format!("mov {}, {}", dest, operand));
+            }
+            return;
+        }
+
+        let operand = self.operand(value);
+        match (from.is_signed(), from.size) {
+            (true, 4) => self.line(format!("movsxd {}, {}", dest, operand)),
+            (true, _) => self.line(format!("movsx {}, {}", dest, operand)),
+            // Writing a doubleword clears the upper half of the register
+            (false, 4) => {
+                let dest = self.view(dest, &RegisterSize::Dword);
+                self.line(format!("mov {}, {}", dest, operand));
+            }
+            (false, _) => self.line(format!("movzx {}, {}", dest, operand)),
+        }
+    }
+
+    /// `cvtsi2s*` only reads signed doublewords and quadwords, everything else
+    /// is widened in the scratch register first
+    fn int_to_float(
+        &mut self,
+        value: &TmpChild,
+        from: &TaggedType,
+        to: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let cvt = format!("cvtsi2{}", cvt_suffix(to));
+        let operand = self.operand(value);
+        let source = match (from.is_signed(), from.size) {
+            (true, 4) | (true, 8) => operand,
+            (true, _) => {
+                let scratch = self.scratch(&RegisterSize::Dword);
+                self.line(format!("movsx {}, {}", scratch, operand));
+                scratch.to_string()
+            }
+            (false, 4) => {
+                // Zero extended to a quadword, which is always positive
+                let scratch = self.scratch(&RegisterSize::Dword);
+                self.line(format!("mov {}, {}", scratch, operand));
+                reg::SCRATCH.to_string()
+            }
+            (false, 8) => return self.u64_to_float(&operand, &cvt, dest),
+            (false, _) => {
+                let scratch = self.scratch(&RegisterSize::Dword);
+                self.line(format!("movzx {}, {}", scratch, operand));
+                scratch.to_string()
+            }
+        };
+        self.line(format!("{} {}, {}", cvt, dest, source));
+    }
+
+    /// Values with the top bit set are halved, keeping the lowest bit so the
+    /// rounding stays correct, converted as signed and doubled again
+    fn u64_to_float(&mut self, operand: &str, cvt: &str, dest: &RegisterLabel) {
+        let (large, done, rounded) = (self.local_label(), self.local_label(), self.local_label());
+        let scratch = reg::SCRATCH;
+        let add = if cvt.ends_with("ss") {
+            "addss"
+        } else {
+            "addsd"
+        };
+
+        self.line(format!("mov {}, {}", scratch, operand));
+        self.line(format!("test {}, {}", scratch, scratch));
+        self.line(format!("js {}", large));
+        self.line(format!("{} {}, {}", cvt, dest, scratch));
+        self.line(format!("jmp {}", done));
+        self.out.push_str(&format!("{}:\n", large));
+        self.line(format!("shr {}, 1", scratch));
+        self.line(format!("jnc {}", rounded));
+        self.line(format!("or {}, 1", scratch));
+        self.out.push_str(&format!("{}:\n", rounded));
+        self.line(format!("{} {}, {}", cvt, dest, scratch));
+        self.line(format!("{} {}, {}", add, dest, dest));
+        self.out.push_str(&format!("{}:\n", done));
+    }
+
+    /// `cvtts*2si` only writes doublewords and quadwords. `u32` goes through a
+    /// quadword so that values above `i32::MAX` survive, `u64` needs
+    /// `float_to_u64`.
+    fn float_to_int(
+        &mut self,
+        value: &TmpChild,
+        from: &TaggedType,
+        to: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let size = tipe_size(to);
+        let wide = if to.size == 8 || (to.size == 4 && !to.is_signed()) {
+            RegisterSize::Qword
+        } else {
+            RegisterSize::Dword
+        };
+        let cvt = format!("cvtt{}2si", cvt_suffix(from));
+        if to.size == 8 && !to.is_signed() {
+            return self.float_to_u64(value, from, &cvt, dest);
+        }
+        let operand = self.operand(value);
+
+        if size == wide {
+            self.line(format!("{} {}, {}", cvt, dest, operand));
+        } else {
+            let scratch = self.scratch(&wide);
+            self.line(format!("{} {}, {}", cvt, scratch, operand));
+            let low = self.scratch(&size);
+            self.line(format!("mov {}, {}", dest, low));
+        }
+    }
+
+    /// Values from 2^63 on are out of range for the signed conversion, they
+    /// are lowered by 2^63 first and get the top bit back afterwards
+    fn float_to_u64(
+        &mut self,
+        value: &TmpChild,
+        from: &TaggedType,
+        cvt: &str,
+        dest: &RegisterLabel,
+    ) {
+        let (large, done) = (self.local_label(), self.local_label());
+        let scratch = reg::FLOAT_SCRATCH;
+        let suffix = cvt_suffix(from);
+        let limit = self.operand(&TmpChild::Literal(
+            "9223372036854775808.0".to_string(),
+            from.clone(),
+        ));
+
+        self.float_move(&scratch, value, from);
+        self.line(format!("comi{} {}, {}", suffix, scratch, limit));
+        self.line(format!("jae {}", large));
+        self.line(format!("{} {}, {}", cvt, dest, scratch));
+        self.line(format!("jmp {}", done));
+        self.out.push_str(&format!("{}:\n", large));
+        self.line(format!("sub{} {}, {}", suffix, scratch, limit));
+        self.line(format!("{} {}, {}", cvt, dest, scratch));
+        self.line(format!("mov {}, 0x8000000000000000", reg::SCRATCH));
+        self.line(format!("xor {}, {}", dest, reg::SCRATCH));
+        self.out.push_str(&format!("{}:\n", done));
+    }
+}
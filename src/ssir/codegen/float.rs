@@ -0,0 +1,159 @@
+//! SSE2 scalar code for `f32` and `f64`, single precision instructions end
+//! in `ss` and double precision ones in `sd`
+
+use crate::{
+    ast::{BinaryOp, UnaryOp},
+    reg::{self, RegisterLabel},
+    typechecker::TaggedType,
+};
+
+use super::{Emitter, TmpChild};
+
+fn suffix(tipe: &TaggedType) -> &'static str {
+    if tipe.size == 4 {
+        "ss"
+    } else {
+        "sd"
+    }
+}
+
+pub fn mov(tipe: &TaggedType) -> String {
+    format!("mov{}", suffix(tipe))
+}
+
+impl Emitter {
+    /// Literals are loaded from `.rodata`, there are no float immediates
+    pub(super) fn float_constant(&mut self, value: &str, tipe: &TaggedType) -> String {
+        let data = if tipe.size == 4 {
+            format!(".float {}", value)
+        } else {
+            format!(".double {}", value)
+        };
+        self.constant(data)
+    }
+
+    pub(super) fn float_move(&mut self, dest: &RegisterLabel, value: &TmpChild, tipe: &TaggedType) {
+        let operand = self.operand(value);
+        self.line(format!("{} {}, {}", mov(tipe), dest, operand));
+    }
+
+    /// The value as an XMM register, loading it into the scratch register if
+    /// it lives in memory
+    fn float_register(&mut self, value: &TmpChild, tipe: &TaggedType) -> RegisterLabel {
+        match Emitter::register(value) {
+            Some(label) => label.clone(),
+            None => match value {
+                TmpChild::Param(index, _) if self.params[*index].is_ok() => {
+                    self.params[*index].clone().unwrap()
+                }
+                _ => {
+                    self.float_move(&reg::FLOAT_SCRATCH, value, tipe);
+                    reg::FLOAT_SCRATCH
+                }
+            },
+        }
+    }
+
+    pub(super) fn float_unary(
+        &mut self,
+        op: &UnaryOp,
+        value: &TmpChild,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        match op {
+            UnaryOp::Negate => {
+                // Flip the sign bit, `xorps` wants the whole 16 byte mask
+                let mask = if tipe.size == 4 {
+                    ".long 0x80000000, 0, 0, 0"
+                } else {
+                    ".quad 0x8000000000000000, 0"
+                };
+                let mask = self.constant(mask.to_string());
+                self.move_into(dest, value, tipe);
+                self.line(format!("xorps {}, XMMWORD PTR [rip+{}]", dest, mask));
+            }
+            UnaryOp::Not | UnaryOp::BitNot | UnaryOp::None => unreachable!(),
+        }
+    }
+
+    pub(super) fn float_binary(
+        &mut self,
+        op: &BinaryOp,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let mnemonic = match op {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            _ => return self.float_comparison(op, lhs, rhs, tipe, dest),
+        };
+
+        let (mut lhs, mut rhs) = (lhs, rhs);
+        let rhs_in_dest = Emitter::register(rhs) == Some(dest);
+        if rhs_in_dest && Emitter::register(lhs) != Some(dest) {
+            if let BinaryOp::Add | BinaryOp::Mul = op {
+                std::mem::swap(&mut lhs, &mut rhs);
+            } else {
+                // Moving `lhs` in would overwrite `rhs`, keep it aside
+                self.float_move(&reg::FLOAT_SCRATCH, rhs, tipe);
+                self.move_into(dest, lhs, tipe);
+                self.line(format!(
+                    "{}{} {}, {}",
+                    mnemonic,
+                    suffix(tipe),
+                    dest,
+                    reg::FLOAT_SCRATCH
+                ));
+                return;
+            }
+        }
+
+        self.move_into(dest, lhs, tipe);
+        let rhs = self.operand(rhs);
+        self.line(format!("{}{} {}, {}", mnemonic, suffix(tipe), dest, rhs));
+    }
+
+    /// `ucomis*` sets the flags like an unsigned comparison, and all of ZF,
+    /// PF and CF when either side is NaN. Only the above conditions and the
+    /// parity-checked equality are false for NaN, so `<` and `<=` are
+    /// computed as `>` and `>=` with the operands swapped.
+    fn float_comparison(
+        &mut self,
+        op: &BinaryOp,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let (lhs, rhs) = match op {
+            BinaryOp::Less | BinaryOp::LessEq => (rhs, lhs),
+            _ => (lhs, rhs),
+        };
+
+        let lhs = self.float_register(lhs, tipe);
+        let rhs = self.operand(rhs);
+        self.line(format!("ucomi{} {}, {}", suffix(tipe), lhs, rhs));
+
+        let scratch = self.scratch(&reg::RegisterSize::Byte);
+        match op {
+            BinaryOp::Greater | BinaryOp::Less => self.line(format!("seta {}", dest)),
+            BinaryOp::GreaterEq | BinaryOp::LessEq => self.line(format!("setae {}", dest)),
+            BinaryOp::Equal => {
+                self.line(format!("sete {}", dest));
+                self.line(format!("setnp {}", scratch));
+                self.line(format!("and {}, {}", dest, scratch));
+            }
+            BinaryOp::NotEqual => {
+                self.line(format!("setne {}", dest));
+                self.line(format!("setp {}", scratch));
+                self.line(format!("or {}, {}", dest, scratch));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
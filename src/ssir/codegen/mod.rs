@@ -0,0 +1,809 @@
+use crate::{
+    ast::{BinaryOp, Overflow, UnaryOp},
+    error,
+    reg::{self, RegisterLabel, RegisterManager, RegisterSize},
+    typechecker::{TaggedType, TypeKind},
+};
+
+use super::{
+    ins::{Function, Instruction},
+    opt::fold,
+    tmp::{Bound, IndexTmp, TmpChild, TmpNode},
+};
+
+mod convert;
+mod float;
+mod overflow;
+#[cfg(test)]
+mod tests;
+
+/// Generates x86-64 assembly for the GNU assembler, in Intel syntax. The
+/// functions have to be register allocated and laid out already.
+pub fn emit(functions: &[Function]) -> String {
+    let mut emitter = Emitter::new();
+    emitter.out.push_str("\t.intel_syntax noprefix\n\t.text\n");
+    for func in functions {
+        emitter.function(func);
+    }
+    emitter.panic_routine();
+    emitter.constant_pool();
+    // The stack doesn't need to be executable
+    emitter
+        .out
+        .push_str("\t.section .note.GNU-stack,\"\",@progbits\n");
+    emitter.out
+}
+
+fn ptr(size: &RegisterSize) -> &'static str {
+    match size {
+        RegisterSize::Byte => "BYTE PTR",
+        RegisterSize::Word => "WORD PTR",
+        RegisterSize::Dword => "DWORD PTR",
+        RegisterSize::Qword => "QWORD PTR",
+        RegisterSize::Oword => "XMMWORD PTR",
+    }
+}
+
+fn tipe_size(tipe: &TaggedType) -> RegisterSize {
+    reg::size_to_reg_size(tipe.size)
+}
+
+/// Integer and boolean literals as immediates
+fn immediate(value: &str) -> &str {
+    match value {
+        "true" => "1",
+        "false" => "0",
+        value => value,
+    }
+}
+
+/// Whether an instruction other than `mov` can take the literal, only `mov`
+/// has a 64 bit immediate form
+fn fits_imm32(value: &str) -> bool {
+    immediate(value)
+        .parse::<i64>()
+        .is_ok_and(|value| i32::try_from(value).is_ok())
+}
+
+struct Emitter {
+    out: String,
+    /// Data the code refers to, emitted into `.rodata` after the functions
+    constants: Vec<(String, String)>,
+    rmgr: RegisterManager,
+    /// Name of the function being emitted, blocks labels are local to it
+    name: String,
+    /// Where every parameter arrives, a register or an `rbp` offset
+    params: Vec<Result<RegisterLabel, usize>>,
+    /// Registers pushed by the prologue
+    saved: Vec<RegisterLabel>,
+    /// Set by the epilogue, which has to wait for the return value to be moved
+    /// into place before restoring the registers
+    returning: bool,
+    /// Labels used inside a single instruction's code, numbered per function
+    local_labels: usize,
+    /// Source location of the instructions being emitted
+    location: (usize, usize),
+    /// Stubs reporting a panic, emitted after the function's blocks. Every
+    /// label prints its message and exits.
+    panics: Vec<(String, String)>,
+    /// Whether any function needs the shared panic routine
+    panicking: bool,
+}
+
+impl Emitter {
+    fn new() -> Emitter {
+        Emitter {
+            out: String::new(),
+            constants: Vec::new(),
+            rmgr: RegisterManager::new(),
+            name: String::new(),
+            params: Vec::new(),
+            saved: Vec::new(),
+            returning: false,
+            local_labels: 0,
+            location: (0, 0),
+            panics: Vec::new(),
+            panicking: false,
+        }
+    }
+
+    fn line(&mut self, text: String) {
+        self.out.push('\t');
+        self.out.push_str(&text);
+        self.out.push('\n');
+    }
+
+    fn label(&self, id: usize) -> String {
+        format!(".L{}_LC{}", self.name, id)
+    }
+
+    /// Label of the read-only constant holding `data`, reusing an existing one
+    fn constant(&mut self, data: String) -> String {
+        let index = match self.constants.iter().position(|(_, other)| *other == data) {
+            Some(index) => index,
+            None => {
+                self.constants
+                    .push((format!(".LCONST{}", self.constants.len()), data));
+                self.constants.len() - 1
+            }
+        };
+        self.constants[index].0.clone()
+    }
+
+    fn constant_pool(&mut self) {
+        if self.constants.is_empty() {
+            return;
+        }
+
+        self.out.push_str("\t.section .rodata\n");
+        for (label, data) in &self.constants {
+            // 16 bytes covers the packed masks used by the float instructions
+            self.out
+                .push_str(&format!("\t.p2align 4\n{}:\n\t{}\n", label, data));
+        }
+    }
+
+    fn function(&mut self, func: &Function) {
+        self.name = func.name.clone();
+        self.saved = Vec::new();
+        self.local_labels = 0;
+        self.panics = Vec::new();
+
+        // Stack arguments sit above the return address and the saved `rbp`
+        let mut stack_offset = 16;
+        self.params = self
+            .rmgr
+            .argument_registers(&func.params)
+            .into_iter()
+            .map(|label| {
+                label.ok_or_else(|| {
+                    stack_offset += 8;
+                    stack_offset - 8
+                })
+            })
+            .collect();
+
+        self.out
+            .push_str(&format!("\t.globl {}\n{}:\n", func.name, func.name));
+        for ins in &func.instructions {
+            self.instruction(ins);
+        }
+        for label in &func.labels {
+            self.out.push_str(&format!("{}:\n", self.label(label.id)));
+            for ins in &label.instructions {
+                self.instruction(ins);
+            }
+        }
+        self.panic_stubs();
+    }
+
+    /// Label of a stub panicking with `message` at the current location
+    fn panic_label(&mut self, message: &str) -> String {
+        let message = format!(
+            "panic at {}:{}: {}",
+            self.location.0, self.location.1, message
+        );
+        if let Some((label, _)) = self.panics.iter().find(|(_, other)| *other == message) {
+            return label.clone();
+        }
+
+        let label = format!(".L{}_PANIC{}", self.name, self.panics.len());
+        self.panics.push((label.clone(), message));
+        label
+    }
+
+    fn panic_stubs(&mut self) {
+        for (label, message) in std::mem::take(&mut self.panics) {
+            let length = message.len() + 1;
+            let constant = self.constant(format!(".ascii \"{}\\n\"", message));
+            self.out.push_str(&format!("{}:\n", label));
+            self.line(format!("lea rdi, [rip+{}]", constant));
+            self.line(format!("mov esi, {}", length));
+            self.line("jmp .Lsencha_panic".to_string());
+            self.panicking = true;
+        }
+    }
+
+    /// Writes the message in `rdi`, `rsi` bytes long, to stderr and exits
+    /// with the status Rust uses for panics
+    fn panic_routine(&mut self) {
+        if !self.panicking {
+            return;
+        }
+
+        self.out.push_str(".Lsencha_panic:\n");
+        for line in [
+            "mov rdx, rsi",
+            "mov rsi, rdi",
+            "mov edi, 2",
+            "mov eax, 1",
+            "syscall",
+            "mov edi, 101",
+            "mov eax, 60",
+            "syscall",
+        ] {
+            self.line(line.to_string());
+        }
+    }
+
+    fn view(&self, label: &RegisterLabel, size: &RegisterSize) -> RegisterLabel {
+        self.rmgr.view(label, size).unwrap()
+    }
+
+    fn scratch(&self, size: &RegisterSize) -> RegisterLabel {
+        self.view(&reg::SCRATCH, size)
+    }
+
+    fn register(child: &TmpChild) -> Option<&RegisterLabel> {
+        match child {
+            TmpChild::TmpRef(_, _, Some(label)) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// A register, memory or immediate operand for the value
+    fn operand(&mut self, child: &TmpChild) -> String {
+        match child {
+            TmpChild::TmpRef(_, _, Some(label)) => label.to_string(),
+            TmpChild::TmpRef(id, _, None) => {
+                error::panic(format!("tmp{} wasn't given a register", id))
+            }
+            TmpChild::LoadVar(_, tipe, Some(slot)) => format!("{} {}", ptr(&tipe_size(tipe)), slot),
+            TmpChild::LoadVar(name, _, None) => {
+                error::panic(format!("Variable '{}' wasn't given a stack slot", name))
+            }
+            TmpChild::Param(index, tipe) => match &self.params[*index] {
+                Ok(label) => label.to_string(),
+                Err(offset) => format!("{} [rbp+{}]", ptr(&tipe_size(tipe)), offset),
+            },
+            TmpChild::Literal(value, tipe) if tipe.kind == TypeKind::Float => {
+                let label = self.float_constant(value, tipe);
+                format!("{} [rip+{}]", ptr(&tipe_size(tipe)), label)
+            }
+            TmpChild::Literal(value, _) => immediate(value).to_string(),
+            TmpChild::None => unreachable!(),
+        }
+    }
+
+    /// An operand for an instruction that can't take two memory operands or a
+    /// 64 bit immediate, such values are first loaded into `staging`
+    fn int_operand(
+        &mut self,
+        child: &TmpChild,
+        size: &RegisterSize,
+        staging: &RegisterLabel,
+    ) -> String {
+        let needs_staging = match child {
+            TmpChild::Literal(value, _) => !fits_imm32(value),
+            TmpChild::TmpRef(_, _, _) => false,
+            TmpChild::Param(index, _) => self.params[*index].is_err(),
+            _ => true,
+        };
+
+        let operand = self.operand(child);
+        if !needs_staging {
+            return operand;
+        }
+        let staging = self.view(staging, size);
+        self.line(format!("mov {}, {}", staging, operand));
+        staging.to_string()
+    }
+
+    /// Memory operand for a value of type `tipe` at `address`, which is moved
+    /// into the scratch register unless it already is in a register
+    fn memory(&mut self, address: &TmpChild, tipe: &TaggedType) -> String {
+        let base = match Emitter::register(address) {
+            Some(label) => label.clone(),
+            None => {
+                let operand = self.operand(address);
+                self.line(format!("mov {}, {}", reg::SCRATCH, operand));
+                reg::SCRATCH
+            }
+        };
+        format!("{} [{}]", ptr(&tipe_size(tipe)), base)
+    }
+
+    /// Checks the index against the length, as unsigned numbers so that
+    /// negative indices are out of bounds too, and computes the address of the
+    /// element. Indices known to be in bounds aren't checked. The bounds of a
+    /// range can be equal to the length.
+    fn element_address(&mut self, index: &IndexTmp, dest: &RegisterLabel) {
+        let literal = |child: &TmpChild| match child {
+            TmpChild::Literal(value, _) => value.parse::<usize>().ok(),
+            _ => None,
+        };
+        let in_bounds = |position, length| match index.bound {
+            Bound::Element => position < length,
+            Bound::RangeEnd | Bound::RangeStart => position <= length,
+        };
+        let offset = match (literal(&index.index), literal(&index.length)) {
+            (Some(position), Some(length)) if in_bounds(position, length) => {
+                Some(position * index.stride)
+            }
+            _ => None,
+        };
+
+        if offset.is_none() {
+            let position = self.operand(&index.index);
+            self.line(format!("mov {}, {}", reg::SCRATCH, position));
+            let length = self.operand(&index.length);
+            self.line(format!("cmp {}, {}", reg::SCRATCH, length));
+            let message = match (index.bound, literal(&index.length)) {
+                (Bound::RangeStart, _) => "range starts after its end".to_string(),
+                (Bound::Element, Some(length)) => {
+                    format!("index out of bounds, the length is {}", length)
+                }
+                (Bound::Element, None) => "index out of bounds".to_string(),
+                (Bound::RangeEnd, Some(length)) => {
+                    format!("range end out of bounds, the length is {}", length)
+                }
+                (Bound::RangeEnd, None) => "range end out of bounds".to_string(),
+            };
+            let panic = self.panic_label(&message);
+            let jump = match index.bound {
+                Bound::Element => "jae",
+                Bound::RangeEnd | Bound::RangeStart => "ja",
+            };
+            self.line(format!("{} {}", jump, panic));
+        }
+
+        let address_type = TaggedType::new(8, TypeKind::Numeric, Some(false));
+        let base = match Emitter::register(&index.base) {
+            Some(label) => label.clone(),
+            None => {
+                self.move_into(dest, &index.base, &address_type);
+                dest.clone()
+            }
+        };
+        match offset {
+            Some(0) if base == *dest => (),
+            Some(offset) => self.line(format!("lea {}, [{}+{}]", dest, base, offset)),
+            None if std::matches!(index.stride, 1 | 2 | 4 | 8) => self.line(format!(
+                "lea {}, [{}+{}*{}]",
+                dest,
+                base,
+                reg::SCRATCH,
+                index.stride
+            )),
+            None => {
+                self.line(format!(
+                    "imul {}, {}, {}",
+                    reg::SCRATCH,
+                    reg::SCRATCH,
+                    index.stride
+                ));
+                self.line(format!("lea {}, [{}+{}]", dest, base, reg::SCRATCH));
+            }
+        }
+    }
+
+    /// Copies a value into a register, unless it is already there
+    fn move_into(&mut self, dest: &RegisterLabel, value: &TmpChild, tipe: &TaggedType) {
+        if Emitter::register(value) == Some(dest) {
+            return;
+        }
+        if let TmpChild::Param(index, _) = value {
+            if self.params[*index].as_ref() == Ok(dest) {
+                return;
+            }
+        }
+
+        if tipe.kind == TypeKind::Float {
+            self.float_move(dest, value, tipe);
+        } else {
+            let operand = self.operand(value);
+            self.line(format!("mov {}, {}", dest, operand));
+        }
+    }
+
+    fn instruction(&mut self, ins: &Instruction) {
+        match ins {
+            Instruction::TmpNode(node, tipe, label) => {
+                let dest = match label {
+                    Some(label) => label.clone(),
+                    None => error::panic(format!("tmp{} wasn't given a register", node.id())),
+                };
+                self.tmp_node(node, tipe, &dest);
+            }
+            // Only reserves the slot, like arrays whose items are stored next
+            Instruction::VarDecl(_, TmpChild::None, _, _) => (),
+            Instruction::VarDecl(name, value, tipe, slot)
+            | Instruction::VarAssign(name, value, tipe, slot) => {
+                let slot = slot.unwrap_or_else(|| {
+                    error::panic(format!("Variable '{}' wasn't given a stack slot", name))
+                });
+                let dest = format!("{} {}", ptr(&tipe_size(tipe)), slot);
+                self.store(&dest, value, tipe);
+            }
+            Instruction::Store(address, value, tipe) => {
+                // The scratch register may be needed for the value, addresses
+                // are always computed into temporaries
+                let source = match tipe.kind {
+                    TypeKind::Float => None,
+                    _ => Some(self.int_operand(value, &tipe_size(tipe), &reg::SCRATCH)),
+                };
+                let dest = self.memory(address, tipe);
+                match source {
+                    Some(source) => self.line(format!("mov {}, {}", dest, source)),
+                    None => self.store(&dest, value, tipe),
+                }
+            }
+            Instruction::Branch(cond, then_label, else_label) => {
+                self.branch(cond, *then_label, *else_label)
+            }
+            Instruction::Jump(label) => {
+                let label = self.label(*label);
+                self.line(format!("jmp {}", label));
+            }
+            Instruction::Ret(value) => self.ret(value),
+            Instruction::Loc(line, column) => {
+                self.location = (*line, *column);
+                self.line(format!("# {}:{}", line, column));
+            }
+            Instruction::Pop => (),
+            Instruction::Prologue(saved, frame_size) => {
+                self.line("push rbp".to_string());
+                self.line("mov rbp, rsp".to_string());
+                for label in saved {
+                    self.line(format!("push {}", label));
+                }
+                if *frame_size > 0 {
+                    self.line(format!("sub rsp, {}", frame_size));
+                }
+                self.saved = saved.clone();
+            }
+            Instruction::Epilogue(_) => self.returning = true,
+            Instruction::Phi(name, _, _) => {
+                error::panic(format!("Phi of '{}' reached code generation", name))
+            }
+        }
+    }
+
+    fn store(&mut self, dest: &str, value: &TmpChild, tipe: &TaggedType) {
+        let size = tipe_size(tipe);
+        if tipe.kind == TypeKind::Float {
+            let source = match Emitter::register(value) {
+                Some(label) => label.clone(),
+                None => {
+                    let scratch = reg::FLOAT_SCRATCH;
+                    self.float_move(&scratch, value, tipe);
+                    scratch
+                }
+            };
+            self.line(format!("{} {}, {}", float::mov(tipe), dest, source));
+        } else {
+            let source = self.int_operand(value, &size, &reg::SCRATCH);
+            self.line(format!("mov {}, {}", dest, source));
+        }
+    }
+
+    fn tmp_node(&mut self, node: &TmpNode, tipe: &TaggedType, dest: &RegisterLabel) {
+        match node {
+            TmpNode::ValueTmp(value) => self.move_into(dest, &value.value, tipe),
+            TmpNode::AssignTmp(assign) => self.move_into(dest, &assign.value, tipe),
+            TmpNode::CastTmp(cast) => self.cast(&cast.value, tipe, dest),
+            TmpNode::AddressTmp(address) => {
+                let slot = address.slot.unwrap_or_else(|| {
+                    error::panic(format!(
+                        "Variable '{}' wasn't given a stack slot",
+                        address.var
+                    ))
+                });
+                self.line(format!("lea {}, {}", dest, slot));
+            }
+            TmpNode::LoadTmp(load) => {
+                let source = self.memory(&load.address, tipe);
+                if tipe.kind == TypeKind::Float {
+                    self.line(format!("{} {}, {}", float::mov(tipe), dest, source));
+                } else {
+                    self.line(format!("mov {}, {}", dest, source));
+                }
+            }
+            TmpNode::IndexTmp(index) => self.element_address(index, dest),
+            TmpNode::UnaryTmp(unary) => {
+                if tipe.kind == TypeKind::Float {
+                    return self.float_unary(&unary.op, &unary.value, tipe, dest);
+                }
+
+                self.move_into(dest, &unary.value, tipe);
+                match unary.op {
+                    UnaryOp::Negate => self.line(format!("neg {}", dest)),
+                    UnaryOp::Not => self.line(format!("xor {}, 1", dest)),
+                    UnaryOp::BitNot => self.line(format!("not {}", dest)),
+                    UnaryOp::None => unreachable!(),
+                }
+            }
+            TmpNode::BinaryTmp(binary) => {
+                let (lhs, rhs) = (&binary.lhs, &binary.rhs);
+                if binary.tipe.kind == TypeKind::Float {
+                    return self.float_binary(&binary.op, lhs, rhs, &binary.tipe, dest);
+                }
+
+                match binary.op {
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul
+                        if matches!(binary.overflow, Overflow::Checked | Overflow::Saturating) =>
+                    {
+                        self.checked_arithmetic(&binary.op, lhs, rhs, &binary.overflow, tipe, dest)
+                    }
+                    BinaryOp::Add
+                    | BinaryOp::Sub
+                    | BinaryOp::Mul
+                    | BinaryOp::BitAnd
+                    | BinaryOp::BitOr
+                    | BinaryOp::BitXor => self.int_arithmetic(&binary.op, lhs, rhs, tipe, dest),
+                    BinaryOp::Div | BinaryOp::Rem => {
+                        self.int_division(&binary.op, lhs, rhs, tipe, dest)
+                    }
+                    BinaryOp::Shl | BinaryOp::Shr => {
+                        self.int_shift(&binary.op, lhs, rhs, tipe, dest)
+                    }
+                    _ => self.int_comparison(&binary.op, lhs, rhs, &binary.tipe, dest),
+                }
+            }
+        }
+    }
+
+    /// x86 arithmetic overwrites its first operand, so `dest` gets `lhs` first
+    fn int_arithmetic(
+        &mut self,
+        op: &BinaryOp,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let mut size = tipe_size(tipe);
+        let (mut lhs, mut rhs) = (lhs, rhs);
+        let rhs_in_dest = Emitter::register(rhs) == Some(dest);
+        if rhs_in_dest && Emitter::register(lhs) != Some(dest) {
+            if *op == BinaryOp::Sub {
+                // dest = lhs - dest
+                self.line(format!("neg {}", dest));
+                let lhs = self.int_operand(lhs, &size, &reg::SCRATCH);
+                self.line(format!("add {}, {}", dest, lhs));
+                return;
+            }
+            std::mem::swap(&mut lhs, &mut rhs);
+        }
+
+        self.move_into(dest, lhs, tipe);
+        let mut dest = dest.clone();
+        let mnemonic = match op {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::BitAnd => "and",
+            BinaryOp::BitOr => "or",
+            BinaryOp::BitXor => "xor",
+            _ => "imul",
+        };
+
+        // There's no byte multiplication into an arbitrary register, the low
+        // byte of a doubleword product is the same
+        if *op == BinaryOp::Mul && size == RegisterSize::Byte {
+            size = RegisterSize::Dword;
+            dest = self.view(&dest, &size);
+            let operand = match Emitter::register(rhs) {
+                Some(label) => self.view(label, &size).to_string(),
+                None if matches!(rhs, TmpChild::Literal(_, _)) => self.operand(rhs),
+                None => {
+                    let operand = self.operand(rhs);
+                    let scratch = self.scratch(&size);
+                    self.line(format!("movsx {}, {}", scratch, operand));
+                    scratch.to_string()
+                }
+            };
+            self.line(format!("imul {}, {}", dest, operand));
+            return;
+        }
+
+        let rhs = self.int_operand(rhs, &size, &reg::SCRATCH);
+        self.line(format!("{} {}, {}", mnemonic, dest, rhs));
+    }
+
+    /// `idiv` and `div` divide `rdx:rax`, leaving the quotient in `rax` and
+    /// the remainder in `rdx`. Whatever lives in those two is saved around
+    /// them. Bytes and words are extended and divided as doublewords.
+    fn int_division(
+        &mut self,
+        op: &BinaryOp,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let size = tipe_size(tipe);
+        let wide = match size {
+            RegisterSize::Qword => RegisterSize::Qword,
+            _ => RegisterSize::Dword,
+        };
+        let signed = tipe.is_signed();
+        let extend = match (size == wide, signed) {
+            (true, _) => "mov",
+            (false, true) => "movsx",
+            (false, false) => "movzx",
+        };
+
+        let full_dest = self.view(dest, &RegisterSize::Qword);
+        let saved = [RegisterLabel::Rax, RegisterLabel::Rdx]
+            .into_iter()
+            .filter(|label| *label != full_dest)
+            .collect::<Vec<RegisterLabel>>();
+        for label in &saved {
+            self.line(format!("push {}", label));
+        }
+
+        let divisor = self.scratch(&wide);
+        let rhs = self.operand(rhs);
+        if rhs.parse::<i64>().is_ok() {
+            self.line(format!("mov {}, {}", divisor, rhs));
+        } else {
+            self.line(format!("{} {}, {}", extend, divisor, rhs));
+        }
+
+        let dividend = self.view(&RegisterLabel::Rax, &wide);
+        let lhs = self.operand(lhs);
+        if lhs.parse::<i64>().is_ok() {
+            self.line(format!("mov {}, {}", dividend, lhs));
+        } else {
+            self.line(format!("{} {}, {}", extend, dividend, lhs));
+        }
+
+        if signed {
+            match wide {
+                RegisterSize::Qword => self.line("cqo".to_string()),
+                _ => self.line("cdq".to_string()),
+            }
+            self.line(format!("idiv {}", divisor));
+        } else {
+            self.line("xor edx, edx".to_string());
+            self.line(format!("div {}", divisor));
+        }
+
+        let result = match op {
+            BinaryOp::Rem => RegisterLabel::Rdx,
+            _ => RegisterLabel::Rax,
+        };
+        let result = self.view(&result, &size);
+        if result != *dest {
+            self.line(format!("mov {}, {}", dest, result));
+        }
+        for label in saved.iter().rev() {
+            self.line(format!("pop {}", label));
+        }
+    }
+
+    /// A shift count that isn't a literal has to be in `cl`, `rcx` is saved
+    /// around the shift unless it is the destination. Right shifts of signed
+    /// values keep the sign.
+    fn int_shift(
+        &mut self,
+        op: &BinaryOp,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let size = tipe_size(tipe);
+        let mnemonic = match (op, tipe.is_signed()) {
+            (BinaryOp::Shl, _) => "shl",
+            (_, true) => "sar",
+            (_, false) => "shr",
+        };
+
+        if let TmpChild::Literal(count, _) = rhs {
+            let count = count.parse::<i128>().unwrap() & fold::shift_mask(tipe);
+            self.move_into(dest, lhs, tipe);
+            self.line(format!("{} {}, {}", mnemonic, dest, count));
+            return;
+        }
+
+        // The value is shifted in the scratch register, loading the count
+        // could overwrite it otherwise
+        let value = self.scratch(&size);
+        let operand = self.operand(lhs);
+        self.line(format!("mov {}, {}", value, operand));
+
+        let save = self.view(dest, &RegisterSize::Qword) != RegisterLabel::Rcx;
+        if save {
+            self.line("push rcx".to_string());
+        }
+        let count = self.resized(rhs, &RegisterSize::Byte);
+        if count != "cl" {
+            self.line(format!("mov cl, {}", count));
+        }
+        self.line(format!("{} {}, cl", mnemonic, value));
+        if save {
+            self.line("pop rcx".to_string());
+        }
+        self.line(format!("mov {}, {}", dest, value));
+    }
+
+    fn int_comparison(
+        &mut self,
+        op: &BinaryOp,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let size = tipe_size(tipe);
+        let (lhs, rhs_staging) = match Emitter::register(lhs) {
+            Some(label) => (label.to_string(), reg::SCRATCH),
+            None => {
+                // The destination only holds the flag, its register is free
+                // to stage the other operand
+                let operand = self.operand(lhs);
+                let scratch = self.scratch(&size);
+                self.line(format!("mov {}, {}", scratch, operand));
+                (scratch.to_string(), self.view(dest, &RegisterSize::Qword))
+            }
+        };
+        let rhs = self.int_operand(rhs, &size, &rhs_staging);
+        self.line(format!("cmp {}, {}", lhs, rhs));
+
+        // Unsigned orderings are above and below
+        let condition = match (op, tipe.is_signed()) {
+            (BinaryOp::Greater, true) => "g",
+            (BinaryOp::GreaterEq, true) => "ge",
+            (BinaryOp::Less, true) => "l",
+            (BinaryOp::LessEq, true) => "le",
+            (BinaryOp::Greater, false) => "a",
+            (BinaryOp::GreaterEq, false) => "ae",
+            (BinaryOp::Less, false) => "b",
+            (BinaryOp::LessEq, false) => "be",
+            (BinaryOp::Equal, _) => "e",
+            (BinaryOp::NotEqual, _) => "ne",
+            _ => unreachable!(),
+        };
+        self.line(format!("set{} {}", condition, dest));
+    }
+
+    fn branch(&mut self, cond: &TmpChild, then_label: usize, else_label: usize) {
+        let (then_label, else_label) = (self.label(then_label), self.label(else_label));
+        match cond {
+            TmpChild::Literal(value, _) => {
+                let target = if value == "true" {
+                    then_label
+                } else {
+                    else_label
+                };
+                self.line(format!("jmp {}", target));
+            }
+            cond => {
+                let operand = self.operand(cond);
+                match Emitter::register(cond) {
+                    Some(_) => self.line(format!("test {}, {}", operand, operand)),
+                    None => self.line(format!("cmp {}, 0", operand)),
+                }
+                self.line(format!("jne {}", then_label));
+                self.line(format!("jmp {}", else_label));
+            }
+        }
+    }
+
+    fn ret(&mut self, value: &Option<TmpChild>) {
+        if let Some(value) = value {
+            let tipe = super::get_child_type(value);
+            let dest = self
+                .rmgr
+                .return_register(reg::register_class(&tipe), &tipe_size(&tipe))
+                .unwrap();
+            self.move_into(&dest, value, &tipe);
+        }
+
+        if self.returning {
+            let saved = self.saved.clone();
+            if saved.is_empty() {
+                self.line("mov rsp, rbp".to_string());
+            } else {
+                self.line(format!("lea rsp, [rbp-{}]", saved.len() * 8));
+            }
+            for label in saved.iter().rev() {
+                self.line(format!("pop {}", label));
+            }
+            self.line("pop rbp".to_string());
+            self.returning = false;
+        }
+        self.line("ret".to_string());
+    }
+}
@@ -0,0 +1,212 @@
+//! `+`, `-` and `*` that notice when the result doesn't fit. Checked
+//! arithmetic jumps to a panic stub, saturating arithmetic clamps to the
+//! smallest or largest value of the type.
+
+use crate::{
+    ast::{BinaryOp, Overflow},
+    reg::{self, RegisterLabel, RegisterSize},
+    typechecker::{TaggedType, TypeKind},
+};
+
+use super::{tipe_size, Emitter, TmpChild};
+
+/// Largest value of a signed type
+fn signed_max(tipe: &TaggedType) -> i64 {
+    i64::MAX >> (64 - tipe.size * 8)
+}
+
+impl Emitter {
+    pub(super) fn checked_arithmetic(
+        &mut self,
+        op: &BinaryOp,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        overflow: &Overflow,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        match op {
+            BinaryOp::Mul if tipe.size == 8 => {
+                self.checked_wide_mul(lhs, rhs, overflow, tipe, dest)
+            }
+            BinaryOp::Mul => self.checked_mul(lhs, rhs, overflow, tipe, dest),
+            _ => self.checked_add_sub(op, lhs, rhs, overflow, tipe, dest),
+        }
+    }
+
+    /// Jumps to the panic stub on `condition`, or skips the clamping code that
+    /// follows unless `condition` holds. Returns the label ending the clamping
+    /// code.
+    fn on_overflow(&mut self, condition: &str, overflow: &Overflow) -> Option<String> {
+        if *overflow == Overflow::Checked {
+            let panic = self.panic_label("arithmetic overflow");
+            self.line(format!("j{} {}", condition, panic));
+            return None;
+        }
+
+        let done = self.local_label();
+        let inverse = match condition.strip_prefix('n') {
+            Some(condition) => condition.to_string(),
+            None => format!("n{}", condition),
+        };
+        self.line(format!("j{} {}", inverse, done));
+        Some(done)
+    }
+
+    /// The flags have to come from the operation itself, so `rhs` in the
+    /// destination is never negated first like `int_arithmetic` does
+    fn checked_add_sub(
+        &mut self,
+        op: &BinaryOp,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        overflow: &Overflow,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let size = tipe_size(tipe);
+        let mnemonic = match op {
+            BinaryOp::Add => "add",
+            _ => "sub",
+        };
+
+        let (mut lhs, mut rhs) = (lhs, rhs);
+        let rhs_in_dest = Emitter::register(rhs) == Some(dest);
+        if rhs_in_dest && Emitter::register(lhs) != Some(dest) && *op == BinaryOp::Sub {
+            let scratch = self.scratch(&size);
+            let operand = self.operand(lhs);
+            self.line(format!("mov {}, {}", scratch, operand));
+            self.line(format!("sub {}, {}", scratch, dest));
+            self.line(format!("mov {}, {}", dest, scratch));
+        } else {
+            if rhs_in_dest {
+                std::mem::swap(&mut lhs, &mut rhs);
+            }
+            self.move_into(dest, lhs, tipe);
+            let rhs = self.int_operand(rhs, &size, &reg::SCRATCH);
+            self.line(format!("{} {}, {}", mnemonic, dest, rhs));
+        }
+
+        let condition = if tipe.is_signed() { "o" } else { "c" };
+        let Some(done) = self.on_overflow(condition, overflow) else {
+            return;
+        };
+        if tipe.is_signed() {
+            // The wrapped result has the opposite sign of the real one, which
+            // turns into all ones below zero and all zeros above it
+            self.line(format!("sar {}, {}", dest, tipe.size * 8 - 1));
+            if tipe.size == 8 {
+                self.line(format!("btc {}, 63", dest));
+            } else {
+                self.line(format!("xor {}, {}", dest, -signed_max(tipe) - 1));
+            }
+        } else if *op == BinaryOp::Add {
+            self.line(format!("mov {}, -1", dest));
+        } else {
+            self.line(format!("mov {}, 0", dest));
+        }
+        self.out.push_str(&format!("{}:\n", done));
+    }
+
+    /// Narrower operands are extended to quadwords, where their product always
+    /// fits, and the product is compared with its own low part
+    fn checked_mul(
+        &mut self,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        overflow: &Overflow,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let wide = TaggedType::new(8, TypeKind::Numeric, tipe.signed);
+        let full_dest = self.view(dest, &RegisterSize::Qword);
+        // `rhs` first, it may live in the destination
+        self.extend_into(&reg::SCRATCH, rhs, tipe, &wide);
+        self.extend_into(&full_dest, lhs, tipe, &wide);
+        self.line(format!("imul {}, {}", full_dest, reg::SCRATCH));
+
+        if tipe.is_signed() {
+            let low = self.view(dest, &tipe_size(tipe));
+            let extend = if tipe.size == 4 { "movsxd" } else { "movsx" };
+            self.line(format!("{} {}, {}", extend, reg::SCRATCH, low));
+            self.line(format!("cmp {}, {}", reg::SCRATCH, full_dest));
+        } else {
+            self.line(format!("mov {}, {}", reg::SCRATCH, full_dest));
+            self.line(format!("shr {}, {}", reg::SCRATCH, tipe.size * 8));
+        }
+
+        let Some(done) = self.on_overflow("ne", overflow) else {
+            return;
+        };
+        if tipe.is_signed() {
+            self.line(format!("sar {}, 63", full_dest));
+            self.line(format!("xor {}, {}", dest, signed_max(tipe)));
+        } else {
+            self.line(format!("mov {}, -1", dest));
+        }
+        self.out.push_str(&format!("{}:\n", done));
+    }
+
+    fn extend_into(
+        &mut self,
+        dest: &RegisterLabel,
+        value: &TmpChild,
+        from: &TaggedType,
+        to: &TaggedType,
+    ) {
+        match value {
+            TmpChild::Literal(_, _) => {
+                let operand = self.operand(value);
+                self.line(format!("mov {}, {}", dest, operand));
+            }
+            value => self.int_to_int(value, from, to, dest),
+        }
+    }
+
+    /// The one operand forms multiply into `rdx:rax` and set the overflow and
+    /// carry flags when the high half is needed. Whatever lives in those two
+    /// is saved around them, like for a division.
+    fn checked_wide_mul(
+        &mut self,
+        lhs: &TmpChild,
+        rhs: &TmpChild,
+        overflow: &Overflow,
+        tipe: &TaggedType,
+        dest: &RegisterLabel,
+    ) {
+        let saved = [RegisterLabel::Rax, RegisterLabel::Rdx]
+            .into_iter()
+            .filter(|label| label != dest)
+            .collect::<Vec<RegisterLabel>>();
+        for label in &saved {
+            self.line(format!("push {}", label));
+        }
+
+        let rhs = self.operand(rhs);
+        self.line(format!("mov {}, {}", reg::SCRATCH, rhs));
+        let lhs = self.operand(lhs);
+        self.line(format!("mov rax, {}", lhs));
+        let mnemonic = if tipe.is_signed() { "imul" } else { "mul" };
+        self.line(format!("{} {}", mnemonic, reg::SCRATCH));
+
+        if let Some(done) = self.on_overflow("o", overflow) {
+            if tipe.is_signed() {
+                // The high half has the sign of the real product
+                self.line("mov rax, rdx".to_string());
+                self.line("sar rax, 63".to_string());
+                self.line("btc rax, 63".to_string());
+                self.line("not rax".to_string());
+            } else {
+                self.line("mov rax, -1".to_string());
+            }
+            self.out.push_str(&format!("{}:\n", done));
+        }
+
+        if *dest != RegisterLabel::Rax {
+            self.line(format!("mov {}, rax", dest));
+        }
+        for label in saved.iter().rev() {
+            self.line(format!("pop {}", label));
+        }
+    }
+}
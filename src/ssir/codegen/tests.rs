@@ -0,0 +1,402 @@
+//! Instruction selection for every integer width `TypeCheck::new` declares
+
+use crate::{
+    parser::Parser,
+    ssir::{
+        cfg::Cfg,
+        frame,
+        opt::fold,
+        pass::{OptLevel, PassManager},
+        regalloc::linear,
+        SSir,
+    },
+    tokenizer::Tokenizer,
+    typechecker::{TaggedType, TypeCheck, TypeKind},
+};
+
+use super::emit;
+
+const SIGNED: [&str; 4] = ["i8", "i16", "i32", "i64"];
+const UNSIGNED: [&str; 4] = ["u8", "u16", "u32", "u64"];
+
+/// Runs the whole pipeline over `source` without optimizations
+fn compile(source: &str) -> String {
+    let source = source.to_string();
+    let mut parser = Parser::new(Tokenizer::new(&source), &source);
+    parser.parse();
+
+    let mut typecheck = TypeCheck::new();
+    for decl in &mut parser.declarations {
+        typecheck.check(decl);
+    }
+    assert!(!typecheck.has_errors());
+
+    let mut ssir = SSir::new();
+    ssir.generate(&mut parser.declarations);
+    let mut cfgs = ssir
+        .get_functions()
+        .into_iter()
+        .map(Cfg::build)
+        .collect::<Vec<Cfg>>();
+    PassManager::new(OptLevel::O0).run(&mut cfgs);
+
+    let functions = cfgs
+        .into_iter()
+        .map(|mut cfg| {
+            linear::allocate(&mut cfg);
+            frame::layout(&mut cfg);
+            cfg.into_function()
+        })
+        .collect::<Vec<_>>();
+    emit(&functions)
+}
+
+fn division(tipe: &str) -> String {
+    compile(&format!(
+        "func div(a: {0}, b: {0}) -> {0} {{ ret a / b; }}",
+        tipe
+    ))
+}
+
+fn comparison(tipe: &str, op: &str) -> String {
+    compile(&format!(
+        "func cmp(a: {0}, b: {0}) -> bool {{ ret a {1} b; }}",
+        tipe, op
+    ))
+}
+
+fn has_line(asm: &str, prefix: &str) -> bool {
+    asm.lines()
+        .any(|line| line.trim_start().starts_with(prefix))
+}
+
+#[test]
+fn signed_division() {
+    for tipe in SIGNED {
+        let asm = division(tipe);
+        assert!(has_line(&asm, "idiv "), "{}:\n{}", tipe, asm);
+        assert!(
+            has_line(&asm, "cdq") || has_line(&asm, "cqo"),
+            "{}:\n{}",
+            tipe,
+            asm
+        );
+    }
+}
+
+#[test]
+fn unsigned_division() {
+    for tipe in UNSIGNED {
+        let asm = division(tipe);
+        assert!(has_line(&asm, "div "), "{}:\n{}", tipe, asm);
+        assert!(has_line(&asm, "xor edx, edx"), "{}:\n{}", tipe, asm);
+        assert!(!has_line(&asm, "idiv "), "{}:\n{}", tipe, asm);
+    }
+}
+
+#[test]
+fn small_division_operands_are_extended() {
+    for (signed, unsigned) in [("i8", "u8"), ("i16", "u16")] {
+        let asm = division(signed);
+        assert!(has_line(&asm, "movsx "), "{}:\n{}", signed, asm);
+        let asm = division(unsigned);
+        assert!(has_line(&asm, "movzx "), "{}:\n{}", unsigned, asm);
+        assert!(!has_line(&asm, "movsx "), "{}:\n{}", unsigned, asm);
+    }
+}
+
+#[test]
+fn signed_comparisons() {
+    let conditions = [
+        ("<", "setl "),
+        ("<=", "setle "),
+        (">", "setg "),
+        (">=", "setge "),
+    ];
+    for tipe in SIGNED {
+        for (op, set) in conditions {
+            let asm = comparison(tipe, op);
+            assert!(has_line(&asm, set), "{} {}:\n{}", tipe, op, asm);
+        }
+    }
+}
+
+#[test]
+fn unsigned_comparisons() {
+    let conditions = [
+        ("<", "setb "),
+        ("<=", "setbe "),
+        (">", "seta "),
+        (">=", "setae "),
+    ];
+    for tipe in UNSIGNED {
+        for (op, set) in conditions {
+            let asm = comparison(tipe, op);
+            assert!(has_line(&asm, set), "{} {}:\n{}", tipe, op, asm);
+        }
+    }
+}
+
+#[test]
+fn equality_ignores_signedness() {
+    for tipe in SIGNED.iter().chain(UNSIGNED.iter()) {
+        assert!(has_line(&comparison(tipe, "=="), "sete "), "{}", tipe);
+        assert!(has_line(&comparison(tipe, "!="), "setne "), "{}", tipe);
+    }
+}
+
+fn checked(tipe: &str, builtin: &str) -> String {
+    compile(&format!(
+        "func f(a: {0}, b: {0}) -> {0} {{ ret {1}(a, b); }}",
+        tipe, builtin
+    ))
+}
+
+#[test]
+fn checked_add_and_sub_test_the_flag_of_their_signedness() {
+    for builtin in ["checked_add", "checked_sub"] {
+        for tipe in SIGNED {
+            let asm = checked(tipe, builtin);
+            assert!(has_line(&asm, "jo .Lf_PANIC0"), "{}:\n{}", tipe, asm);
+        }
+        for tipe in UNSIGNED {
+            let asm = checked(tipe, builtin);
+            assert!(has_line(&asm, "jc .Lf_PANIC0"), "{}:\n{}", tipe, asm);
+        }
+    }
+}
+
+#[test]
+fn overflow_panics_through_one_routine() {
+    let asm = compile(
+        "func f(a: i32, b: i64) -> i64 { ret checked_mul(a, 2) as i64 + checked_mul(b, 3); }",
+    );
+    assert!(asm.contains(".Lf_PANIC0:\n"), "{}", asm);
+    assert!(asm.contains("panic at 1:"), "{}", asm);
+    assert_eq!(asm.matches(".Lsencha_panic:\n").count(), 1, "{}", asm);
+    assert!(!has_line(&asm, "jmp .Lf_PANIC"), "{}", asm);
+}
+
+#[test]
+fn floats_from_two_to_the_63_convert_to_u64_through_an_offset() {
+    for tipe in ["f32", "f64"] {
+        let asm = compile(&format!("func f(x: {}) -> u64 {{ ret x as u64; }}", tipe));
+        let suffix = if tipe == "f32" { "ss" } else { "sd" };
+        assert!(
+            has_line(&asm, &format!("comi{}", suffix)),
+            "{}:\n{}",
+            tipe,
+            asm
+        );
+        assert!(
+            has_line(&asm, &format!("sub{}", suffix)),
+            "{}:\n{}",
+            tipe,
+            asm
+        );
+        assert!(has_line(&asm, "xor "), "{}:\n{}", tipe, asm);
+    }
+
+    let u64_type = TaggedType::new(8, TypeKind::Numeric, Some(false));
+    let f64_type = TaggedType::new(8, TypeKind::Float, None);
+    let cast = |value: &str| fold::cast_literal(value, &f64_type, &u64_type).unwrap();
+    assert_eq!(cast("1e19"), "10000000000000000000");
+    assert_eq!(cast("9223372036854775808.0"), "9223372036854775808");
+    assert_eq!(cast("3.7"), "3");
+    assert_eq!(cast("2e19"), "0");
+}
+
+#[test]
+fn pointers_load_and_store_the_width_of_their_pointee() {
+    let widths = ["BYTE", "WORD", "DWORD", "QWORD"];
+    for (index, width) in widths.iter().enumerate() {
+        for tipe in [SIGNED[index], UNSIGNED[index]] {
+            let asm = compile(&format!(
+                "func f(p: ptr<{0}>) -> {0} {{ x := *p; *p = 1; ret x; }}",
+                tipe
+            ));
+            // Everything else in `f` lives on the stack
+            let memory = format!("{} PTR [r", width);
+            let accesses = asm
+                .lines()
+                .filter(|line| line.contains(&memory) && !line.contains("[rbp"));
+            assert_eq!(accesses.count(), 2, "{}:\n{}", tipe, asm);
+        }
+    }
+}
+
+#[test]
+fn only_indices_not_known_to_be_in_bounds_are_checked() {
+    let asm = compile("func f(i: i64) -> i32 { var a: i32[3] = {1, 2, 3}; ret a[i] + a[2]; }");
+    assert_eq!(asm.matches("\tcmp ").count(), 1, "{}", asm);
+    assert!(has_line(&asm, "jae .Lf_PANIC0"), "{}", asm);
+    assert!(
+        asm.contains("index out of bounds, the length is 3"),
+        "{}",
+        asm
+    );
+}
+
+#[test]
+fn rows_of_nested_arrays_are_scaled_by_their_size() {
+    let asm = compile("func f(i: i64) -> i32 { var g: i32[2][3] = {{0; 3}; 2}; ret g[i][i]; }");
+    assert!(has_line(&asm, "imul r11, r11, 12"), "{}", asm);
+    assert_eq!(asm.matches("\tjae ").count(), 2, "{}", asm);
+}
+
+#[test]
+fn bracketed_array_types_name_the_outer_length_first() {
+    let rows = |tipe: &str| {
+        compile(&format!(
+            "func f(i: i64, p: ptr<{0}>) -> i32 {{ var g: {0} = {{{{0; 3}}; 2}}; ret g[i][i]; }}",
+            tipe
+        ))
+    };
+    for tipe in ["i32[2][3]", "[[i32; 3]; 2]", "[i32[3]; 2]"] {
+        let asm = rows(tipe);
+        assert!(has_line(&asm, "cmp r11, 2"), "{}:\n{}", tipe, asm);
+        assert!(has_line(&asm, "imul r11, r11, 12"), "{}:\n{}", tipe, asm);
+        assert!(has_line(&asm, "cmp r11, 3"), "{}:\n{}", tipe, asm);
+    }
+}
+
+#[test]
+fn range_bounds_can_reach_the_length() {
+    let asm = compile("func f(s: []i32, a: u64, b: u64) -> u64 { ret len(s[a..b]); }");
+    assert!(has_line(&asm, "ja .Lf_PANIC0"), "{}", asm);
+    assert!(has_line(&asm, "ja .Lf_PANIC1"), "{}", asm);
+    assert!(!asm.contains("\tjae "), "{}", asm);
+    assert!(asm.contains("range end out of bounds"), "{}", asm);
+    assert!(asm.contains("range starts after its end"), "{}", asm);
+}
+
+#[test]
+fn float_arithmetic_uses_the_scalar_instruction_of_its_width() {
+    for (tipe, suffix) in [("f32", "ss"), ("f64", "sd")] {
+        for (op, mnemonic) in [("+", "add"), ("-", "sub"), ("*", "mul"), ("/", "div")] {
+            let asm = compile(&format!(
+                "func f(a: {0}, b: {0}) -> {0} {{ ret a {1} b; }}",
+                tipe, op
+            ));
+            let instruction = format!("{}{} ", mnemonic, suffix);
+            assert!(has_line(&asm, &instruction), "{} {}:\n{}", tipe, op, asm);
+            assert!(!has_line(&asm, "imul ") && !has_line(&asm, "idiv "));
+        }
+    }
+}
+
+#[test]
+fn float_literals_and_sign_masks_come_from_memory() {
+    for (tipe, data) in [("f32", ".float 2.5"), ("f64", ".double 2.5")] {
+        let asm = compile(&format!("func f(a: {0}) -> {0} {{ ret -a * 2.5; }}", tipe));
+        assert!(asm.contains(data), "{}:\n{}", tipe, asm);
+        assert!(has_line(&asm, "xorps "), "{}:\n{}", tipe, asm);
+    }
+}
+
+#[test]
+fn float_comparisons_are_false_for_nan() {
+    let conditions = [
+        ("<", vec!["seta "]),
+        ("<=", vec!["setae "]),
+        (">", vec!["seta "]),
+        (">=", vec!["setae "]),
+        ("==", vec!["sete ", "setnp "]),
+        ("!=", vec!["setne ", "setp "]),
+    ];
+    for (tipe, suffix) in [("f32", "ss"), ("f64", "sd")] {
+        for (op, sets) in &conditions {
+            let asm = comparison(tipe, op);
+            let compare = format!("ucomi{} ", suffix);
+            assert!(has_line(&asm, &compare), "{} {}:\n{}", tipe, op, asm);
+            for set in sets {
+                assert!(has_line(&asm, set), "{} {}:\n{}", tipe, op, asm);
+            }
+            assert!(!has_line(&asm, "setb") && !has_line(&asm, "setl"));
+        }
+    }
+}
+
+fn cast(from: &str, to: &str) -> String {
+    compile(&format!(
+        "func f(x: {}) -> {} {{ ret x as {}; }}",
+        from, to, to
+    ))
+}
+
+#[test]
+fn widening_casts_extend_by_the_signedness_of_the_source() {
+    let cases = [
+        ("i8", "i64", "movsx "),
+        ("i16", "u32", "movsx "),
+        ("i32", "i64", "movsxd "),
+        ("u8", "i16", "movzx "),
+        ("u16", "u64", "movzx "),
+    ];
+    for (from, to, extend) in cases {
+        let asm = cast(from, to);
+        assert!(has_line(&asm, extend), "{} as {}:\n{}", from, to, asm);
+    }
+
+    // Writing the doubleword clears the upper half
+    let asm = cast("u32", "i64");
+    assert!(
+        !has_line(&asm, "movzx ") && !has_line(&asm, "movsx"),
+        "{}",
+        asm
+    );
+
+    for (from, to) in [("i64", "u8"), ("u32", "i16"), ("i16", "u16")] {
+        let asm = cast(from, to);
+        assert!(
+            !has_line(&asm, "movzx ") && !has_line(&asm, "movsx"),
+            "{}",
+            asm
+        );
+    }
+}
+
+#[test]
+fn casts_between_ints_and_floats_convert() {
+    let cases = [
+        ("f32", "f64", "cvtss2sd "),
+        ("f64", "f32", "cvtsd2ss "),
+        ("i32", "f64", "cvtsi2sd "),
+        ("u8", "f32", "cvtsi2ss "),
+        ("f64", "i16", "cvttsd2si "),
+        ("f32", "u32", "cvttss2si "),
+    ];
+    for (from, to, convert) in cases {
+        let asm = cast(from, to);
+        assert!(has_line(&asm, convert), "{} as {}:\n{}", from, to, asm);
+    }
+
+    // The top bit of a u64 would read as a sign
+    let asm = cast("u64", "f64");
+    assert!(has_line(&asm, "js ") && has_line(&asm, "shr "), "{}", asm);
+}
+
+#[test]
+fn literal_casts_fold_like_the_instructions() {
+    let tipe = |name: &str| {
+        let size = name[1..].parse::<usize>().unwrap() / 8;
+        match &name[..1] {
+            "f" => TaggedType::new(size, TypeKind::Float, None),
+            signed => TaggedType::new(size, TypeKind::Numeric, Some(signed == "i")),
+        }
+    };
+    let cases = [
+        ("300", "i32", "u8", "44"),
+        ("-1", "i8", "u16", "65535"),
+        ("-1", "i32", "u64", "18446744073709551615"),
+        ("200", "u8", "i8", "-56"),
+        ("-3.9", "f64", "i32", "-3"),
+        ("3e10", "f64", "i32", "-2147483648"),
+        ("4e9", "f32", "u32", "4000000000"),
+        ("7", "i32", "f32", "7.0"),
+    ];
+    for (value, from, to, expected) in cases {
+        let folded = fold::cast_literal(value, &tipe(from), &tipe(to));
+        assert_eq!(folded.as_deref(), Some(expected), "{} as {}", value, to);
+    }
+}
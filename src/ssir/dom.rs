@@ -0,0 +1,118 @@
+use std::collections::BTreeSet;
+
+use super::cfg::Cfg;
+
+/// Dominator tree and dominance frontiers of the blocks reachable from the entry
+pub struct Dominators {
+    pub children: Vec<Vec<usize>>,
+    pub frontiers: Vec<BTreeSet<usize>>,
+}
+
+impl Dominators {
+    /// Uses the iterative algorithm from "A Simple, Fast Dominance Algorithm"
+    /// by Cooper, Harvey and Kennedy
+    pub fn compute(cfg: &Cfg) -> Dominators {
+        let block_count = cfg.blocks.len();
+        let rpo = reverse_postorder(cfg);
+
+        let mut rpo_index = vec![usize::MAX; block_count];
+        for (index, block) in rpo.iter().enumerate() {
+            rpo_index[*block] = index;
+        }
+
+        // Immediate dominator of every block, `None` for unreachable blocks.
+        // The entry block is its own immediate dominator.
+        let mut idom: Vec<Option<usize>> = vec![None; block_count];
+        idom[0] = Some(0);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for pred in &cfg.blocks[*block].preds {
+                    if idom[*pred].is_none() {
+                        continue;
+                    }
+
+                    new_idom = match new_idom {
+                        None => Some(*pred),
+                        Some(other) => Some(intersect(&idom, &rpo_index, *pred, other)),
+                    };
+                }
+
+                if new_idom.is_some() && idom[*block] != new_idom {
+                    idom[*block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); block_count];
+        for block in rpo.iter().skip(1) {
+            children[idom[*block].unwrap()].push(*block);
+        }
+
+        let mut frontiers = vec![BTreeSet::new(); block_count];
+        for block in &rpo {
+            let preds = &cfg.blocks[*block].preds;
+            if preds.len() < 2 {
+                continue;
+            }
+
+            for pred in preds {
+                let mut runner = *pred;
+                if idom[runner].is_none() {
+                    continue;
+                }
+
+                while Some(runner) != idom[*block] {
+                    frontiers[runner].insert(*block);
+                    runner = idom[runner].unwrap();
+                }
+            }
+        }
+
+        Dominators {
+            children,
+            frontiers,
+        }
+    }
+}
+
+fn intersect(idom: &[Option<usize>], rpo_index: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idom[a].unwrap();
+        }
+        while rpo_index[b] > rpo_index[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
+
+pub fn reverse_postorder(cfg: &Cfg) -> Vec<usize> {
+    let mut visited = vec![false; cfg.blocks.len()];
+    let mut postorder = Vec::with_capacity(cfg.blocks.len());
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+
+    while let Some((block, next_succ)) = stack.pop() {
+        let succs = &cfg.blocks[block].succs;
+        if next_succ < succs.len() {
+            stack.push((block, next_succ + 1));
+
+            let succ = succs[next_succ];
+            if !visited[succ] {
+                visited[succ] = true;
+                stack.push((succ, 0));
+            }
+        } else {
+            postorder.push(block);
+        }
+    }
+
+    postorder.reverse();
+    postorder
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use super::{
+    cfg::Cfg,
+    ins::Instruction,
+    tmp::{TmpChild, TmpNode},
+};
+
+/// Location of a variable in the stack frame, below the frame pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub offset: usize,
+}
+
+impl std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[rbp-{}]", self.offset)
+    }
+}
+
+/// Size of a pushed register
+const REGISTER_BYTES: usize = 8;
+/// `rsp` has to be 16 byte aligned at every call
+const STACK_ALIGNMENT: usize = 16;
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+/// Natural alignment of a variable. Arrays only record their total size, so
+/// this is the largest power of two dividing it, up to a quadword.
+fn alignment(size: usize) -> usize {
+    if size == 0 {
+        return 1;
+    }
+    (1 << size.trailing_zeros()).min(REGISTER_BYTES)
+}
+
+/// Gives every variable of the function, spill slots included, its own
+/// `rbp`-relative slot and writes them into the loads and stores. The slots
+/// sit below the callee-saved registers pushed by the prologue, which also
+/// gets the number of bytes to reserve for them.
+pub fn layout(cfg: &mut Cfg) {
+    // Variables in the order they are first defined, with their size
+    let mut variables: Vec<(String, usize)> = Vec::new();
+    let mut saved = 0;
+    for ins in cfg
+        .blocks
+        .iter()
+        .flat_map(|block| block.instructions.iter())
+    {
+        match ins {
+            Instruction::VarDecl(name, _, tipe, _) | Instruction::VarAssign(name, _, tipe, _) => {
+                match variables.iter_mut().find(|(var, _)| var == name) {
+                    Some((_, size)) => *size = (*size).max(tipe.size),
+                    None => variables.push((name.clone(), tipe.size)),
+                }
+            }
+            Instruction::Prologue(registers, _) => saved = registers.len() * REGISTER_BYTES,
+            _ => (),
+        }
+    }
+
+    // Largest alignment first, so no padding is needed between the slots
+    variables.sort_by_key(|(_, size)| std::cmp::Reverse(alignment(*size)));
+
+    let mut offset = saved;
+    let mut slots = HashMap::new();
+    for (name, size) in variables {
+        offset = align_up(offset + size, alignment(size));
+        slots.insert(name, Slot { offset });
+    }
+    let frame_size = align_up(offset, STACK_ALIGNMENT) - saved;
+
+    let slot_of = |name: &String| slots.get(name).copied();
+    for block in &mut cfg.blocks {
+        for ins in &mut block.instructions {
+            for operand in ins.operands_mut() {
+                if let TmpChild::LoadVar(name, _, slot) = operand {
+                    *slot = slot_of(name);
+                }
+            }
+
+            match ins {
+                Instruction::VarDecl(name, _, _, slot)
+                | Instruction::VarAssign(name, _, _, slot) => *slot = slot_of(name),
+                Instruction::TmpNode(TmpNode::AddressTmp(address), _, _) => {
+                    address.slot = slot_of(&address.var)
+                }
+                Instruction::Prologue(_, size) => *size = frame_size,
+                _ => (),
+            }
+        }
+        for operand in block.terminator.operands_mut() {
+            if let TmpChild::LoadVar(name, _, slot) = operand {
+                *slot = slot_of(name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssir::{
+        pass::{OptLevel, PassManager},
+        regalloc::linear,
+        tests::lower,
+    };
+
+    /// Lays out the frame of `source` and returns the variables with their
+    /// slot and size, the saved registers and the reserved bytes
+    fn frame(source: &str, level: OptLevel) -> (HashMap<String, (usize, usize)>, usize, usize) {
+        let mut cfgs = lower(source);
+        PassManager::new(level).run(&mut cfgs);
+        let mut cfg = cfgs.remove(0);
+        linear::allocate(&mut cfg);
+        layout(&mut cfg);
+
+        let mut slots = HashMap::new();
+        let (mut saved, mut frame_size) = (0, 0);
+        for ins in cfg.blocks.iter().flat_map(|block| &block.instructions) {
+            match ins {
+                Instruction::VarDecl(name, _, tipe, slot)
+                | Instruction::VarAssign(name, _, tipe, slot) => {
+                    let offset = slot.unwrap().offset;
+                    let (_, size) = slots.entry(name.clone()).or_insert((offset, 0));
+                    *size = tipe.size.max(*size);
+                }
+                Instruction::Prologue(registers, size) => {
+                    (saved, frame_size) = (registers.len() * REGISTER_BYTES, *size)
+                }
+                _ => (),
+            }
+        }
+        (slots, saved, frame_size)
+    }
+
+    fn assert_valid(slots: &HashMap<String, (usize, usize)>, saved: usize, frame_size: usize) {
+        assert_eq!((saved + frame_size) % STACK_ALIGNMENT, 0);
+        let mut ranges = slots
+            .iter()
+            .map(|(name, (offset, size))| {
+                assert_eq!(offset % alignment(*size), 0, "{}", name);
+                assert!(
+                    *offset >= saved + size,
+                    "{} overlaps the saved registers",
+                    name
+                );
+                assert!(
+                    *offset <= saved + frame_size,
+                    "{} is outside the frame",
+                    name
+                );
+                (offset - size, *offset, name)
+            })
+            .collect::<Vec<_>>();
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            assert!(
+                pair[0].1 <= pair[1].0,
+                "{:?} overlaps {:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn slots_are_aligned_and_disjoint() {
+        let source = "func f(a: i8, b: i64, c: i16, i: i64) -> i64 {
+            var d: i32[3] = {1, 2, 3}
+            e := a
+            g := c
+            ret b + (d[i] as i64) + (e as i64) + (g as i64)
+        }";
+        let (slots, saved, frame_size) = frame(source, OptLevel::O0);
+        assert_eq!(slots["d"].1, 12);
+        assert_eq!(slots["a"].1, 1);
+        assert_eq!(saved, 0);
+        assert_valid(&slots, saved, frame_size);
+
+        // Sorted by alignment, the slots need no padding between them
+        let total = slots.values().map(|(_, size)| size).sum::<usize>();
+        assert_eq!(frame_size, align_up(total, STACK_ALIGNMENT));
+    }
+
+    #[test]
+    fn spill_slots_sit_below_the_saved_registers() {
+        let mut expr = "a * 24".to_string();
+        for factor in (1..24).rev() {
+            expr = format!("a * {} + ({})", factor, expr);
+        }
+        let source = format!("func f(a: i64) -> i64 {{ ret {}; }}", expr);
+        let (slots, saved, frame_size) = frame(&source, OptLevel::O1);
+        assert!(saved > 0);
+        assert!(slots.keys().any(|name| name.starts_with("$spill")));
+        assert_valid(&slots, saved, frame_size);
+    }
+}
@@ -1,15 +1,80 @@
 use crate::typechecker::TaggedType;
 use crate::reg::RegisterLabel;
+use super::frame::Slot;
 use super::tmp::{TmpChild, TmpNode};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Instruction {
     TmpNode(TmpNode, TaggedType, Option<RegisterLabel>),
-    VarDecl(String, TmpChild, TaggedType),
-    VarAssign(String, TmpChild, TaggedType),
-    If(TmpNode),
+    /// Stores to a variable carry the stack slot given by the frame layout
+    VarDecl(String, TmpChild, TaggedType, Option<Slot>),
+    VarAssign(String, TmpChild, TaggedType, Option<Slot>),
+    /// Writes the value to the memory the address points to
+    Store(TmpChild, TmpChild, TaggedType),
+    /// Jumps to the first label if the condition is true, otherwise to the second
+    Branch(TmpChild, usize, usize),
     Jump(usize),
+    Ret(Option<TmpChild>),
+    /// SSA merge of a variable, one incoming value per predecessor block
+    Phi(String, Vec<(usize, TmpChild)>, TaggedType),
+    /// Source location of the statement that follows
+    Loc(usize, usize),
     Pop,
+    /// Saves the callee-saved registers the function uses and reserves the
+    /// stack frame, whose size is filled in by the frame layout
+    Prologue(Vec<RegisterLabel>, usize),
+    /// Restores the registers saved by the prologue before returning
+    Epilogue(Vec<RegisterLabel>),
+}
+
+impl Instruction {
+    pub fn operands(&self) -> Vec<&TmpChild> {
+        match self {
+            Instruction::TmpNode(node, _, _) => node.operands(),
+            Instruction::VarDecl(_, value, _, _) | Instruction::VarAssign(_, value, _, _) => {
+                vec![value]
+            }
+            Instruction::Store(address, value, _) => vec![address, value],
+            Instruction::Branch(cond, _, _) => vec![cond],
+            Instruction::Ret(value) => value.iter().collect(),
+            Instruction::Phi(_, incoming, _) => incoming.iter().map(|(_, value)| value).collect(),
+            Instruction::Jump(_)
+            | Instruction::Loc(_, _)
+            | Instruction::Pop
+            | Instruction::Prologue(_, _)
+            | Instruction::Epilogue(_) => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut TmpChild> {
+        match self {
+            Instruction::TmpNode(node, _, _) => node.operands_mut(),
+            Instruction::VarDecl(_, value, _, _) | Instruction::VarAssign(_, value, _, _) => {
+                vec![value]
+            }
+            Instruction::Store(address, value, _) => vec![address, value],
+            Instruction::Branch(cond, _, _) => vec![cond],
+            Instruction::Ret(value) => value.iter_mut().collect(),
+            Instruction::Phi(_, incoming, _) => {
+                incoming.iter_mut().map(|(_, value)| value).collect()
+            }
+            Instruction::Jump(_)
+            | Instruction::Loc(_, _)
+            | Instruction::Pop
+            | Instruction::Prologue(_, _)
+            | Instruction::Epilogue(_) => vec![],
+        }
+    }
+
+    /// Name of the variable written by this instruction
+    pub fn defined_var(&self) -> Option<&String> {
+        match self {
+            Instruction::VarDecl(name, _, _, _)
+            | Instruction::VarAssign(name, _, _, _)
+            | Instruction::Phi(name, _, _) => Some(name),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -36,14 +101,17 @@ pub struct Function {
     pub instructions: Vec<Instruction>,
     pub labels: Vec<Label>,
     pub name: String,
+    /// Types of the parameters, in order
+    pub params: Vec<TaggedType>,
 }
 
 impl Function {
-    pub fn new(name: String) -> Function {
+    pub fn new(name: String, params: Vec<TaggedType>) -> Function {
         Function {
             instructions: Vec::new(),
             labels: Vec::new(),
             name,
+            params,
         }
     }
 
@@ -51,7 +119,6 @@ impl Function {
         self.instructions.push(ins);
     }
 
-    #[allow(dead_code)]
     pub fn add_label(&mut self, label: Label) {
         self.labels.push(label);
     }
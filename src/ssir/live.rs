@@ -0,0 +1,76 @@
+use std::collections::BTreeSet;
+
+use super::{cfg::Cfg, ins::Instruction, tmp::TmpChild};
+
+/// Temporaries read by an instruction
+pub fn used_tmps(operands: Vec<&TmpChild>) -> impl Iterator<Item = usize> + '_ {
+    operands.into_iter().filter_map(|operand| match operand {
+        TmpChild::TmpRef(id, _, _) => Some(*id),
+        _ => None,
+    })
+}
+
+/// Temporary written by an instruction
+pub fn defined_tmp(ins: &Instruction) -> Option<usize> {
+    match ins {
+        Instruction::TmpNode(node, _, _) => Some(node.id()),
+        _ => None,
+    }
+}
+
+/// Temporaries whose value is still needed at the start and at the end of
+/// every block
+pub struct Liveness {
+    pub live_in: Vec<BTreeSet<usize>>,
+    pub live_out: Vec<BTreeSet<usize>>,
+}
+
+impl Liveness {
+    pub fn compute(cfg: &Cfg) -> Liveness {
+        let block_count = cfg.blocks.len();
+        let mut uses = vec![BTreeSet::new(); block_count];
+        let mut defs = vec![BTreeSet::new(); block_count];
+
+        for block in &cfg.blocks {
+            for ins in &block.instructions {
+                for tmp in used_tmps(ins.operands()) {
+                    if !defs[block.id].contains(&tmp) {
+                        uses[block.id].insert(tmp);
+                    }
+                }
+                if let Some(tmp) = defined_tmp(ins) {
+                    defs[block.id].insert(tmp);
+                }
+            }
+            for tmp in used_tmps(block.terminator.operands()) {
+                if !defs[block.id].contains(&tmp) {
+                    uses[block.id].insert(tmp);
+                }
+            }
+        }
+
+        let mut live_in = uses.clone();
+        let mut live_out = vec![BTreeSet::new(); block_count];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in cfg.blocks.iter().rev() {
+                let mut out = BTreeSet::new();
+                for succ in &block.succs {
+                    out.extend(live_in[*succ].iter().cloned());
+                }
+
+                let mut live = uses[block.id].clone();
+                live.extend(out.difference(&defs[block.id]).cloned());
+
+                if live.len() != live_in[block.id].len() || out.len() != live_out[block.id].len() {
+                    live_in[block.id] = live;
+                    live_out[block.id] = out;
+                    changed = true;
+                }
+            }
+        }
+
+        Liveness { live_in, live_out }
+    }
+}
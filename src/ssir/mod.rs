@@ -1,123 +1,110 @@
 use crate::{
-    ast::{BinaryOp, Node},
+    ast::{Binary, BinaryOp, For, If, LogicalOp, Node, Overflow, UnaryOp},
     reg::RegisterLabel,
     typechecker::{TaggedType, TypeKind},
 };
 
 use self::{
+    frame::Slot,
     ins::{Function, Instruction, Label},
-    tmp::{AssignTmp, BinaryTmp, GroupingTmp, LogicalTmp, TmpChild, TmpNode, UnaryTmp, ValueTmp},
+    tmp::{
+        AddressTmp, AssignTmp, BinaryTmp, Bound, CastTmp, IndexTmp, LoadTmp, TmpChild, TmpNode,
+        UnaryTmp, ValueTmp,
+    },
     var_table::{VarTable, Variable},
 };
 
+pub mod cfg;
+pub mod codegen;
+pub mod dom;
+pub mod frame;
 mod ins;
+pub mod live;
+pub mod opt;
+pub mod pass;
+pub mod regalloc;
 mod reveng;
+pub mod ssa;
+#[cfg(test)]
+mod tests;
 mod tmp;
-pub mod transform;
 mod var_table;
 
-fn print_instruction(ins: &Instruction) {
+pub fn format_instruction(ins: &Instruction) -> String {
     match ins {
-        Instruction::TmpNode(node, tipe, label) => {
-            print_node(node, Some(tipe), label);
+        Instruction::TmpNode(node, tipe, label) => format_node(node, tipe, label),
+        Instruction::VarDecl(name, TmpChild::None, tipe, slot) => {
+            format!("{} := uninit", format_var(name, tipe, slot))
         }
-        Instruction::VarDecl(name, node, size) => {
-            println!("\t{}{{{}}} := {}", size, name, node);
+        Instruction::VarDecl(name, node, tipe, slot) => {
+            format!("{} := {}", format_var(name, tipe, slot), node)
         }
-        Instruction::Pop => {
-            println!("\tpop");
+        Instruction::Pop => "pop".to_string(),
+        Instruction::Prologue(saved, frame_size) => {
+            format!("prologue {} frame {}", format_registers(saved), frame_size)
         }
-        Instruction::VarAssign(name, id, tipe) => {
-            println!("\t{}{{{}}} = {}", tipe, name, id);
+        Instruction::Epilogue(saved) => format!("epilogue {}", format_registers(saved)),
+        Instruction::Loc(line, column) => format!("loc {}:{}", line, column),
+        Instruction::VarAssign(name, id, tipe, slot) => {
+            format!("{} = {}", format_var(name, tipe, slot), id)
         }
-        Instruction::If(cond) => {
-            print!("\tif ");
-            print_node(cond, None, &None);
+        Instruction::Store(address, value, tipe) => {
+            format!("store {} [{}] = {}", tipe, address, value)
         }
-        Instruction::Jump(lc) => {
-            println!("\tjump LC{}", lc);
+        Instruction::Branch(cond, then_lc, else_lc) => {
+            format!("branch {} LC{} LC{}", cond, then_lc, else_lc)
         }
+        Instruction::Jump(lc) => format!("jump LC{}", lc),
+        Instruction::Ret(Some(value)) => format!("ret {}", value),
+        Instruction::Ret(None) => "ret".to_string(),
+        Instruction::Phi(name, incoming, tipe) => {
+            let incoming = incoming
+                .iter()
+                .map(|(block, value)| format!("BB{}: {}", block, value))
+                .collect::<Vec<String>>();
+            format!("{}{{{}}} = phi [{}]", tipe, name, incoming.join(", "))
+        }
+    }
+}
+
+fn format_var(name: &str, tipe: &TaggedType, slot: &Option<Slot>) -> String {
+    match slot {
+        Some(slot) => format!("{} -> {}{{{}}}", slot, tipe, name),
+        None => format!("{}{{{}}}", tipe, name),
     }
 }
 
-fn print_node(node: &TmpNode, tipe: Option<&TaggedType>, label: &Option<RegisterLabel>) {
+fn format_registers(registers: &[RegisterLabel]) -> String {
+    let registers = registers
+        .iter()
+        .map(|reg| reg.to_string())
+        .collect::<Vec<String>>();
+    format!("[{}]", registers.join(", "))
+}
+
+fn format_node(node: &TmpNode, tipe: &TaggedType, label: &Option<RegisterLabel>) -> String {
+    let dest = TmpChild::TmpRef(node.id(), tipe.clone(), label.clone());
     match node {
-        TmpNode::ValueTmp(value) => {
-            if let Some(tipe) = tipe {
-                println!(
-                    "\t{} -> {}{{tmp{}}} = {}",
-                    label.as_ref().unwrap(),
-                    tipe,
-                    value.id,
-                    value.value
-                )
-            } else {
-                println!("{}", value.value);
-            }
-        }
+        TmpNode::ValueTmp(value) => format!("{} = {}", dest, value.value),
+        TmpNode::BinaryTmp(binary) if binary.overflow != Overflow::Unchecked => format!(
+            "{} = {} {} {} {}",
+            dest, binary.overflow, binary.lhs, binary.op, binary.rhs
+        ),
         TmpNode::BinaryTmp(binary) => {
-            if let Some(tipe) = tipe {
-                println!(
-                    "\t{} -> {}{{tmp{}}} = {} {} {}",
-                    label.as_ref().unwrap(),
-                    tipe,
-                    binary.id,
-                    binary.lhs,
-                    binary.op,
-                    binary.rhs
-                )
-            } else {
-                println!("{} {} {}", binary.lhs, binary.op, binary.rhs);
-            }
-        }
-        TmpNode::LogicalTmp(logical) => {
-            if let Some(tipe) = tipe {
-                println!(
-                    "\t{} -> {}{{tmp{}}} = {} {} {}",
-                    label.as_ref().unwrap(),
-                    tipe,
-                    logical.id,
-                    logical.lhs,
-                    logical.op,
-                    logical.rhs
-                )
-            } else {
-                println!("{} {} {}", logical.lhs, logical.op, logical.rhs);
-            }
-        }
-        TmpNode::UnaryTmp(unary) => {
-            if let Some(tipe) = tipe {
-                println!(
-                    "\t{} -> {}{{tmp{}}} = {} {}",
-                    label.as_ref().unwrap(),
-                    tipe,
-                    unary.id,
-                    unary.op,
-                    unary.value
-                )
-            } else {
-                println!("{} {}", unary.op, unary.value);
-            }
-        }
-        TmpNode::AssignTmp(assign) => {
-            if let Some(tipe) = tipe {
-                println!(
-                    "\t{} -> {}{{tmp{}}} = {}",
-                    label.as_ref().unwrap(),
-                    tipe,
-                    assign.id,
-                    assign.value
-                );
-            } else {
-                println!("{}", assign.value);
-            }
-        }
-        TmpNode::GroupingTmp(grouping) => {
-            println!(
-                "\t{}{{tmp{}}} = ({})",
-                grouping.tipe, grouping.id, grouping.expr
-            );
+            format!("{} = {} {} {}", dest, binary.lhs, binary.op, binary.rhs)
         }
+        TmpNode::UnaryTmp(unary) => format!("{} = {} {}", dest, unary.op, unary.value),
+        TmpNode::AssignTmp(assign) => format!("{} = {}", dest, assign.value),
+        TmpNode::CastTmp(cast) => format!("{} = {} as {}", dest, cast.value, tipe),
+        TmpNode::AddressTmp(address) => match &address.slot {
+            Some(slot) => format!("{} = ADDR {} -> {{{}}}", dest, slot, address.var),
+            None => format!("{} = ADDR {{{}}}", dest, address.var),
+        },
+        TmpNode::LoadTmp(load) => format!("{} = LOAD [{}]", dest, load.address),
+        TmpNode::IndexTmp(index) => format!(
+            "{} = INDEX [{} + {} * {}] {} {}",
+            dest, index.base, index.index, index.stride, index.bound, index.length
+        ),
     }
 }
 
@@ -125,12 +112,12 @@ pub fn print_functions(functions: &Vec<Function>) {
     for func in functions {
         println!("func {}:", func.name);
         for ins in &func.instructions {
-            print_instruction(ins);
+            println!("\t{}", format_instruction(ins));
         }
         for label in &func.labels {
             println!("LC{}:", label.id);
             for ins in &label.instructions {
-                print_instruction(ins);
+                println!("\t{}", format_instruction(ins));
             }
         }
     }
@@ -139,12 +126,22 @@ pub fn print_functions(functions: &Vec<Function>) {
 pub fn get_child_type(child: &TmpChild) -> TaggedType {
     match child {
         TmpChild::Literal(_, tipe) => tipe.clone(),
-        TmpChild::LoadVar(_, tipe) => tipe.clone(),
+        TmpChild::LoadVar(_, tipe, _) => tipe.clone(),
+        TmpChild::Param(_, tipe) => tipe.clone(),
         TmpChild::TmpRef(_, tipe, _) => tipe.clone(),
         _ => panic!("No tagged type for tmp child"),
     }
 }
 
+/// Addresses are unsigned quadwords
+fn address_type() -> TaggedType {
+    TaggedType::new(8, TypeKind::Numeric, Some(false))
+}
+
+fn address_literal(value: usize) -> TmpChild {
+    TmpChild::Literal(value.to_string(), address_type())
+}
+
 /// Secondary stage intermediate representation
 pub struct SSir {
     tmp_count: usize,
@@ -153,8 +150,9 @@ pub struct SSir {
     label: Option<Label>,
     label_count: usize,
     variables: VarTable,
-    is_condition: bool,
-    condition_node: Option<TmpNode>,
+    /// Integer `+ - *` without an explicit overflow behaviour panic on
+    /// overflow
+    overflow_checks: bool,
 }
 
 impl SSir {
@@ -166,11 +164,14 @@ impl SSir {
             label: None,
             label_count: 0,
             variables: VarTable::new(),
-            is_condition: false,
-            condition_node: None,
+            overflow_checks: false,
         }
     }
 
+    pub fn overflow_checks(&mut self, enabled: bool) {
+        self.overflow_checks = enabled;
+    }
+
     pub fn generate(&mut self, decls: &mut Vec<Box<Node>>) {
         for decl in decls {
             self.process_node(decl);
@@ -181,17 +182,20 @@ impl SSir {
         self.functions
     }
 
-    fn add_func(&mut self, name: String) {
-        self.func = Some(Function::new(name));
+    fn add_func(&mut self, name: String, params: Vec<TaggedType>) {
+        self.func = Some(Function::new(name, params));
     }
 
-    fn swap_label(&mut self, label_count: usize) {
-        self.label = Some(Label::new(label_count))
+    /// Ends the current label (if any) and continues emitting into `label_id`
+    fn swap_label(&mut self, label_id: usize) {
+        self.end_label();
+        self.label = Some(Label::new(label_id))
     }
 
-    fn add_label(&mut self) {
+    /// Reserves a label id without starting it
+    fn reserve_label(&mut self) -> usize {
         self.label_count += 1;
-        self.label = Some(Label::new(self.label_count));
+        self.label_count
     }
 
     fn end_func(&mut self) {
@@ -200,7 +204,9 @@ impl SSir {
 
     fn end_label(&mut self) {
         if let Some(f) = &mut self.func {
-            f.labels.push(self.label.take().unwrap());
+            if let Some(label) = self.label.take() {
+                f.labels.push(label);
+            }
         } else {
             panic!("Not compiling a function.");
         }
@@ -222,11 +228,68 @@ impl SSir {
     }
 
     fn process_node(&mut self, node: &mut Box<Node>) -> TmpChild {
+        // Slices don't fit in a temporary, they are only ever copied between
+        // memory locations
+        if self.is_slice(node) {
+            self.slice_parts(node);
+            return TmpChild::None;
+        }
+
         match &mut **node {
             // Statements
             Node::Function(fun) => {
-                self.add_func(fun.name.clone());
+                // A slice is passed as its address and its length, like a
+                // struct of the two would be, except that it can be split
+                // between the last register and the stack
+                let params: Vec<TaggedType> = fun
+                    .args
+                    .iter()
+                    .flat_map(|arg| match arg.tipe.kind {
+                        TypeKind::Slice => vec![address_type(), address_type()],
+                        _ => vec![arg.tipe.clone()],
+                    })
+                    .collect();
+                self.add_func(fun.name.clone(), params.clone());
+
+                // Arguments are copied out of their registers into variables,
+                // all of them are read before the first one is stored
+                self.variables.add_scope();
+                let mut values = Vec::new();
+                for (index, tipe) in params.into_iter().enumerate() {
+                    let id = self.get_tmp_id();
+                    self.add_ins(Instruction::TmpNode(
+                        TmpNode::ValueTmp(ValueTmp::new(TmpChild::Param(index, tipe.clone()), id)),
+                        tipe.clone(),
+                        None,
+                    ));
+                    values.push(TmpChild::TmpRef(id, tipe, None));
+                }
+                let mut values = values.into_iter();
+                for arg in &fun.args {
+                    let value = match arg.tipe.kind {
+                        TypeKind::Slice => TmpChild::None,
+                        _ => values.next().unwrap(),
+                    };
+                    let name = self
+                        .variables
+                        .add_var(Variable::new(arg.name.clone(), arg.tipe.clone()));
+                    self.add_ins(Instruction::Loc(arg.name_loc.0, arg.name_loc.1));
+                    self.add_ins(Instruction::VarDecl(
+                        name.clone(),
+                        value,
+                        arg.tipe.clone(),
+                        None,
+                    ));
+
+                    if arg.tipe.kind == TypeKind::Slice {
+                        let address = self.variable_address(&name);
+                        let parts = (values.next().unwrap(), values.next().unwrap());
+                        self.store_slice(address, parts);
+                    }
+                }
+
                 self.process_node(&mut fun.body);
+                self.variables.end_scope();
                 self.end_label();
                 self.end_func();
 
@@ -235,16 +298,59 @@ impl SSir {
             Node::Block(bl) => {
                 self.variables.add_scope();
                 for stmt in &mut bl.statements {
+                    if let Some((line, column)) = stmt.loc() {
+                        self.add_ins(Instruction::Loc(line, column));
+                    }
                     self.process_node(stmt);
                 }
                 self.variables.end_scope();
                 TmpChild::None
             }
+            Node::VarDecl(vd)
+                if std::matches!(*vd.value, Node::ArrayLiteral(..) | Node::ArrayRepeat(_)) =>
+            {
+                // The array is reserved on the stack and its items are stored
+                // one by one
+                let name = self
+                    .variables
+                    .add_var(Variable::new(vd.name.clone(), vd.dtype.clone()));
+                self.add_ins(Instruction::VarDecl(
+                    name.clone(),
+                    TmpChild::None,
+                    vd.dtype.clone(),
+                    None,
+                ));
+
+                let base = self.variable_address(&name);
+                self.lower_array(base, &mut vd.value);
+                self.add_ins(Instruction::Pop);
+
+                TmpChild::None
+            }
+            Node::VarDecl(vd) if vd.dtype.kind == TypeKind::Slice => {
+                let parts = self.slice_parts(&mut vd.value);
+                let name = self
+                    .variables
+                    .add_var(Variable::new(vd.name.clone(), vd.dtype.clone()));
+                self.add_ins(Instruction::VarDecl(
+                    name.clone(),
+                    TmpChild::None,
+                    vd.dtype.clone(),
+                    None,
+                ));
+
+                let address = self.variable_address(&name);
+                self.store_slice(address, parts);
+                self.add_ins(Instruction::Pop);
+
+                TmpChild::None
+            }
             Node::VarDecl(vd) => {
                 let tmp = self.process_node(&mut vd.value);
-                self.add_ins(Instruction::VarDecl(vd.name.clone(), tmp, vd.dtype.clone()));
-                self.variables
+                let name = self
+                    .variables
                     .add_var(Variable::new(vd.name.clone(), vd.dtype.clone()));
+                self.add_ins(Instruction::VarDecl(name, tmp, vd.dtype.clone(), None));
                 self.add_ins(Instruction::Pop);
 
                 TmpChild::None
@@ -256,155 +362,95 @@ impl SSir {
                 TmpChild::None
             }
             Node::If(ief) => {
-                reveng::reverse_binary(&mut ief.condition);
-                self.is_condition = true;
-                self.process_node(&mut ief.condition);
-
-                // let mut else_loc: usize = 0;
-                // if let Some(_) = &ief.else_block {
-                // else_loc = self.label_count + 1;
-                // }
-
-                let cond_node = self.condition_node.take();
-                self.add_ins(Instruction::If(cond_node.unwrap()));
-                if let Some(ealse) = &mut ief.else_block {
-                    self.process_node(ealse);
-                }
-
-                let reserved = self.label_count + 2;
-                self.add_ins(Instruction::Jump(reserved));
-                self.add_label();
-
-                self.process_node(&mut ief.then_block);
-                self.add_ins(Instruction::Jump(reserved));
-                self.end_label();
-
-                self.swap_label(reserved);
-                
-                // if let Some(els) = &mut ief.else_block {
-                //     self.add_label();
-                //     self.process_node(els);
-                //     self.end_label();
-                // }
+                let join_label = self.reserve_label();
+                self.lower_if(ief, join_label);
 
+                self.swap_label(join_label);
                 self.add_ins(Instruction::Pop);
                 TmpChild::None
             }
-            Node::Binary(bi) => {
-                let is_condition = self.is_condition;
-                if is_condition {
-                    self.is_condition = false;
-                }
-
-                let lhs = self.process_node(&mut bi.lhs);
-                let rhs = self.process_node(&mut bi.rhs);
-
-                let lhs_type = get_child_type(&lhs);
-                let res_type = match bi.op {
-                    BinaryOp::Add | BinaryOp::Div | BinaryOp::Mul | BinaryOp::Sub => {
-                        lhs_type.clone()
-                    }
-                    _ => TaggedType::new(1, TypeKind::Bool, None),
+            Node::For(fr) => {
+                let (pointer, length) = match fr.length {
+                    Some(length) => (self.address_of(&mut fr.target), address_literal(length)),
+                    None => self.slice_parts(&mut fr.target),
                 };
+                self.lower_for(fr, pointer, length);
 
-                if is_condition {
-                    self.condition_node = Some(TmpNode::BinaryTmp(BinaryTmp::new(
-                        lhs,
-                        rhs,
-                        bi.op.clone(),
-                        0,
-                        lhs_type.clone(),
-                    )));
-                    return TmpChild::None;
-                } else {
-                    let id = self.get_tmp_id();
-                    self.add_ins(Instruction::TmpNode(
-                        TmpNode::BinaryTmp(BinaryTmp::new(
-                            lhs,
-                            rhs,
-                            bi.op.clone(),
-                            id,
-                            lhs_type.clone(),
-                        )),
-                        res_type.clone(),
-                        None,
-                    ));
+                self.add_ins(Instruction::Pop);
+                TmpChild::None
+            }
+            Node::Ret(ret) => {
+                let value = ret.value.as_mut().map(|value| self.process_node(value));
+                self.add_ins(Instruction::Ret(value));
 
-                    return TmpChild::TmpRef(id, res_type.clone(), None);
-                }
+                TmpChild::None
+            }
+            Node::Binary(bi) => {
+                let lhs = self.process_node(&mut bi.lhs);
+                self.lower_binary(bi, lhs)
             }
             Node::VarGet(name, _, _) => {
-                let id = self.get_tmp_id();
                 let var = self.variables.get_var(name.clone()).unwrap();
-                self.add_ins(Instruction::TmpNode(
-                    TmpNode::ValueTmp(ValueTmp::new(
-                        TmpChild::LoadVar(name.clone(), var.tagged_type.clone()),
-                        id,
-                    )),
-                    var.tagged_type.clone(),
-                    None,
-                ));
-
-                return TmpChild::TmpRef(id, var.tagged_type.clone(), None);
+                self.read_var(&var.ssir_name, var.tagged_type)
             }
             Node::Unary(un) => {
-                let is_condition = self.is_condition;
-                if is_condition {
-                    self.is_condition = false;
+                // `!(a < b)` is lowered as `a >= b` for everything but floats
+                if un.op == UnaryOp::Not && reveng::reverse_binary(&mut un.expr) {
+                    return self.process_node(&mut un.expr);
                 }
 
                 let value = self.process_node(&mut un.expr);
                 let ttype = get_child_type(&value);
-                if is_condition {
-                    let utmp = UnaryTmp::new(value, un.op.clone(), 0);
-                    self.condition_node = Some(TmpNode::UnaryTmp(utmp));
 
-                    return TmpChild::None;
-                } else {
-                    let id = self.get_tmp_id();
-                    let utmp = UnaryTmp::new(value, un.op.clone(), id);
-                    self.add_ins(Instruction::TmpNode(
-                        TmpNode::UnaryTmp(utmp),
-                        ttype.clone(),
-                        None,
-                    ));
+                let id = self.get_tmp_id();
+                let utmp = UnaryTmp::new(value, un.op.clone(), id);
+                self.add_ins(Instruction::TmpNode(
+                    TmpNode::UnaryTmp(utmp),
+                    ttype.clone(),
+                    None,
+                ));
 
-                    return TmpChild::TmpRef(id, ttype, None);
-                }
+                TmpChild::TmpRef(id, ttype, None)
             }
-            Node::Logical(lg) => {
-                let is_condition = self.is_condition;
-                if is_condition {
-                    self.is_condition = false;
-                }
-
-                let lhs = self.process_node(&mut lg.lhs);
-                let rhs = self.process_node(&mut lg.rhs);
-                let ttype = get_child_type(&lhs);
+            Node::Logical(_) => {
+                // Short-circuit through branches, the result lives in a hidden
+                // variable that is only set to true on the path where it holds
+                let id = self.get_tmp_id();
+                let name = format!("$sc{}", id);
+                let ttype = TaggedType::new(1, TypeKind::Bool, None);
+                let true_label = self.reserve_label();
+                let join_label = self.reserve_label();
+
+                self.add_ins(Instruction::VarDecl(
+                    name.clone(),
+                    TmpChild::Literal("false".to_string(), ttype.clone()),
+                    ttype.clone(),
+                    None,
+                ));
+                self.lower_condition(node, true_label, join_label);
 
-                if is_condition {
-                    let ltmp = LogicalTmp::new(lhs, rhs, lg.op.clone(), 0);
-                    self.condition_node = Some(TmpNode::LogicalTmp(ltmp));
+                self.swap_label(true_label);
+                self.add_ins(Instruction::VarAssign(
+                    name.clone(),
+                    TmpChild::Literal("true".to_string(), ttype.clone()),
+                    ttype.clone(),
+                    None,
+                ));
+                self.add_ins(Instruction::Jump(join_label));
 
-                    return TmpChild::None;
-                } else {
-                    let id = self.get_tmp_id();
-                    let ltmp = LogicalTmp::new(lhs, rhs, lg.op.clone(), id);
-                    self.add_ins(Instruction::TmpNode(
-                        TmpNode::LogicalTmp(ltmp),
-                        ttype.clone(),
-                        None,
-                    ));
+                self.swap_label(join_label);
+                self.add_ins(Instruction::TmpNode(
+                    TmpNode::ValueTmp(ValueTmp::new(
+                        TmpChild::LoadVar(name, ttype.clone(), None),
+                        id,
+                    )),
+                    ttype.clone(),
+                    None,
+                ));
 
-                    return TmpChild::TmpRef(id, ttype, None);
-                }
+                TmpChild::TmpRef(id, ttype, None)
             }
             Node::Assign(asi) => {
-                let is_condition = self.is_condition;
-                if is_condition {
-                    self.is_condition = false;
-                }
-
                 let value = self.process_node(&mut asi.value);
                 let ttype = get_child_type(&value);
 
@@ -417,39 +463,64 @@ impl SSir {
                     None,
                 ));
                 self.add_ins(Instruction::VarAssign(
-                    asi.name.clone(),
+                    self.ssir_name(&asi.name),
                     TmpChild::TmpRef(id, ttype.clone(), None),
                     ttype.clone(),
+                    None,
                 ));
 
-                return TmpChild::TmpRef(id, ttype, None);
+                TmpChild::TmpRef(id, ttype, None)
             }
             Node::Number(n, size, _, _) => TmpChild::Literal(n.clone(), size.clone()),
             Node::Float(f, size, _, _) => TmpChild::Literal(f.clone(), size.clone()),
             Node::BoolLiteral(b, size, _, _) => TmpChild::Literal(b.to_string(), size.clone()),
-            Node::Grouping(grouping) => {
-                let is_condition = self.is_condition;
-                if is_condition {
-                    self.is_condition = false;
-                }
+            Node::Grouping(grouping) => self.process_node(&mut grouping.expr),
+            Node::GetPtr(get_ptr) => self.address_of(&mut get_ptr.expr),
+            Node::Deref(deref) => {
+                let address = self.process_node(&mut deref.expr);
+                self.load(address, &deref.dtype)
+            }
+            Node::Index(index) => {
+                let dtype = index.dtype.clone();
+                let address = self.address_of(node);
+                self.load(address, &dtype)
+            }
+            Node::Len(len) => self.slice_parts(&mut len.expr).1,
+            Node::Store(store) if store.compound => {
+                let address = self.address_of(&mut store.target);
+                let tipe = match &*store.target {
+                    Node::Deref(deref) => deref.dtype.clone(),
+                    Node::Index(index) => index.dtype.clone(),
+                    _ => unreachable!(),
+                };
+                let current = self.load(address.clone(), &tipe);
+                let Node::Binary(bi) = &mut *store.value else {
+                    unreachable!()
+                };
+                let value = self.lower_binary(bi, current);
+                self.add_ins(Instruction::Store(address, value.clone(), tipe));
 
-                let expr = self.process_node(&mut grouping.expr);
-                let ttype = get_child_type(&expr);
-
-                if is_condition {
-                    self.condition_node = Some(TmpNode::GroupingTmp(GroupingTmp::new(
-                        expr,
-                        ttype.clone(),
-                        0,
-                    )));
-                    return TmpChild::None;
-                } else {
-                    if let TmpChild::TmpRef(ref_id, _, _) = expr {
-                        return TmpChild::TmpRef(ref_id, ttype, None);
-                    } else {
-                        return expr;
-                    }
-                }
+                value
+            }
+            Node::Store(store) => {
+                let value = self.process_node(&mut store.value);
+                let address = self.address_of(&mut store.target);
+                let ttype = get_child_type(&value);
+                self.add_ins(Instruction::Store(address, value.clone(), ttype));
+
+                value
+            }
+            Node::Cast(cast) => {
+                let value = self.process_node(&mut cast.expr);
+
+                let id = self.get_tmp_id();
+                self.add_ins(Instruction::TmpNode(
+                    TmpNode::CastTmp(CastTmp::new(value, id)),
+                    cast.dtype.clone(),
+                    None,
+                ));
+
+                TmpChild::TmpRef(id, cast.dtype.clone(), None)
             }
             _ => {
                 println!("{:#?}", node);
@@ -457,4 +528,434 @@ impl SSir {
             }
         }
     }
+
+    /// `lhs op rhs` with the left operand already lowered
+    fn lower_binary(&mut self, bi: &mut Binary, lhs: TmpChild) -> TmpChild {
+        let rhs = self.process_node(&mut bi.rhs);
+
+        let lhs_type = get_child_type(&lhs);
+        let res_type = match bi.op.is_comparison() {
+            true => TaggedType::new(1, TypeKind::Bool, None),
+            false => lhs_type.clone(),
+        };
+
+        let arithmetic = std::matches!(bi.op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul);
+        let overflow = match bi.overflow {
+            Overflow::Unchecked
+                if self.overflow_checks && arithmetic && lhs_type.kind == TypeKind::Numeric =>
+            {
+                Overflow::Checked
+            }
+            overflow => overflow,
+        };
+
+        let id = self.get_tmp_id();
+        self.add_ins(Instruction::TmpNode(
+            TmpNode::BinaryTmp(BinaryTmp::new(
+                lhs,
+                rhs,
+                bi.op.clone(),
+                id,
+                lhs_type,
+                overflow,
+            )),
+            res_type.clone(),
+            None,
+        ));
+
+        TmpChild::TmpRef(id, res_type, None)
+    }
+
+    /// Address of an lvalue, the typechecker only lets variables,
+    /// dereferenced pointers and array elements through
+    fn address_of(&mut self, node: &mut Box<Node>) -> TmpChild {
+        match &mut **node {
+            Node::VarGet(name, _, _) => self.variable_address(&self.ssir_name(name)),
+            Node::Deref(deref) => self.process_node(&mut deref.expr),
+            Node::Grouping(grouping) => self.address_of(&mut grouping.expr),
+            Node::Index(index) => {
+                let (base, length) = match index.length {
+                    Some(length) => (self.address_of(&mut index.expr), address_literal(length)),
+                    None => self.slice_parts(&mut index.expr),
+                };
+                let position = self.process_node(&mut index.index);
+                self.element_address(base, position, length, &index.dtype, Bound::Element)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_slice(&self, node: &Node) -> bool {
+        let variable_kind = |name: &String| {
+            self.variables
+                .get_var(name.clone())
+                .map(|var| var.tagged_type.kind)
+        };
+        let kind = match node {
+            Node::Slice(_) => return true,
+            Node::Grouping(grouping) => return self.is_slice(&grouping.expr),
+            Node::Store(store) => return self.is_slice(&store.target),
+            Node::VarGet(name, _, _) => variable_kind(name),
+            Node::Assign(asi) => variable_kind(&asi.name),
+            Node::Deref(deref) => Some(deref.dtype.kind),
+            Node::Index(index) => Some(index.dtype.kind),
+            _ => None,
+        };
+        kind == Some(TypeKind::Slice)
+    }
+
+    /// Address of the first element and number of elements of a slice. A
+    /// slice in memory is the two of them, in that order.
+    fn slice_parts(&mut self, node: &mut Box<Node>) -> (TmpChild, TmpChild) {
+        match &mut **node {
+            Node::Slice(slice) => {
+                let (base, length) = match slice.length {
+                    Some(length) => (self.address_of(&mut slice.expr), address_literal(length)),
+                    None => self.slice_parts(&mut slice.expr),
+                };
+                let start = slice.start.as_mut().map(|start| self.process_node(start));
+                let end = match &mut slice.end {
+                    Some(end) => {
+                        let end = self.process_node(end);
+                        self.element_address(
+                            base.clone(),
+                            end.clone(),
+                            length,
+                            &slice.dtype,
+                            Bound::RangeEnd,
+                        );
+                        end
+                    }
+                    None => length,
+                };
+                let Some(start) = start else {
+                    return (base, end);
+                };
+
+                let pointer = self.element_address(
+                    base,
+                    start.clone(),
+                    end.clone(),
+                    &slice.dtype,
+                    Bound::RangeStart,
+                );
+                let length = self.address_arithmetic(end, start, BinaryOp::Sub);
+                (pointer, length)
+            }
+            Node::Grouping(grouping) => self.slice_parts(&mut grouping.expr),
+            Node::Assign(asi) => {
+                let parts = self.slice_parts(&mut asi.value);
+                let address = self.variable_address(&self.ssir_name(&asi.name));
+                self.store_slice(address, parts.clone());
+                parts
+            }
+            Node::Store(store) => {
+                let parts = self.slice_parts(&mut store.value);
+                let address = self.address_of(&mut store.target);
+                self.store_slice(address, parts.clone());
+                parts
+            }
+            _ => {
+                let address = self.address_of(node);
+                let length_address = self.slice_length_address(address.clone());
+                let pointer = self.load(address, &address_type());
+                let length = self.load(length_address, &address_type());
+                (pointer, length)
+            }
+        }
+    }
+
+    fn store_slice(&mut self, address: TmpChild, (pointer, length): (TmpChild, TmpChild)) {
+        let length_address = self.slice_length_address(address.clone());
+        self.add_ins(Instruction::Store(address, pointer, address_type()));
+        self.add_ins(Instruction::Store(length_address, length, address_type()));
+    }
+
+    fn slice_length_address(&mut self, address: TmpChild) -> TmpChild {
+        let index = address_literal(1);
+        self.element_address(
+            address,
+            index,
+            address_literal(2),
+            &address_type(),
+            Bound::Element,
+        )
+    }
+
+    /// Loops through hidden variables holding the address of the elements,
+    /// their number and the position of the current one, which is read into
+    /// the loop variable at the start of every iteration
+    fn lower_for(&mut self, fr: &mut For, pointer: TmpChild, length: TmpChild) {
+        let id = self.get_tmp_id();
+        let pointer_var = format!("$ptr{}", id);
+        let length_var = format!("$len{}", id);
+        let counter_var = format!("$i{}", id);
+        for (name, value) in [
+            (&pointer_var, pointer),
+            (&length_var, length),
+            (&counter_var, address_literal(0)),
+        ] {
+            self.add_ins(Instruction::VarDecl(
+                name.clone(),
+                value,
+                address_type(),
+                None,
+            ));
+        }
+
+        let cond_label = self.reserve_label();
+        let body_label = self.reserve_label();
+        let done_label = self.reserve_label();
+        self.add_ins(Instruction::Jump(cond_label));
+
+        self.swap_label(cond_label);
+        let counter = self.read_var(&counter_var, address_type());
+        let length = self.read_var(&length_var, address_type());
+        let id = self.get_tmp_id();
+        let bool_type = TaggedType::new(1, TypeKind::Bool, None);
+        self.add_ins(Instruction::TmpNode(
+            TmpNode::BinaryTmp(BinaryTmp::new(
+                counter,
+                length,
+                BinaryOp::Less,
+                id,
+                address_type(),
+                Overflow::Unchecked,
+            )),
+            bool_type.clone(),
+            None,
+        ));
+        self.add_ins(Instruction::Branch(
+            TmpChild::TmpRef(id, bool_type, None),
+            body_label,
+            done_label,
+        ));
+
+        self.swap_label(body_label);
+        let counter = self.read_var(&counter_var, address_type());
+        let pointer = self.read_var(&pointer_var, address_type());
+        let offset =
+            self.address_arithmetic(counter, address_literal(fr.dtype.size), BinaryOp::Mul);
+        let address = self.address_arithmetic(pointer, offset, BinaryOp::Add);
+        let element = self.load(address, &fr.dtype);
+
+        self.variables.add_scope();
+        let name = self
+            .variables
+            .add_var(Variable::new(fr.name.clone(), fr.dtype.clone()));
+        self.add_ins(Instruction::Loc(fr.name_loc.0, fr.name_loc.1));
+        self.add_ins(Instruction::VarDecl(name, element, fr.dtype.clone(), None));
+        self.process_node(&mut fr.body);
+        self.variables.end_scope();
+
+        let counter = self.read_var(&counter_var, address_type());
+        let next = self.address_arithmetic(counter, address_literal(1), BinaryOp::Add);
+        self.add_ins(Instruction::VarAssign(
+            counter_var,
+            next,
+            address_type(),
+            None,
+        ));
+        self.add_ins(Instruction::Jump(cond_label));
+
+        self.swap_label(done_label);
+    }
+
+    fn address_arithmetic(&mut self, lhs: TmpChild, rhs: TmpChild, op: BinaryOp) -> TmpChild {
+        let id = self.get_tmp_id();
+        self.add_ins(Instruction::TmpNode(
+            TmpNode::BinaryTmp(BinaryTmp::new(
+                lhs,
+                rhs,
+                op,
+                id,
+                address_type(),
+                Overflow::Unchecked,
+            )),
+            address_type(),
+            None,
+        ));
+
+        TmpChild::TmpRef(id, address_type(), None)
+    }
+
+    /// The SSIR name of the visible variable `name`
+    fn ssir_name(&self, name: &str) -> String {
+        self.variables.get_var(name.to_string()).unwrap().ssir_name
+    }
+
+    fn read_var(&mut self, name: &str, tipe: TaggedType) -> TmpChild {
+        let id = self.get_tmp_id();
+        self.add_ins(Instruction::TmpNode(
+            TmpNode::ValueTmp(ValueTmp::new(
+                TmpChild::LoadVar(name.to_string(), tipe.clone(), None),
+                id,
+            )),
+            tipe.clone(),
+            None,
+        ));
+
+        TmpChild::TmpRef(id, tipe, None)
+    }
+
+    fn load(&mut self, address: TmpChild, tipe: &TaggedType) -> TmpChild {
+        let id = self.get_tmp_id();
+        self.add_ins(Instruction::TmpNode(
+            TmpNode::LoadTmp(LoadTmp::new(address, id)),
+            tipe.clone(),
+            None,
+        ));
+
+        TmpChild::TmpRef(id, tipe.clone(), None)
+    }
+
+    /// Stores the items of an array literal into the array at `base`, nested
+    /// literals fill the element they are the item of
+    fn lower_array(&mut self, base: TmpChild, literal: &mut Box<Node>) {
+        match &mut **literal {
+            Node::ArrayLiteral(items, element_type, _, _) => {
+                let length = address_literal(items.len());
+                for (position, item) in items.iter_mut().enumerate() {
+                    let address = self.element_address(
+                        base.clone(),
+                        address_literal(position),
+                        length.clone(),
+                        element_type,
+                        Bound::Element,
+                    );
+                    self.store_item(address, item, element_type);
+                }
+            }
+            Node::ArrayRepeat(repeat) => {
+                let is_array =
+                    std::matches!(*repeat.value, Node::ArrayLiteral(..) | Node::ArrayRepeat(_));
+                // A single value is only computed once
+                let value = match is_array {
+                    true => None,
+                    false => Some(self.process_node(&mut repeat.value)),
+                };
+                for position in 0..repeat.count {
+                    let address = self.element_address(
+                        base.clone(),
+                        address_literal(position),
+                        address_literal(repeat.count),
+                        &repeat.element,
+                        Bound::Element,
+                    );
+                    match &value {
+                        Some(value) => self.add_ins(Instruction::Store(
+                            address,
+                            value.clone(),
+                            repeat.element.clone(),
+                        )),
+                        None => self.lower_array(address, &mut repeat.value),
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn store_item(&mut self, address: TmpChild, item: &mut Box<Node>, tipe: &TaggedType) {
+        if std::matches!(**item, Node::ArrayLiteral(..) | Node::ArrayRepeat(_)) {
+            self.lower_array(address, item);
+        } else {
+            let value = self.process_node(item);
+            self.add_ins(Instruction::Store(address, value, tipe.clone()));
+        }
+    }
+
+    fn variable_address(&mut self, name: &str) -> TmpChild {
+        let id = self.get_tmp_id();
+        self.add_ins(Instruction::TmpNode(
+            TmpNode::AddressTmp(AddressTmp::new(name.to_string(), id)),
+            address_type(),
+            None,
+        ));
+
+        TmpChild::TmpRef(id, address_type(), None)
+    }
+
+    /// Address of the element at `index` of the array at `base`, checked
+    /// against the array's `length`
+    fn element_address(
+        &mut self,
+        base: TmpChild,
+        index: TmpChild,
+        length: TmpChild,
+        element_type: &TaggedType,
+        bound: Bound,
+    ) -> TmpChild {
+        let id = self.get_tmp_id();
+        self.add_ins(Instruction::TmpNode(
+            TmpNode::IndexTmp(IndexTmp::new(
+                base,
+                index,
+                length,
+                element_type.size,
+                bound,
+                id,
+            )),
+            address_type(),
+            None,
+        ));
+
+        TmpChild::TmpRef(id, address_type(), None)
+    }
+
+    /// Lowers an `if` statement, `else if` chains share the same `join_label`
+    fn lower_if(&mut self, ief: &mut If, join_label: usize) {
+        let then_label = self.reserve_label();
+        let else_label = match ief.else_block {
+            Some(_) => self.reserve_label(),
+            None => join_label,
+        };
+
+        self.lower_condition(&mut ief.condition, then_label, else_label);
+
+        self.swap_label(then_label);
+        self.process_node(&mut ief.then_block);
+        self.add_ins(Instruction::Jump(join_label));
+
+        if let Some(else_block) = &mut ief.else_block {
+            self.swap_label(else_label);
+            if let Node::If(elif) = &mut **else_block {
+                self.lower_if(elif, join_label);
+            } else {
+                self.process_node(else_block);
+                self.add_ins(Instruction::Jump(join_label));
+            }
+        }
+    }
+
+    /// Lowers a boolean expression into branches to `true_label` or `false_label`,
+    /// `&&` and `||` only evaluate their rhs when the lhs didn't decide the result
+    fn lower_condition(&mut self, node: &mut Box<Node>, true_label: usize, false_label: usize) {
+        match &mut **node {
+            Node::Logical(lg) => {
+                let rhs_label = self.reserve_label();
+                match lg.op {
+                    LogicalOp::And => self.lower_condition(&mut lg.lhs, rhs_label, false_label),
+                    LogicalOp::Or => self.lower_condition(&mut lg.lhs, true_label, rhs_label),
+                }
+
+                self.swap_label(rhs_label);
+                self.lower_condition(&mut lg.rhs, true_label, false_label);
+            }
+            Node::Unary(un) if un.op == UnaryOp::Not => {
+                self.lower_condition(&mut un.expr, false_label, true_label);
+            }
+            Node::Grouping(grouping) => {
+                self.lower_condition(&mut grouping.expr, true_label, false_label);
+            }
+            Node::BoolLiteral(value, _, _, _) => {
+                let target = if *value { true_label } else { false_label };
+                self.add_ins(Instruction::Jump(target));
+            }
+            _ => {
+                let value = self.process_node(node);
+                self.add_ins(Instruction::Branch(value, true_label, false_label));
+            }
+        }
+    }
 }
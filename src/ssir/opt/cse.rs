@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::BinaryOp,
+    ssir::{
+        cfg::Cfg,
+        ins::Instruction,
+        tmp::{TmpChild, TmpNode},
+    },
+    typechecker::TaggedType,
+};
+
+/// An expression that has already been computed in the current block
+struct Numbered {
+    id: usize,
+    /// Variables the expression loads, a new definition of one of them
+    /// invalidates the expression
+    vars: Vec<String>,
+}
+
+/// Local value numbering: a temporary computing the same expression as an
+/// earlier one in the same block is replaced by it, and temporaries that only
+/// copy another temporary are forwarded to it.
+///
+/// Variables that are a copy of another variable are forwarded as well, as
+/// long as both are defined once (which SSA guarantees). Returns whether
+/// anything was replaced.
+pub fn eliminate_common_subexpressions(cfg: &mut Cfg) -> bool {
+    let propagated = propagate_var_copies(cfg);
+    let address_taken = cfg.address_taken();
+
+    let mut tmp_replace: HashMap<usize, TmpChild> = HashMap::new();
+
+    for block in &mut cfg.blocks {
+        let mut numbered: HashMap<String, Numbered> = HashMap::new();
+
+        let instructions = std::mem::take(&mut block.instructions);
+        for mut ins in instructions {
+            for operand in ins.operands_mut() {
+                replace_tmp(operand, &tmp_replace);
+            }
+
+            match &ins {
+                Instruction::TmpNode(TmpNode::AssignTmp(assign), _, _)
+                    if std::matches!(assign.value, TmpChild::TmpRef(_, _, _)) =>
+                {
+                    tmp_replace.insert(assign.id, assign.value.clone());
+                    continue;
+                }
+                Instruction::TmpNode(node, tipe, _) => {
+                    if let Some(key) = node_key(node, tipe) {
+                        if let Some(existing) = numbered.get(&key) {
+                            tmp_replace.insert(
+                                node.id(),
+                                TmpChild::TmpRef(existing.id, tipe.clone(), None),
+                            );
+                            continue;
+                        }
+
+                        let vars = node
+                            .operands()
+                            .into_iter()
+                            .filter_map(|operand| match operand {
+                                TmpChild::LoadVar(name, _, _) => Some(name.clone()),
+                                _ => None,
+                            })
+                            .collect();
+                        numbered.insert(
+                            key,
+                            Numbered {
+                                id: node.id(),
+                                vars,
+                            },
+                        );
+                    }
+                }
+                _ => (),
+            }
+
+            if let Some(name) = ins.defined_var() {
+                numbered.retain(|_, expr| !expr.vars.contains(name));
+            }
+            if let Instruction::Store(_, _, _) = ins {
+                numbered.retain(|_, expr| !expr.vars.iter().any(|var| address_taken.contains(var)));
+            }
+
+            block.instructions.push(ins);
+        }
+    }
+
+    // Uses in blocks that were visited before the definition
+    for block in &mut cfg.blocks {
+        for ins in &mut block.instructions {
+            for operand in ins.operands_mut() {
+                replace_tmp(operand, &tmp_replace);
+            }
+        }
+        for operand in block.terminator.operands_mut() {
+            replace_tmp(operand, &tmp_replace);
+        }
+    }
+
+    propagated || !tmp_replace.is_empty()
+}
+
+fn replace_tmp(operand: &mut TmpChild, tmp_replace: &HashMap<usize, TmpChild>) {
+    while let TmpChild::TmpRef(id, tipe, _) = operand {
+        match tmp_replace.get(id) {
+            Some(TmpChild::TmpRef(new_id, _, _)) => {
+                *operand = TmpChild::TmpRef(*new_id, tipe.clone(), None);
+            }
+            Some(replacement) => {
+                *operand = replacement.clone();
+                return;
+            }
+            None => return,
+        }
+    }
+}
+
+fn operand_key(operand: &TmpChild) -> String {
+    match operand {
+        TmpChild::Literal(value, tipe) => format!("{}{{{}}}", tipe, value),
+        TmpChild::LoadVar(name, _, _) => format!("load {}", name),
+        TmpChild::Param(index, _) => format!("param {}", index),
+        TmpChild::TmpRef(id, _, _) => format!("tmp{}", id),
+        TmpChild::None => unreachable!(),
+    }
+}
+
+/// Textual key identifying the value computed by `node`, two nodes with the
+/// same key always compute the same value
+fn node_key(node: &TmpNode, tipe: &TaggedType) -> Option<String> {
+    let key = match node {
+        TmpNode::ValueTmp(value) => format!("{} {}", tipe, operand_key(&value.value)),
+        TmpNode::UnaryTmp(unary) => {
+            format!("{} {} {}", tipe, unary.op, operand_key(&unary.value))
+        }
+        TmpNode::BinaryTmp(binary) => {
+            let mut lhs = operand_key(&binary.lhs);
+            let mut rhs = operand_key(&binary.rhs);
+            let commutative = std::matches!(
+                binary.op,
+                BinaryOp::Add
+                    | BinaryOp::Mul
+                    | BinaryOp::BitAnd
+                    | BinaryOp::BitOr
+                    | BinaryOp::BitXor
+                    | BinaryOp::Equal
+                    | BinaryOp::NotEqual
+            );
+            if commutative && lhs > rhs {
+                std::mem::swap(&mut lhs, &mut rhs);
+            }
+            format!(
+                "{} {} {} {} {} {}",
+                tipe, binary.tipe, binary.overflow, binary.op, lhs, rhs
+            )
+        }
+        TmpNode::CastTmp(cast) => format!("{} as {}", operand_key(&cast.value), tipe),
+        // Stores through pointers aren't tracked, every load reads memory again
+        TmpNode::AssignTmp(_)
+        | TmpNode::AddressTmp(_)
+        | TmpNode::LoadTmp(_)
+        | TmpNode::IndexTmp(_) => return None,
+    };
+
+    Some(key)
+}
+
+/// `x := y` makes every load of `x` a load of `y`, when both variables
+/// have a single definition and no pointer can change them
+fn propagate_var_copies(cfg: &mut Cfg) -> bool {
+    let address_taken = cfg.address_taken();
+    let mut def_counts: HashMap<String, usize> = HashMap::new();
+    let mut loads: HashMap<usize, String> = HashMap::new();
+    for block in &cfg.blocks {
+        for ins in &block.instructions {
+            if let Some(name) = ins.defined_var() {
+                *def_counts.entry(name.clone()).or_insert(0) += 1;
+            }
+            if let Instruction::TmpNode(TmpNode::ValueTmp(value), _, _) = ins {
+                if let TmpChild::LoadVar(name, _, _) = &value.value {
+                    loads.insert(value.id, name.clone());
+                }
+            }
+        }
+    }
+
+    let mut copies: HashMap<String, String> = HashMap::new();
+    for block in &cfg.blocks {
+        for ins in &block.instructions {
+            if let Instruction::VarDecl(name, value, _, _) = ins {
+                let source = match value {
+                    TmpChild::LoadVar(source, _, _) => source,
+                    TmpChild::TmpRef(id, _, _) => match loads.get(id) {
+                        Some(source) => source,
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+
+                let single =
+                    |var: &String| def_counts.get(var) == Some(&1) && !address_taken.contains(var);
+                if single(name) && single(source) {
+                    copies.insert(name.clone(), source.clone());
+                }
+            }
+        }
+    }
+
+    if copies.is_empty() {
+        return false;
+    }
+
+    let mut propagated = false;
+    for block in &mut cfg.blocks {
+        let operands = block
+            .instructions
+            .iter_mut()
+            .flat_map(|ins| ins.operands_mut())
+            .chain(block.terminator.operands_mut());
+        for operand in operands {
+            if let TmpChild::LoadVar(name, _, _) = operand {
+                while let Some(source) = copies.get(name) {
+                    *name = source.clone();
+                    propagated = true;
+                }
+            }
+        }
+    }
+
+    propagated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssir::tests::lower_one;
+
+    /// How many times `name` is read after value numbering
+    fn loads_after_cse(source: &str, name: &str) -> usize {
+        let mut cfg = lower_one(source);
+        eliminate_common_subexpressions(&mut cfg);
+        cfg.blocks
+            .iter()
+            .flat_map(|block| {
+                block
+                    .instructions
+                    .iter()
+                    .flat_map(|ins| ins.operands())
+                    .chain(block.terminator.operands())
+            })
+            .filter(|operand| std::matches!(operand, TmpChild::LoadVar(var, _, _) if var == name))
+            .count()
+    }
+
+    #[test]
+    fn stores_invalidate_loads_of_variables_behind_pointers() {
+        let unchanged = loads_after_cse(
+            "func f(a: i32) -> i32 {
+                x := a
+                p := &x
+                b := x + 1
+                c := x + 1
+                ret b + c
+            }",
+            "x",
+        );
+        assert_eq!(unchanged, 1);
+
+        let stored = loads_after_cse(
+            "func f(a: i32) -> i32 {
+                x := a
+                p := &x
+                b := x + 1
+                *p = 5
+                c := x + 1
+                ret b + c
+            }",
+            "x",
+        );
+        assert_eq!(stored, 2);
+    }
+
+    #[test]
+    fn copies_are_only_propagated_when_both_sides_are_defined_once() {
+        let source = "func f(a: i32) -> i32 {
+            y := a
+            x := y
+            ret x
+        }";
+        assert_eq!(loads_after_cse(source, "x"), 0);
+        assert_eq!(loads_after_cse(source, "y"), 0);
+
+        let redefined = "func f(a: i32) -> i32 {
+            y := a
+            x := y
+            y = 5
+            ret x + y
+        }";
+        assert_eq!(loads_after_cse(redefined, "x"), 1);
+    }
+}
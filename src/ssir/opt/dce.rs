@@ -0,0 +1,270 @@
+use std::collections::HashSet;
+
+use crate::{
+    error,
+    ssir::{
+        cfg::{BasicBlock, Cfg},
+        dom,
+        ins::Instruction,
+        tmp::TmpChild,
+    },
+};
+
+fn reachable_blocks(cfg: &Cfg) -> Vec<bool> {
+    let mut reachable = vec![false; cfg.blocks.len()];
+    for block in dom::reverse_postorder(cfg) {
+        reachable[block] = true;
+    }
+    reachable
+}
+
+fn has_code(block: &BasicBlock) -> bool {
+    block
+        .instructions
+        .iter()
+        .any(|ins| !std::matches!(ins, Instruction::Loc(_, _) | Instruction::Pop))
+}
+
+/// Hidden variables made up by the compiler, and variables the user marked as
+/// intentionally unused
+fn is_silent_var(name: &str) -> bool {
+    name.starts_with('$') || name.starts_with('_')
+}
+
+/// Warns about variables that are never read and code that can never run.
+/// This has to look at the unoptimized graph, propagation removes the loads
+/// of constant variables and folded branches aren't something the user wrote.
+pub fn report_dead_code(cfg: &Cfg) {
+    find_dead_code(cfg, &mut error::warn);
+}
+
+fn find_dead_code(cfg: &Cfg, warn: &mut dyn FnMut(String)) {
+    let mut loaded = cfg.address_taken();
+    for block in &cfg.blocks {
+        let operands = block
+            .instructions
+            .iter()
+            .flat_map(|ins| ins.operands())
+            .chain(block.terminator.operands());
+        for operand in operands {
+            if let TmpChild::LoadVar(name, _, _) = operand {
+                loaded.insert(name.clone());
+            }
+        }
+    }
+
+    let mut reported = HashSet::new();
+    for block in &cfg.blocks {
+        let mut loc = None;
+        for ins in &block.instructions {
+            match ins {
+                Instruction::Loc(line, column) => loc = Some((*line, *column)),
+                Instruction::VarDecl(name, _, _, _) => {
+                    if is_silent_var(name) || loaded.contains(name) || !reported.insert(name) {
+                        continue;
+                    }
+
+                    // Shadowing variables are suffixed with `$<n>` in the SSIR
+                    let name = name.split('$').next().unwrap_or_default();
+                    match loc {
+                        Some((line, column)) => {
+                            warn(format!("{}:{} unused variable `{}`", line, column, name))
+                        }
+                        None => warn(format!("unused variable `{}`", name)),
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Only the first block of every unreachable region is reported
+    let reachable = reachable_blocks(cfg);
+    let mut tainted = vec![false; cfg.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in &cfg.blocks {
+            if reachable[block.id] || tainted[block.id] {
+                continue;
+            }
+
+            if has_code(block) || block.preds.iter().any(|pred| tainted[*pred]) {
+                tainted[block.id] = true;
+                changed = true;
+            }
+        }
+    }
+
+    for block in &cfg.blocks {
+        if reachable[block.id] || !has_code(block) {
+            continue;
+        }
+        if block.preds.iter().any(|pred| tainted[*pred]) {
+            continue;
+        }
+
+        let loc = block.instructions.iter().find_map(|ins| match ins {
+            Instruction::Loc(line, column) => Some((*line, *column)),
+            _ => None,
+        });
+        match loc {
+            Some((line, column)) => warn(format!("{}:{} unreachable code", line, column)),
+            None => warn("unreachable code".to_string()),
+        }
+    }
+}
+
+/// Removes blocks that can't be reached from the entry, then temporaries and
+/// variables that are never read, until nothing else becomes dead. Returns
+/// whether anything was removed.
+pub fn eliminate_dead_code(cfg: &mut Cfg) -> bool {
+    let mut eliminated = false;
+
+    let reachable = reachable_blocks(cfg);
+    if reachable.iter().any(|reachable| !reachable) {
+        cfg.retain_blocks(&reachable);
+        eliminated = true;
+    }
+
+    // Phis left with a single predecessor are plain definitions
+    for block in &mut cfg.blocks {
+        for ins in &mut block.instructions {
+            if let Instruction::Phi(name, incoming, tipe) = ins {
+                if incoming.len() == 1 {
+                    let value = incoming.pop().unwrap().1;
+                    *ins = Instruction::VarDecl(name.clone(), value, tipe.clone(), None);
+                    eliminated = true;
+                }
+            }
+        }
+    }
+
+    // Pointers may read these at any point
+    let address_taken = cfg.address_taken();
+    loop {
+        let mut used_tmps = HashSet::new();
+        let mut used_vars = address_taken.clone();
+        for block in &cfg.blocks {
+            let operands = block
+                .instructions
+                .iter()
+                .flat_map(|ins| ins.operands())
+                .chain(block.terminator.operands());
+            for operand in operands {
+                match operand {
+                    TmpChild::TmpRef(id, _, _) => {
+                        used_tmps.insert(*id);
+                    }
+                    TmpChild::LoadVar(name, _, _) => {
+                        used_vars.insert(name.clone());
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let mut removed = false;
+        for block in &mut cfg.blocks {
+            let before = block.instructions.len();
+            block.instructions.retain(|ins| match ins {
+                Instruction::TmpNode(node, _, _) => {
+                    used_tmps.contains(&node.id()) || node.may_trap()
+                }
+                Instruction::VarDecl(name, _, _, _)
+                | Instruction::VarAssign(name, _, _, _)
+                | Instruction::Phi(name, _, _) => used_vars.contains(name),
+                _ => true,
+            });
+            removed |= block.instructions.len() != before;
+        }
+
+        if !removed {
+            break;
+        }
+        eliminated = true;
+    }
+
+    eliminated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast::BinaryOp,
+        ssir::{tests::lower_one, tmp::TmpNode},
+    };
+
+    fn warnings(source: &str) -> Vec<String> {
+        let mut warnings = Vec::new();
+        find_dead_code(&lower_one(source), &mut |message| warnings.push(message));
+        warnings
+    }
+
+    #[test]
+    fn only_variables_the_user_named_are_reported_unused() {
+        let warnings = warnings(
+            "func f(a: i32, s: []i32) -> i32 {
+                b := a + 1
+                _c := 2
+                for v in s {
+                    a = v
+                }
+                ret a
+            }",
+        );
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].starts_with("2:"), "{:?}", warnings);
+        assert!(
+            warnings[0].ends_with("unused variable `b`"),
+            "{:?}",
+            warnings
+        );
+    }
+
+    #[test]
+    fn unreachable_regions_are_reported_once() {
+        let warnings = warnings(
+            "func f(a: i32) -> i32 {
+                ret 1
+                d := a
+                if d > 1 {
+                    ret 2
+                }
+                ret d
+            }",
+        );
+        assert_eq!(warnings, ["3:18 unreachable code"]);
+    }
+
+    #[test]
+    fn unused_values_and_unreachable_blocks_are_removed() {
+        let mut cfg = lower_one(
+            "func f(a: i32) -> i32 {
+                b := a * 3
+                if a > 0 {
+                    ret 1
+                    ret b
+                }
+                ret 0
+            }",
+        );
+        let before = cfg.blocks.len();
+        assert!(eliminate_dead_code(&mut cfg));
+        assert!(cfg.blocks.len() < before);
+        assert!(reachable_blocks(&cfg).iter().all(|reachable| *reachable));
+
+        for ins in cfg.blocks.iter().flat_map(|block| &block.instructions) {
+            assert!(
+                ins.defined_var().is_none_or(|name| name != "b"),
+                "{:?}",
+                ins
+            );
+            if let Instruction::TmpNode(TmpNode::BinaryTmp(binary), _, _) = ins {
+                assert_ne!(binary.op, BinaryOp::Mul, "{:?}", binary);
+            }
+        }
+        assert!(!eliminate_dead_code(&mut cfg));
+    }
+}
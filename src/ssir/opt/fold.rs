@@ -0,0 +1,628 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{BinaryOp, Overflow, UnaryOp},
+    error,
+    ssir::{
+        cfg::{Cfg, Terminator},
+        ins::Instruction,
+        tmp::{TmpChild, TmpNode},
+    },
+    typechecker::{TaggedType, TypeKind},
+};
+
+/// Folds operations on literals and propagates the results into their uses.
+/// Variables are only propagated when they have a single definition, which is
+/// always the case in SSA form, and their address isn't taken. Branches on a
+/// constant become jumps. Returns whether anything was folded.
+pub fn fold_constants(cfg: &mut Cfg) -> bool {
+    let mut var_consts: HashMap<String, TmpChild> = HashMap::new();
+    let mut tmp_consts: HashMap<usize, TmpChild> = HashMap::new();
+    let address_taken = cfg.address_taken();
+
+    let mut def_counts: HashMap<String, usize> = HashMap::new();
+    for block in &cfg.blocks {
+        for ins in &block.instructions {
+            if let Some(name) = ins.defined_var() {
+                *def_counts.entry(name.clone()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut folded = false;
+    let mut changed = true;
+    while changed {
+        // New constant variables need another round to reach their uses
+        changed = false;
+        let mut round = false;
+
+        for block in &mut cfg.blocks {
+            let instructions = std::mem::take(&mut block.instructions);
+            for mut ins in instructions {
+                for operand in ins.operands_mut() {
+                    round |= substitute(operand, &var_consts, &tmp_consts);
+                }
+
+                match &ins {
+                    Instruction::TmpNode(node, tipe, _) => {
+                        // The warnings come from `report_constant_errors`
+                        if let Some(literal) = fold_node(node, tipe, &mut |_| ()) {
+                            tmp_consts.insert(node.id(), literal);
+                            round = true;
+                            continue;
+                        }
+                    }
+                    Instruction::VarDecl(name, value, _, _)
+                        if std::matches!(value, TmpChild::Literal(_, _))
+                            && def_counts.get(name) == Some(&1)
+                            && !address_taken.contains(name)
+                            && !var_consts.contains_key(name) =>
+                    {
+                        var_consts.insert(name.clone(), value.clone());
+                        changed = true;
+                    }
+                    Instruction::Phi(name, incoming, tipe) => {
+                        if let Some(value) = same_incoming(incoming) {
+                            ins = Instruction::VarDecl(name.clone(), value, tipe.clone(), None);
+                            round = true;
+                        }
+                    }
+                    _ => (),
+                }
+
+                block.instructions.push(ins);
+            }
+
+            for operand in block.terminator.operands_mut() {
+                round |= substitute(operand, &var_consts, &tmp_consts);
+            }
+        }
+
+        round |= fold_branches(cfg);
+        changed |= round;
+        folded |= round;
+    }
+
+    folded
+}
+
+fn substitute(
+    operand: &mut TmpChild,
+    var_consts: &HashMap<String, TmpChild>,
+    tmp_consts: &HashMap<usize, TmpChild>,
+) -> bool {
+    let constant = match operand {
+        TmpChild::LoadVar(name, _, _) => var_consts.get(name),
+        TmpChild::TmpRef(id, _, _) => tmp_consts.get(id),
+        _ => None,
+    };
+
+    match constant {
+        Some(constant) => {
+            *operand = constant.clone();
+            true
+        }
+        None => false,
+    }
+}
+
+/// A phi whose incoming values are all the same literal is just that literal
+fn same_incoming(incoming: &[(usize, TmpChild)]) -> Option<TmpChild> {
+    let mut literal: Option<&String> = None;
+    let mut tipe = None;
+    for (_, value) in incoming {
+        match value {
+            TmpChild::Literal(value, value_type) => {
+                if literal.is_some() && literal != Some(value) {
+                    return None;
+                }
+                literal = Some(value);
+                tipe = Some(value_type);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(TmpChild::Literal(literal?.clone(), tipe?.clone()))
+}
+
+/// Turns branches on a literal condition into jumps, and drops the phi
+/// operands of the edges that disappeared
+fn fold_branches(cfg: &mut Cfg) -> bool {
+    let mut changed = false;
+    for id in 0..cfg.blocks.len() {
+        let (taken, dropped) = match &cfg.blocks[id].terminator {
+            Terminator::Branch(TmpChild::Literal(value, _), then_block, else_block) => {
+                if value == "true" {
+                    (*then_block, *else_block)
+                } else {
+                    (*else_block, *then_block)
+                }
+            }
+            _ => continue,
+        };
+
+        cfg.blocks[id].terminator = Terminator::Jump(taken);
+        changed = true;
+
+        if taken == dropped {
+            continue;
+        }
+
+        for ins in &mut cfg.blocks[dropped].instructions {
+            if let Instruction::Phi(_, incoming, _) = ins {
+                incoming.retain(|(pred, _)| *pred != id);
+            }
+        }
+    }
+
+    if changed {
+        cfg.compute_edges();
+    }
+    changed
+}
+
+/// Warns about constant expressions that overflow or divide by zero. Like
+/// `dce::report_dead_code` this looks at the unoptimized graph, so that the
+/// warnings don't depend on the optimization level and only cover the
+/// expressions written with literals, constant variables aren't propagated.
+pub fn report_constant_errors(cfg: &Cfg) {
+    let mut tmp_consts: HashMap<usize, TmpChild> = HashMap::new();
+    let var_consts = HashMap::new();
+    let (mut line, mut column) = (0, 0);
+    for block in &cfg.blocks {
+        for ins in &block.instructions {
+            match ins {
+                Instruction::Loc(l, c) => (line, column) = (*l, *c),
+                Instruction::TmpNode(node, tipe, _) => {
+                    let mut node = node.clone();
+                    for operand in node.operands_mut() {
+                        substitute(operand, &var_consts, &tmp_consts);
+                    }
+
+                    let mut warn =
+                        |message: String| error::warn(format!("{}:{} {}", line, column, message));
+                    if let Some(literal) = fold_node(&node, tipe, &mut warn) {
+                        tmp_consts.insert(node.id(), literal);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn fold_node(node: &TmpNode, tipe: &TaggedType, warn: &mut dyn FnMut(String)) -> Option<TmpChild> {
+    match node {
+        TmpNode::ValueTmp(value) => as_literal(&value.value),
+        TmpNode::AssignTmp(assign) => as_literal(&assign.value),
+        TmpNode::CastTmp(cast) => match &cast.value {
+            TmpChild::Literal(value, value_type) => Some(TmpChild::Literal(
+                cast_literal(value, value_type, tipe)?,
+                tipe.clone(),
+            )),
+            _ => None,
+        },
+        TmpNode::AddressTmp(_) | TmpNode::LoadTmp(_) | TmpNode::IndexTmp(_) => None,
+        TmpNode::UnaryTmp(unary) => {
+            let (value, value_type) = match &unary.value {
+                TmpChild::Literal(value, value_type) => (value, value_type),
+                _ => return None,
+            };
+
+            let folded = match (&unary.op, value_type.kind) {
+                (UnaryOp::Not, TypeKind::Bool) => (value != "true").to_string(),
+                (UnaryOp::Negate, TypeKind::Numeric) => {
+                    let result = fold_int(
+                        -value.parse::<i128>().ok()?,
+                        value_type,
+                        || format!("-{}", value),
+                        warn,
+                    );
+                    result.to_string()
+                }
+                (UnaryOp::BitNot, TypeKind::Numeric) => {
+                    wrap_int(!value.parse::<i128>().ok()?, value_type).to_string()
+                }
+                (UnaryOp::Negate, TypeKind::Float) => {
+                    format_float(-value.parse::<f64>().ok()?, value_type)
+                }
+                _ => return None,
+            };
+
+            Some(TmpChild::Literal(folded, tipe.clone()))
+        }
+        TmpNode::BinaryTmp(binary) => {
+            let (lhs, rhs) = match (&binary.lhs, &binary.rhs) {
+                (TmpChild::Literal(lhs, _), TmpChild::Literal(rhs, _)) => (lhs, rhs),
+                _ => return None,
+            };
+
+            let folded = match binary.tipe.kind {
+                TypeKind::Numeric => {
+                    fold_int_binary(&binary.op, &binary.overflow, lhs, rhs, &binary.tipe, warn)?
+                }
+                TypeKind::Float => fold_float_binary(&binary.op, lhs, rhs, &binary.tipe)?,
+                TypeKind::Bool => match binary.op {
+                    BinaryOp::Equal => (lhs == rhs).to_string(),
+                    BinaryOp::NotEqual => (lhs != rhs).to_string(),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+
+            Some(TmpChild::Literal(folded, tipe.clone()))
+        }
+    }
+}
+
+fn as_literal(child: &TmpChild) -> Option<TmpChild> {
+    match child {
+        TmpChild::Literal(_, _) => Some(child.clone()),
+        _ => None,
+    }
+}
+
+fn int_bounds(tipe: &TaggedType) -> (i128, i128) {
+    let bits = tipe.size as u32 * 8;
+    if tipe.signed.unwrap_or(false) {
+        (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+    } else {
+        (0, (1i128 << bits) - 1)
+    }
+}
+
+/// Wraps `value` to the width of `tipe` the same way the machine would,
+/// warning about the overflow if it doesn't fit
+fn fold_int<F: Fn() -> String>(
+    value: i128,
+    tipe: &TaggedType,
+    expr: F,
+    warn: &mut dyn FnMut(String),
+) -> i128 {
+    let (min, max) = int_bounds(tipe);
+    if value >= min && value <= max {
+        return value;
+    }
+
+    warn(format!(
+        "constant expression `{}` overflows {}",
+        expr(),
+        tipe
+    ));
+    wrap_int(value, tipe)
+}
+
+/// Keeps the low bits of `value` that fit in `tipe`, like a truncating move
+fn wrap_int(value: i128, tipe: &TaggedType) -> i128 {
+    let (min, max) = int_bounds(tipe);
+    let modulus = max - min + 1;
+    (value - min).rem_euclid(modulus) + min
+}
+
+/// Integer and boolean literals as a number
+fn int_value(value: &str) -> Option<i128> {
+    match value {
+        "true" => Some(1),
+        "false" => Some(0),
+        value => value.parse::<i128>().ok(),
+    }
+}
+
+/// Converts a literal to another type the same way the generated code does.
+/// Integers are truncated or extended. Floats are truncated towards zero
+/// through a 32 bit integer, or a 64 bit one for 64 bit and `u32` targets,
+/// and become the minimum of that width when they don't fit. `u64` takes the
+/// values from 2^63 on through an offset of 2^63.
+pub fn cast_literal(value: &str, from: &TaggedType, to: &TaggedType) -> Option<String> {
+    let result = match (from.kind, to.kind) {
+        (TypeKind::Float, TypeKind::Float) => format_float(value.parse::<f64>().ok()?, to),
+        (TypeKind::Float, _) => {
+            let value = value.parse::<f64>().ok()?.trunc();
+            let bits = if to.size == 8 || (to.size == 4 && !to.signed.unwrap_or(false)) {
+                64
+            } else {
+                32
+            };
+            let limit = 1i128 << (bits - 1);
+            let u64_target = to.size == 8 && !to.signed.unwrap_or(false);
+            let int = if u64_target && value >= limit as f64 {
+                // Still out of range after the offset, then the top bit that
+                // was added back cancels the one of the minimum
+                if value >= (limit * 2) as f64 {
+                    0
+                } else {
+                    value as i128
+                }
+            } else if value.is_nan() || value < -limit as f64 || value >= limit as f64 {
+                -limit
+            } else {
+                value as i128
+            };
+            wrap_int(int, to).to_string()
+        }
+        (_, TypeKind::Float) => {
+            let int = int_value(value)?;
+            if to.size == 4 {
+                format!("{:?}", int as f32)
+            } else {
+                format!("{:?}", int as f64)
+            }
+        }
+        _ => wrap_int(int_value(value)?, to).to_string(),
+    };
+
+    Some(result)
+}
+
+/// Bits of a shift count the machine looks at
+pub fn shift_mask(tipe: &TaggedType) -> i128 {
+    if tipe.size == 8 {
+        63
+    } else {
+        31
+    }
+}
+
+/// Folds `+`, `-` and `*` the way the overflow mode asks for. Checked
+/// arithmetic that overflows is left to panic at runtime.
+fn fold_overflowing(
+    op: &BinaryOp,
+    overflow: &Overflow,
+    l: i128,
+    r: i128,
+    tipe: &TaggedType,
+    expr: impl Fn() -> String,
+    warn: &mut dyn FnMut(String),
+) -> Option<i128> {
+    let exact = match op {
+        BinaryOp::Add => l.checked_add(r),
+        BinaryOp::Sub => l.checked_sub(r),
+        _ => l.checked_mul(r),
+    };
+    let (min, max) = int_bounds(tipe);
+    if let Some(value) = exact.filter(|value| *value >= min && *value <= max) {
+        return Some(value);
+    }
+
+    // Only a product of two 64 bit values can overflow an i128, the low
+    // bits are the same either way
+    let wrapped = match op {
+        BinaryOp::Add => l.wrapping_add(r),
+        BinaryOp::Sub => l.wrapping_sub(r),
+        _ => l.wrapping_mul(r),
+    };
+    let result = match overflow {
+        Overflow::Unchecked => fold_int(wrapped, tipe, expr, warn),
+        Overflow::Wrapping => wrap_int(wrapped, tipe),
+        Overflow::Saturating => {
+            let negative = exact.map_or((l < 0) != (r < 0), |value| value < 0);
+            if negative {
+                min
+            } else {
+                max
+            }
+        }
+        Overflow::Checked => {
+            warn(format!(
+                "constant expression `{}` overflows {} and will panic",
+                expr(),
+                tipe
+            ));
+            return None;
+        }
+    };
+
+    Some(result)
+}
+
+fn fold_int_binary(
+    op: &BinaryOp,
+    overflow: &Overflow,
+    lhs: &str,
+    rhs: &str,
+    tipe: &TaggedType,
+    warn: &mut dyn FnMut(String),
+) -> Option<String> {
+    let l = lhs.parse::<i128>().ok()?;
+    let r = rhs.parse::<i128>().ok()?;
+    let expr = || format!("{} {} {}", lhs, op, rhs);
+
+    // Doublewords and quadwords are divided at their own width, where the
+    // quotient of the minimum and -1 doesn't fit and `idiv` traps
+    let traps = tipe.size >= 4 && l == int_bounds(tipe).0 && r == -1;
+    if traps && std::matches!(op, BinaryOp::Div | BinaryOp::Rem) {
+        warn(format!(
+            "constant expression `{}` overflows {} and will trap",
+            expr(),
+            tipe
+        ));
+        return None;
+    }
+
+    let result = match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => {
+            fold_overflowing(op, overflow, l, r, tipe, expr, warn)?.to_string()
+        }
+        BinaryOp::Div => {
+            if r == 0 {
+                warn(format!("constant expression `{}` divides by zero", expr()));
+                return None;
+            }
+            fold_int(l / r, tipe, expr, warn).to_string()
+        }
+        BinaryOp::Rem => {
+            if r == 0 {
+                warn(format!("constant expression `{}` divides by zero", expr()));
+                return None;
+            }
+            fold_int(l % r, tipe, expr, warn).to_string()
+        }
+        BinaryOp::BitAnd => (l & r).to_string(),
+        BinaryOp::BitOr => (l | r).to_string(),
+        BinaryOp::BitXor => (l ^ r).to_string(),
+        // The count is masked like the shift instructions do, and the bits
+        // shifted out are dropped without a warning
+        BinaryOp::Shl => wrap_int(l << (r & shift_mask(tipe)), tipe).to_string(),
+        BinaryOp::Shr => (l >> (r & shift_mask(tipe))).to_string(),
+        BinaryOp::Greater => (l > r).to_string(),
+        BinaryOp::GreaterEq => (l >= r).to_string(),
+        BinaryOp::Less => (l < r).to_string(),
+        BinaryOp::LessEq => (l <= r).to_string(),
+        BinaryOp::Equal => (l == r).to_string(),
+        BinaryOp::NotEqual => (l != r).to_string(),
+    };
+
+    Some(result)
+}
+
+fn format_float(value: f64, tipe: &TaggedType) -> String {
+    if tipe.size == 4 {
+        format!("{:?}", value as f32)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn fold_float_binary(op: &BinaryOp, lhs: &str, rhs: &str, tipe: &TaggedType) -> Option<String> {
+    let mut l = lhs.parse::<f64>().ok()?;
+    let mut r = rhs.parse::<f64>().ok()?;
+    if tipe.size == 4 {
+        l = l as f32 as f64;
+        r = r as f32 as f64;
+    }
+
+    let result = match op {
+        BinaryOp::Add => format_float(l + r, tipe),
+        BinaryOp::Sub => format_float(l - r, tipe),
+        BinaryOp::Mul => format_float(l * r, tipe),
+        BinaryOp::Div => format_float(l / r, tipe),
+        BinaryOp::Rem
+        | BinaryOp::BitAnd
+        | BinaryOp::BitOr
+        | BinaryOp::BitXor
+        | BinaryOp::Shl
+        | BinaryOp::Shr => return None,
+        BinaryOp::Greater => (l > r).to_string(),
+        BinaryOp::GreaterEq => (l >= r).to_string(),
+        BinaryOp::Less => (l < r).to_string(),
+        BinaryOp::LessEq => (l <= r).to_string(),
+        BinaryOp::Equal => (l == r).to_string(),
+        BinaryOp::NotEqual => (l != r).to_string(),
+    };
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssir::tmp::BinaryTmp;
+
+    fn int(size: usize, signed: bool) -> TaggedType {
+        TaggedType::new(size, TypeKind::Numeric, Some(signed))
+    }
+
+    /// Folds `lhs op rhs` and collects the warnings
+    fn fold(
+        lhs: &str,
+        op: BinaryOp,
+        rhs: &str,
+        tipe: &TaggedType,
+        overflow: Overflow,
+    ) -> (Option<String>, Vec<String>) {
+        let literal = |value: &str| TmpChild::Literal(value.to_string(), tipe.clone());
+        let node = TmpNode::BinaryTmp(BinaryTmp::new(
+            literal(lhs),
+            literal(rhs),
+            op,
+            1,
+            tipe.clone(),
+            overflow,
+        ));
+
+        let mut warnings = Vec::new();
+        let folded = fold_node(&node, tipe, &mut |message| warnings.push(message));
+        let value = folded.map(|child| match child {
+            TmpChild::Literal(value, _) => value,
+            _ => unreachable!(),
+        });
+        (value, warnings)
+    }
+
+    #[test]
+    fn overflow_wraps_to_the_width_and_signedness() {
+        let cases = [
+            ("100", BinaryOp::Add, "100", int(1, true), "-56"),
+            ("100", BinaryOp::Add, "200", int(1, false), "44"),
+            ("0", BinaryOp::Sub, "1", int(2, false), "65535"),
+            ("65536", BinaryOp::Mul, "65536", int(4, true), "0"),
+            ("-128", BinaryOp::Div, "-1", int(1, true), "-128"),
+        ];
+        for (lhs, op, rhs, tipe, expected) in cases {
+            let (value, warnings) = fold(lhs, op, rhs, &tipe, Overflow::Unchecked);
+            assert_eq!(value.as_deref(), Some(expected), "{} {}", lhs, rhs);
+            assert_eq!(warnings.len(), 1, "{} {}", lhs, rhs);
+        }
+
+        let (value, warnings) = fold(
+            "100",
+            BinaryOp::Add,
+            "27",
+            &int(1, true),
+            Overflow::Unchecked,
+        );
+        assert_eq!(value.as_deref(), Some("127"));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn overflow_modes_fold_silently_except_checked() {
+        let u8_type = int(1, false);
+        let i8_type = int(1, true);
+        let cases = [
+            (Overflow::Wrapping, "130", &u8_type, Some("4")),
+            (Overflow::Saturating, "130", &u8_type, Some("255")),
+            (Overflow::Saturating, "100", &i8_type, Some("127")),
+            (Overflow::Checked, "100", &i8_type, None),
+        ];
+        for (overflow, operand, tipe, expected) in cases {
+            let checked = overflow == Overflow::Checked;
+            let (value, warnings) = fold(operand, BinaryOp::Add, operand, tipe, overflow);
+            assert_eq!(value.as_deref(), expected);
+            assert_eq!(warnings.len(), checked as usize);
+        }
+    }
+
+    #[test]
+    fn division_by_zero_is_left_to_the_machine() {
+        let (value, warnings) = fold("7", BinaryOp::Rem, "0", &int(8, false), Overflow::Unchecked);
+        assert_eq!(value, None);
+        assert!(warnings[0].contains("divides by zero"));
+    }
+
+    #[test]
+    fn dividing_the_minimum_by_minus_one_is_left_to_trap() {
+        let cases = [
+            ("-2147483648", int(4, true)),
+            ("-9223372036854775808", int(8, true)),
+        ];
+        for (lhs, tipe) in cases {
+            for op in [BinaryOp::Div, BinaryOp::Rem] {
+                let (value, warnings) = fold(lhs, op, "-1", &tipe, Overflow::Unchecked);
+                assert_eq!(value, None, "{}", tipe);
+                assert!(warnings[0].contains("will trap"), "{:?}", warnings);
+            }
+        }
+
+        let (value, warnings) = fold(
+            "-2147483647",
+            BinaryOp::Div,
+            "-1",
+            &int(4, true),
+            Overflow::Unchecked,
+        );
+        assert_eq!(value.as_deref(), Some("2147483647"));
+        assert!(warnings.is_empty());
+    }
+}
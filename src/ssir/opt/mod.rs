@@ -0,0 +1,3 @@
+pub mod cse;
+pub mod dce;
+pub mod fold;
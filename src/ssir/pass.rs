@@ -0,0 +1,304 @@
+use std::time::{Duration, Instant};
+
+use crate::error;
+
+use super::{
+    cfg::Cfg,
+    opt::{cse, dce, fold},
+    print_functions, ssa,
+};
+
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub enum OptLevel {
+    /// No optimizations, the code is lowered as written
+    O0,
+    /// Every pass runs once
+    O1,
+    /// The optimizations are repeated until none of them changes anything
+    O2,
+}
+
+impl OptLevel {
+    pub fn from_flag(flag: &str) -> Option<OptLevel> {
+        match flag {
+            "O0" => Some(OptLevel::O0),
+            "O1" => Some(OptLevel::O1),
+            "O2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+}
+
+struct Pass {
+    name: &'static str,
+    /// Lowest level the pass is enabled at by default
+    level: OptLevel,
+    /// Returns whether the pass changed the function
+    run: fn(&mut Cfg) -> bool,
+}
+
+fn construct_ssa(cfg: &mut Cfg) -> bool {
+    ssa::construct(cfg);
+    true
+}
+
+fn destruct_ssa(cfg: &mut Cfg) -> bool {
+    ssa::destruct(cfg);
+    true
+}
+
+const SSA: Pass = Pass {
+    name: "ssa",
+    level: OptLevel::O1,
+    run: construct_ssa,
+};
+
+/// Always runs after `ssa`, the rest of the pipeline can't handle phis
+const OUT_OF_SSA: Pass = Pass {
+    name: "out-of-ssa",
+    level: OptLevel::O1,
+    run: destruct_ssa,
+};
+
+const OPTIMIZATIONS: [Pass; 3] = [
+    Pass {
+        name: "fold",
+        level: OptLevel::O1,
+        run: fold::fold_constants,
+    },
+    Pass {
+        name: "cse",
+        level: OptLevel::O1,
+        run: cse::eliminate_common_subexpressions,
+    },
+    Pass {
+        name: "dce",
+        level: OptLevel::O1,
+        run: dce::eliminate_dead_code,
+    },
+];
+
+/// Upper bound on the -O2 rounds, in case two passes keep undoing each other
+const MAX_ROUNDS: usize = 16;
+
+struct PassTiming {
+    name: &'static str,
+    runs: usize,
+    total: Duration,
+}
+
+/// Runs the SSIR passes selected by the optimization level over every
+/// function
+pub struct PassManager {
+    level: OptLevel,
+    enabled: Vec<&'static str>,
+    print_after: Vec<&'static str>,
+    time_passes: bool,
+    timings: Vec<PassTiming>,
+}
+
+impl PassManager {
+    pub fn new(level: OptLevel) -> PassManager {
+        let enabled = std::iter::once(&SSA)
+            .chain(OPTIMIZATIONS.iter())
+            .filter(|pass| level >= pass.level)
+            .map(|pass| pass.name)
+            .collect();
+
+        PassManager {
+            level,
+            enabled,
+            print_after: Vec::new(),
+            time_passes: false,
+            timings: Vec::new(),
+        }
+    }
+
+    fn pass_name(name: &str) -> &'static str {
+        std::iter::once(&SSA)
+            .chain(std::iter::once(&OUT_OF_SSA))
+            .chain(OPTIMIZATIONS.iter())
+            .map(|pass| pass.name)
+            .find(|pass| *pass == name)
+            .unwrap_or_else(|| error::panic(format!("Unknown pass '{}'", name)))
+    }
+
+    fn toggled_pass_name(name: &str) -> &'static str {
+        let name = PassManager::pass_name(name);
+        if name == OUT_OF_SSA.name {
+            error::panic(format!("Pass '{}' runs whenever '{}' does", name, SSA.name));
+        }
+        name
+    }
+
+    pub fn enable(&mut self, name: &str) {
+        let name = PassManager::toggled_pass_name(name);
+        if !self.enabled.contains(&name) {
+            self.enabled.push(name);
+        }
+    }
+
+    pub fn disable(&mut self, name: &str) {
+        let name = PassManager::toggled_pass_name(name);
+        self.enabled.retain(|pass| *pass != name);
+    }
+
+    /// Dumps every function with `print_functions` each time `name` runs
+    pub fn print_after(&mut self, name: &str) {
+        let name = PassManager::pass_name(name);
+        self.print_after.push(name);
+    }
+
+    pub fn time_passes(&mut self, time_passes: bool) {
+        self.time_passes = time_passes;
+    }
+
+    fn is_enabled(&self, pass: &Pass) -> bool {
+        self.enabled.contains(&pass.name)
+    }
+
+    fn run_pass(&mut self, pass: &Pass, cfgs: &mut [Cfg]) -> bool {
+        let start = Instant::now();
+        let mut changed = false;
+        for cfg in cfgs.iter_mut() {
+            changed |= (pass.run)(cfg);
+        }
+        let elapsed = start.elapsed();
+
+        match self
+            .timings
+            .iter_mut()
+            .find(|timing| timing.name == pass.name)
+        {
+            Some(timing) => {
+                timing.runs += 1;
+                timing.total += elapsed;
+            }
+            None => self.timings.push(PassTiming {
+                name: pass.name,
+                runs: 1,
+                total: elapsed,
+            }),
+        }
+
+        if self.print_after.contains(&pass.name) {
+            println!("; after {}", pass.name);
+            print_functions(&cfgs.iter().cloned().map(Cfg::into_function).collect());
+        }
+
+        changed
+    }
+
+    pub fn run(&mut self, cfgs: &mut [Cfg]) {
+        let in_ssa = self.is_enabled(&SSA);
+        if in_ssa {
+            self.run_pass(&SSA, cfgs);
+        }
+
+        let mut rounds = 0;
+        loop {
+            let mut changed = false;
+            for pass in &OPTIMIZATIONS {
+                if self.is_enabled(pass) {
+                    changed |= self.run_pass(pass, cfgs);
+                }
+            }
+
+            rounds += 1;
+            if self.level < OptLevel::O2 || !changed || rounds == MAX_ROUNDS {
+                break;
+            }
+        }
+
+        if in_ssa {
+            self.run_pass(&OUT_OF_SSA, cfgs);
+        }
+
+        if self.time_passes {
+            self.print_timings();
+        }
+    }
+
+    fn print_timings(&self) {
+        let total: Duration = self.timings.iter().map(|timing| timing.total).sum();
+
+        eprintln!("{:<12} {:>6} {:>12} {:>7}", "pass", "runs", "time", "%");
+        for timing in &self.timings {
+            let percent = if total.is_zero() {
+                0.0
+            } else {
+                timing.total.as_secs_f64() / total.as_secs_f64() * 100.0
+            };
+            eprintln!(
+                "{:<12} {:>6} {:>12?} {:>6.1}%",
+                timing.name, timing.runs, timing.total, percent
+            );
+        }
+        eprintln!("{:<12} {:>6} {:>12?}", "total", "", total);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssir::tests::lower;
+
+    const SOURCE: &str = "func f(a: i32) -> i32 {
+        x := 2 * 3
+        y := x + a
+        z := y
+        if x > 5 {
+            ret z
+        }
+        ret a
+    }";
+
+    /// The passes that ran over `SOURCE`, in order, with how often they did
+    fn runs(manager: &mut PassManager) -> Vec<(&'static str, usize)> {
+        manager.run(&mut lower(SOURCE));
+        manager
+            .timings
+            .iter()
+            .map(|timing| (timing.name, timing.runs))
+            .collect()
+    }
+
+    #[test]
+    fn levels_select_the_passes_and_how_often_they_run() {
+        assert_eq!(runs(&mut PassManager::new(OptLevel::O0)), []);
+
+        let once = ["ssa", "fold", "cse", "dce", "out-of-ssa"].map(|name| (name, 1));
+        assert_eq!(runs(&mut PassManager::new(OptLevel::O1)), once);
+
+        let repeated = runs(&mut PassManager::new(OptLevel::O2));
+        assert_eq!(repeated.first(), Some(&("ssa", 1)));
+        assert_eq!(repeated.last(), Some(&("out-of-ssa", 1)));
+        for (name, count) in &repeated[1..repeated.len() - 1] {
+            assert!(*count > 1 && *count <= MAX_ROUNDS, "{}: {}", name, count);
+        }
+    }
+
+    #[test]
+    fn flags_toggle_single_passes() {
+        let mut manager = PassManager::new(OptLevel::O1);
+        manager.disable("ssa");
+        manager.disable("cse");
+        assert_eq!(runs(&mut manager), [("fold", 1), ("dce", 1)]);
+
+        let mut manager = PassManager::new(OptLevel::O0);
+        manager.enable("dce");
+        manager.enable("dce");
+        assert_eq!(runs(&mut manager), [("dce", 1)]);
+    }
+
+    #[test]
+    fn printing_and_timing_are_recorded() {
+        let mut manager = PassManager::new(OptLevel::O0);
+        manager.print_after("out-of-ssa");
+        manager.print_after("fold");
+        manager.time_passes(true);
+        assert_eq!(manager.print_after, ["out-of-ssa", "fold"]);
+        assert!(manager.time_passes);
+        assert!(manager.enabled.is_empty());
+    }
+}
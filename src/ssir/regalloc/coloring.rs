@@ -0,0 +1,527 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use log::debug;
+
+use crate::{
+    reg::{self, RegisterClass, RegisterLabel, RegisterManager, RegisterSize},
+    ssir::{
+        cfg::{Cfg, Terminator},
+        ins::Instruction,
+        live::{self, Liveness},
+        tmp::{TmpChild, TmpNode},
+    },
+};
+
+/// Graph-coloring register allocation with iterated register coalescing
+/// ("Iterated Register Coalescing" by George and Appel). Temporaries related
+/// by a copy, or by an operand whose register the result should reuse, are
+/// merged when that can't make the graph uncolorable. Spilled temporaries are
+/// rewritten to go through the stack and the allocation starts over.
+pub fn allocate(cfg: &mut Cfg) -> RegisterManager {
+    let rmgr = RegisterManager::new();
+    let mut no_spill = BTreeSet::new();
+    let mut spill_count = 0;
+
+    loop {
+        let mut graph = Graph::build(cfg, &rmgr, &no_spill);
+        graph.reduce();
+        graph.assign_colors();
+
+        let spilled = graph.spilled_tmps();
+        if spilled.is_empty() {
+            let registers = graph.registers();
+            return super::finish(cfg, &registers, spill_count);
+        }
+
+        debug!("Spilling {:?} in {}", spilled, cfg.name);
+        spill_count += spilled.len();
+        no_spill.extend(super::insert_spill_code(cfg, &spilled));
+        no_spill.extend(spilled);
+    }
+}
+
+struct Node {
+    tmp: usize,
+    /// Registers of different classes never compete with each other
+    class: RegisterClass,
+    size: RegisterSize,
+    /// Index into the colors of the node's class, fixed for parameters
+    color: Option<usize>,
+    precolored: bool,
+    /// Color of the return register, for temporaries that are returned
+    preferred: Option<usize>,
+    degree: usize,
+    adj_list: Vec<usize>,
+    move_list: BTreeSet<usize>,
+    alias: usize,
+    /// Number of definitions and uses, the cost of spilling the node
+    uses: usize,
+    spillable: bool,
+}
+
+struct Graph {
+    nodes: Vec<Node>,
+    adj_set: BTreeSet<(usize, usize)>,
+    /// Coalescing candidates, as (destination, source) nodes
+    moves: Vec<(usize, usize)>,
+    /// Physical registers of every class, a color is an index into these
+    colors: HashMap<RegisterClass, Vec<RegisterLabel>>,
+
+    simplify_worklist: BTreeSet<usize>,
+    freeze_worklist: BTreeSet<usize>,
+    spill_worklist: BTreeSet<usize>,
+    spilled_nodes: BTreeSet<usize>,
+    coalesced_nodes: BTreeSet<usize>,
+    select_stack: Vec<usize>,
+
+    worklist_moves: BTreeSet<usize>,
+    active_moves: BTreeSet<usize>,
+}
+
+impl Graph {
+    fn build(cfg: &Cfg, rmgr: &RegisterManager, no_spill: &BTreeSet<usize>) -> Graph {
+        let mut colors = HashMap::new();
+        for class in [RegisterClass::General, RegisterClass::Float] {
+            colors.insert(class, rmgr.allocatable(class, &RegisterSize::Qword));
+        }
+
+        let mut graph = Graph {
+            nodes: Vec::new(),
+            adj_set: BTreeSet::new(),
+            moves: Vec::new(),
+            colors,
+            simplify_worklist: BTreeSet::new(),
+            freeze_worklist: BTreeSet::new(),
+            spill_worklist: BTreeSet::new(),
+            spilled_nodes: BTreeSet::new(),
+            coalesced_nodes: BTreeSet::new(),
+            select_stack: Vec::new(),
+            worklist_moves: BTreeSet::new(),
+            active_moves: BTreeSet::new(),
+        };
+
+        let arguments = rmgr.argument_registers(&cfg.params);
+        let mut node_of: BTreeMap<usize, usize> = BTreeMap::new();
+        for block in &cfg.blocks {
+            for ins in &block.instructions {
+                if let Instruction::TmpNode(node, tipe, _) = ins {
+                    let class = reg::register_class(tipe);
+                    let color = super::param_register(node, &arguments)
+                        .map(|label| graph.color_of(rmgr, &label, class));
+
+                    node_of.insert(node.id(), graph.nodes.len());
+                    graph.nodes.push(Node {
+                        tmp: node.id(),
+                        class,
+                        size: reg::size_to_reg_size(tipe.size),
+                        color,
+                        precolored: color.is_some(),
+                        preferred: None,
+                        degree: 0,
+                        adj_list: Vec::new(),
+                        move_list: BTreeSet::new(),
+                        alias: graph.nodes.len(),
+                        uses: 0,
+                        spillable: !no_spill.contains(&node.id()),
+                    });
+                }
+            }
+
+            if let Terminator::Ret(Some(TmpChild::TmpRef(tmp, _, _))) = &block.terminator {
+                if let Some(node) = node_of.get(tmp) {
+                    let class = graph.nodes[*node].class;
+                    graph.nodes[*node].preferred = rmgr
+                        .return_register(class, &graph.nodes[*node].size)
+                        .map(|label| graph.color_of(rmgr, &label, class));
+                }
+            }
+        }
+
+        let liveness = Liveness::compute(cfg);
+        for block in &cfg.blocks {
+            let mut live = liveness.live_out[block.id]
+                .iter()
+                .map(|tmp| node_of[tmp])
+                .collect::<BTreeSet<usize>>();
+            for tmp in live::used_tmps(block.terminator.operands()) {
+                live.insert(node_of[&tmp]);
+                graph.nodes[node_of[&tmp]].uses += 1;
+            }
+
+            for ins in block.instructions.iter().rev() {
+                let used = live::used_tmps(ins.operands())
+                    .map(|tmp| node_of[&tmp])
+                    .collect::<Vec<usize>>();
+
+                if let Instruction::TmpNode(tmp_node, _, _) = ins {
+                    let def = node_of[&tmp_node.id()];
+                    graph.nodes[def].uses += 1;
+
+                    // A plain copy doesn't make the two temporaries interfere
+                    let copied = match tmp_node {
+                        TmpNode::ValueTmp(value) => copy_source(&value.value),
+                        TmpNode::AssignTmp(assign) => copy_source(&assign.value),
+                        _ => None,
+                    }
+                    .map(|tmp| node_of[&tmp]);
+                    if let Some(source) = copied {
+                        live.remove(&source);
+                        graph.add_move(def, source);
+                    } else if let Some(source) = super::hint(tmp_node).map(|tmp| node_of[&tmp]) {
+                        graph.add_move(def, source);
+                    }
+
+                    for other in live.clone() {
+                        graph.add_edge(def, other);
+                    }
+                    live.remove(&def);
+                }
+
+                for node in used {
+                    live.insert(node);
+                    graph.nodes[node].uses += 1;
+                }
+            }
+        }
+
+        graph.make_worklist();
+        graph
+    }
+
+    fn color_of(
+        &self,
+        rmgr: &RegisterManager,
+        label: &RegisterLabel,
+        class: RegisterClass,
+    ) -> usize {
+        let full = rmgr.view(label, &RegisterSize::Qword).unwrap();
+        self.colors[&class]
+            .iter()
+            .position(|color| *color == full)
+            .unwrap()
+    }
+
+    fn k(&self, node: usize) -> usize {
+        self.colors[&self.nodes[node].class].len()
+    }
+
+    fn same_class(&self, u: usize, v: usize) -> bool {
+        self.nodes[u].class == self.nodes[v].class
+    }
+
+    fn add_move(&mut self, dest: usize, source: usize) {
+        if dest == source || !self.same_class(dest, source) {
+            return;
+        }
+
+        let index = self.moves.len();
+        self.moves.push((dest, source));
+        self.nodes[dest].move_list.insert(index);
+        self.nodes[source].move_list.insert(index);
+        self.worklist_moves.insert(index);
+    }
+
+    fn add_edge(&mut self, u: usize, v: usize) {
+        if u == v || !self.same_class(u, v) || self.adj_set.contains(&(u, v)) {
+            return;
+        }
+
+        self.adj_set.insert((u, v));
+        self.adj_set.insert((v, u));
+        for (a, b) in [(u, v), (v, u)] {
+            if !self.nodes[a].precolored {
+                self.nodes[a].adj_list.push(b);
+                self.nodes[a].degree += 1;
+            }
+        }
+    }
+
+    fn make_worklist(&mut self) {
+        for node in 0..self.nodes.len() {
+            if self.nodes[node].precolored {
+                continue;
+            }
+
+            if self.nodes[node].degree >= self.k(node) {
+                self.spill_worklist.insert(node);
+            } else if self.move_related(node) {
+                self.freeze_worklist.insert(node);
+            } else {
+                self.simplify_worklist.insert(node);
+            }
+        }
+    }
+
+    fn adjacent(&self, node: usize) -> Vec<usize> {
+        self.nodes[node]
+            .adj_list
+            .iter()
+            .filter(|adj| !self.select_stack.contains(adj) && !self.coalesced_nodes.contains(adj))
+            .cloned()
+            .collect()
+    }
+
+    fn node_moves(&self, node: usize) -> Vec<usize> {
+        self.nodes[node]
+            .move_list
+            .iter()
+            .filter(|m| self.active_moves.contains(m) || self.worklist_moves.contains(m))
+            .cloned()
+            .collect()
+    }
+
+    fn move_related(&self, node: usize) -> bool {
+        !self.node_moves(node).is_empty()
+    }
+
+    fn get_alias(&self, mut node: usize) -> usize {
+        while self.coalesced_nodes.contains(&node) {
+            node = self.nodes[node].alias;
+        }
+        node
+    }
+
+    /// Simplifies, coalesces, freezes and picks spill candidates until every
+    /// node is on the select stack
+    fn reduce(&mut self) {
+        loop {
+            if let Some(node) = self.simplify_worklist.pop_first() {
+                self.simplify(node);
+            } else if let Some(m) = self.worklist_moves.pop_first() {
+                self.coalesce(m);
+            } else if let Some(node) = self.freeze_worklist.pop_first() {
+                self.freeze(node);
+            } else if !self.spill_worklist.is_empty() {
+                self.select_spill();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn simplify(&mut self, node: usize) {
+        self.select_stack.push(node);
+        for adj in self.adjacent(node) {
+            self.decrement_degree(adj);
+        }
+    }
+
+    fn decrement_degree(&mut self, node: usize) {
+        if self.nodes[node].precolored {
+            return;
+        }
+
+        let degree = self.nodes[node].degree;
+        self.nodes[node].degree -= 1;
+        if degree == self.k(node) {
+            let mut nodes = self.adjacent(node);
+            nodes.push(node);
+            self.enable_moves(&nodes);
+
+            self.spill_worklist.remove(&node);
+            if self.move_related(node) {
+                self.freeze_worklist.insert(node);
+            } else {
+                self.simplify_worklist.insert(node);
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[usize]) {
+        for node in nodes {
+            for m in self.node_moves(*node) {
+                if self.active_moves.remove(&m) {
+                    self.worklist_moves.insert(m);
+                }
+            }
+        }
+    }
+
+    fn add_worklist(&mut self, node: usize) {
+        if !self.nodes[node].precolored
+            && !self.move_related(node)
+            && self.nodes[node].degree < self.k(node)
+        {
+            self.freeze_worklist.remove(&node);
+            self.simplify_worklist.insert(node);
+        }
+    }
+
+    /// George's test, coalescing `node` into the precolored `precolored`
+    fn ok(&self, node: usize, precolored: usize) -> bool {
+        self.nodes[node].degree < self.k(node)
+            || self.nodes[node].precolored
+            || self.adj_set.contains(&(node, precolored))
+    }
+
+    /// Briggs' test, the merged node has less than K neighbors of
+    /// significant degree
+    fn conservative(&self, nodes: &BTreeSet<usize>, k: usize) -> bool {
+        let significant = nodes
+            .iter()
+            .filter(|node| self.nodes[**node].degree >= self.k(**node))
+            .count();
+        significant < k
+    }
+
+    fn coalesce(&mut self, m: usize) {
+        let (x, y) = self.moves[m];
+        let (x, y) = (self.get_alias(x), self.get_alias(y));
+        let (u, v) = if self.nodes[y].precolored {
+            (y, x)
+        } else {
+            (x, y)
+        };
+
+        if u == v {
+            self.add_worklist(u);
+        } else if self.nodes[v].precolored || self.adj_set.contains(&(u, v)) {
+            self.add_worklist(u);
+            self.add_worklist(v);
+        } else {
+            let can_coalesce = if self.nodes[u].precolored {
+                self.adjacent(v).into_iter().all(|t| self.ok(t, u))
+            } else {
+                let mut nodes = self.adjacent(u).into_iter().collect::<BTreeSet<usize>>();
+                nodes.extend(self.adjacent(v));
+                self.conservative(&nodes, self.k(u))
+            };
+
+            if can_coalesce {
+                self.combine(u, v);
+                self.add_worklist(u);
+            } else {
+                self.active_moves.insert(m);
+            }
+        }
+    }
+
+    fn combine(&mut self, u: usize, v: usize) {
+        if !self.freeze_worklist.remove(&v) {
+            self.spill_worklist.remove(&v);
+        }
+
+        self.coalesced_nodes.insert(v);
+        self.nodes[v].alias = u;
+        let moves = self.nodes[v].move_list.clone();
+        self.nodes[u].move_list.extend(moves);
+        if self.nodes[u].preferred.is_none() {
+            self.nodes[u].preferred = self.nodes[v].preferred;
+        }
+        self.nodes[u].uses += self.nodes[v].uses;
+        self.nodes[u].spillable &= self.nodes[v].spillable;
+        self.enable_moves(&[v]);
+
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+
+        if self.nodes[u].degree >= self.k(u) && self.freeze_worklist.remove(&u) {
+            self.spill_worklist.insert(u);
+        }
+    }
+
+    fn freeze(&mut self, node: usize) {
+        self.simplify_worklist.insert(node);
+        self.freeze_moves(node);
+    }
+
+    fn freeze_moves(&mut self, u: usize) {
+        for m in self.node_moves(u) {
+            let (x, y) = self.moves[m];
+            let v = if self.get_alias(y) == self.get_alias(u) {
+                self.get_alias(x)
+            } else {
+                self.get_alias(y)
+            };
+
+            self.active_moves.remove(&m);
+            self.worklist_moves.remove(&m);
+            if !self.move_related(v)
+                && !self.nodes[v].precolored
+                && self.nodes[v].degree < self.k(v)
+                && self.freeze_worklist.remove(&v)
+            {
+                self.simplify_worklist.insert(v);
+            }
+        }
+    }
+
+    /// Picks the node that is cheapest to spill for the most neighbors
+    fn select_spill(&mut self) {
+        let cost = |node: &Node| {
+            if node.spillable {
+                node.uses as f64 / node.degree.max(1) as f64
+            } else {
+                f64::INFINITY
+            }
+        };
+
+        let node = *self
+            .spill_worklist
+            .iter()
+            .min_by(|a, b| cost(&self.nodes[**a]).total_cmp(&cost(&self.nodes[**b])))
+            .unwrap();
+
+        self.spill_worklist.remove(&node);
+        self.simplify_worklist.insert(node);
+        self.freeze_moves(node);
+    }
+
+    fn assign_colors(&mut self) {
+        while let Some(node) = self.select_stack.pop() {
+            let mut ok_colors = (0..self.k(node)).collect::<BTreeSet<usize>>();
+            for adj in &self.nodes[node].adj_list {
+                let adj = self.get_alias(*adj);
+                if let Some(color) = self.nodes[adj].color {
+                    ok_colors.remove(&color);
+                }
+            }
+
+            let preferred = self.nodes[node]
+                .preferred
+                .filter(|color| ok_colors.contains(color));
+            match preferred.or_else(|| ok_colors.first().cloned()) {
+                Some(color) => self.nodes[node].color = Some(color),
+                None => {
+                    self.spilled_nodes.insert(node);
+                }
+            }
+        }
+
+        for node in self.coalesced_nodes.clone() {
+            let alias = self.get_alias(node);
+            self.nodes[node].color = self.nodes[alias].color;
+        }
+    }
+
+    /// Temporaries that didn't get a color, including the ones coalesced
+    /// into them
+    fn spilled_tmps(&self) -> BTreeSet<usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(node, _)| self.spilled_nodes.contains(&self.get_alias(*node)))
+            .map(|(_, node)| node.tmp)
+            .collect()
+    }
+
+    fn registers(&self) -> HashMap<usize, RegisterLabel> {
+        let rmgr = RegisterManager::new();
+        self.nodes
+            .iter()
+            .map(|node| {
+                let color = &self.colors[&node.class][node.color.unwrap()];
+                (node.tmp, rmgr.view(color, &node.size).unwrap())
+            })
+            .collect()
+    }
+}
+
+fn copy_source(value: &TmpChild) -> Option<usize> {
+    match value {
+        TmpChild::TmpRef(id, _, _) => Some(*id),
+        _ => None,
+    }
+}
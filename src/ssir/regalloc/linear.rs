@@ -0,0 +1,100 @@
+use std::collections::{BTreeSet, HashMap};
+
+use log::debug;
+
+use crate::{
+    reg::{RegisterLabel, RegisterManager},
+    ssir::cfg::Cfg,
+};
+
+use super::Interval;
+
+/// Linear-scan register allocation ("Linear Scan Register Allocation" by
+/// Poletto and Sarkar). Intervals are visited by start, a register is freed as
+/// soon as its temporary dies, and when none is free the interval that ends
+/// last is spilled. Spilled temporaries are rewritten to go through the stack
+/// and the allocation starts over.
+pub fn allocate(cfg: &mut Cfg) -> RegisterManager {
+    let mut spill_count = 0;
+    loop {
+        let intervals = super::build_intervals(cfg);
+        let (registers, spilled) = scan(&intervals);
+
+        if spilled.is_empty() {
+            return super::finish(cfg, &registers, spill_count);
+        }
+
+        debug!("Spilling {:?} in {}", spilled, cfg.name);
+        spill_count += spilled.len();
+        super::insert_spill_code(cfg, &spilled);
+    }
+}
+
+fn scan(intervals: &[Interval]) -> (HashMap<usize, RegisterLabel>, BTreeSet<usize>) {
+    let mut rmgr = RegisterManager::new();
+    let mut registers: HashMap<usize, RegisterLabel> = HashMap::new();
+    let mut spilled = BTreeSet::new();
+    // Intervals currently holding a register
+    let mut active: Vec<&Interval> = Vec::new();
+
+    for interval in intervals {
+        active.retain(|other| {
+            if other.ends_before(interval) {
+                rmgr.deallocate(registers[&other.tmp].clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        let preferred = interval
+            .preferred
+            .clone()
+            .filter(|label| rmgr.allocate_label(label));
+        let hinted = || {
+            interval
+                .hint
+                .and_then(|hint| registers.get(&hint))
+                .filter(|label| rmgr.class(label) == interval.class)
+                .and_then(|label| rmgr.view(label, &interval.size))
+                .filter(|label| rmgr.allocate_label(label))
+        };
+
+        let label = preferred
+            .or_else(hinted)
+            .or_else(|| rmgr.try_allocate(interval.class, interval.size.clone()));
+        if let Some(label) = label {
+            registers.insert(interval.tmp, label);
+            active.push(interval);
+            continue;
+        }
+
+        // Any register with a view of the right size will do, sizes alias
+        let victim = active
+            .iter()
+            .enumerate()
+            .filter(|(_, other)| other.class == interval.class)
+            .filter_map(|(index, other)| {
+                let label = rmgr.view(&registers[&other.tmp], &interval.size)?;
+                Some((index, other.end, label))
+            })
+            .max_by_key(|(_, end, _)| *end);
+
+        match victim {
+            Some((index, end, label)) if end > interval.end => {
+                let victim = active.remove(index);
+                rmgr.deallocate(registers.remove(&victim.tmp).unwrap());
+                spilled.insert(victim.tmp);
+
+                rmgr.allocate_label(&label);
+                registers.insert(interval.tmp, label);
+                active.push(interval);
+            }
+            _ => {
+                spilled.insert(interval.tmp);
+            }
+        }
+    }
+
+    (registers, spilled)
+}
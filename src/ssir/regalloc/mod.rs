@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::reg::{self, RegisterClass, RegisterLabel, RegisterManager, RegisterSize};
+
+use super::{
+    cfg::{Cfg, Terminator},
+    ins::Instruction,
+    live::{self, Liveness},
+    tmp::{TmpChild, TmpNode, ValueTmp},
+};
+
+pub mod coloring;
+pub mod linear;
+#[cfg(test)]
+mod tests;
+
+/// Range of instruction positions during which a temporary holds a value
+#[derive(Debug, Clone)]
+pub struct Interval {
+    pub tmp: usize,
+    pub start: usize,
+    pub end: usize,
+    /// Position of the instruction computing the temporary
+    pub def: usize,
+    pub class: RegisterClass,
+    pub size: RegisterSize,
+    /// Temporary whose register the result should reuse, x86 instructions
+    /// overwrite their first operand
+    pub hint: Option<usize>,
+    /// Register the calling convention puts the value in: parameters arrive
+    /// in their argument register and returned values leave in the return
+    /// register
+    pub preferred: Option<RegisterLabel>,
+}
+
+impl Interval {
+    /// Whether this interval no longer needs its register when `next` begins.
+    /// An operand read by the instruction defining `next` can hand its
+    /// register over.
+    pub fn ends_before(&self, next: &Interval) -> bool {
+        self.end < next.start || (self.end == next.start && next.start == next.def)
+    }
+}
+
+fn hint(node: &TmpNode) -> Option<usize> {
+    let operand = match node {
+        TmpNode::BinaryTmp(binary) if binary.op.is_comparison() => return None,
+        TmpNode::BinaryTmp(binary) => match (&binary.lhs, &binary.rhs) {
+            (TmpChild::TmpRef(_, _, _), _) => &binary.lhs,
+            _ => &binary.rhs,
+        },
+        TmpNode::UnaryTmp(unary) => &unary.value,
+        TmpNode::AssignTmp(assign) => &assign.value,
+        TmpNode::ValueTmp(_)
+        | TmpNode::CastTmp(_)
+        | TmpNode::AddressTmp(_)
+        | TmpNode::LoadTmp(_)
+        | TmpNode::IndexTmp(_) => return None,
+    };
+
+    match operand {
+        TmpChild::TmpRef(id, _, _) => Some(*id),
+        _ => None,
+    }
+}
+
+/// Argument register a parameter arrives in, out of the function's
+/// `argument_registers`
+fn param_register(node: &TmpNode, arguments: &[Option<RegisterLabel>]) -> Option<RegisterLabel> {
+    match node {
+        TmpNode::ValueTmp(ValueTmp {
+            value: TmpChild::Param(index, _),
+            ..
+        }) => arguments[*index].clone(),
+        _ => None,
+    }
+}
+
+/// Numbers the instructions in block order and computes the live interval of
+/// every temporary, ordered by start
+pub fn build_intervals(cfg: &Cfg) -> Vec<Interval> {
+    let liveness = Liveness::compute(cfg);
+    let rmgr = RegisterManager::new();
+    let arguments = rmgr.argument_registers(&cfg.params);
+    let mut intervals: BTreeMap<usize, Interval> = BTreeMap::new();
+    let extend = |intervals: &mut BTreeMap<usize, Interval>, tmp: usize, position: usize| {
+        if let Some(interval) = intervals.get_mut(&tmp) {
+            interval.start = interval.start.min(position);
+            interval.end = interval.end.max(position);
+        }
+    };
+
+    // Definitions first, so that uses in earlier blocks have an interval to extend
+    let mut position = 0;
+    for block in &cfg.blocks {
+        for ins in &block.instructions {
+            if let Instruction::TmpNode(node, tipe, _) = ins {
+                let preferred = param_register(node, &arguments);
+
+                intervals.insert(
+                    node.id(),
+                    Interval {
+                        tmp: node.id(),
+                        start: position,
+                        end: position,
+                        def: position,
+                        class: reg::register_class(tipe),
+                        size: reg::size_to_reg_size(tipe.size),
+                        hint: hint(node),
+                        preferred,
+                    },
+                );
+            }
+            position += 1;
+        }
+        position += 1;
+    }
+
+    let mut position = 0;
+    for block in &cfg.blocks {
+        for tmp in &liveness.live_in[block.id] {
+            extend(&mut intervals, *tmp, position);
+        }
+
+        for ins in &block.instructions {
+            for tmp in live::used_tmps(ins.operands()) {
+                extend(&mut intervals, tmp, position);
+            }
+            position += 1;
+        }
+        for tmp in live::used_tmps(block.terminator.operands()) {
+            extend(&mut intervals, tmp, position);
+        }
+        if let Terminator::Ret(Some(TmpChild::TmpRef(tmp, _, _))) = &block.terminator {
+            if let Some(interval) = intervals.get_mut(tmp) {
+                interval.preferred = rmgr.return_register(interval.class, &interval.size);
+            }
+        }
+
+        for tmp in &liveness.live_out[block.id] {
+            extend(&mut intervals, *tmp, position);
+        }
+        position += 1;
+    }
+
+    let mut intervals = intervals.into_values().collect::<Vec<Interval>>();
+    intervals.sort_by_key(|interval| (interval.start, interval.tmp));
+    intervals
+}
+
+fn spill_slot(tmp: usize) -> String {
+    format!("$spill{}", tmp)
+}
+
+/// Keeps the spilled temporaries on the stack: every definition is stored to
+/// a hidden variable right away and every use reloads it into a new
+/// temporary, which only lives for a single instruction. Returns the reload
+/// temporaries, spilling them again wouldn't free anything.
+pub fn insert_spill_code(cfg: &mut Cfg, spilled: &BTreeSet<usize>) -> BTreeSet<usize> {
+    let mut reloads = BTreeSet::new();
+    let mut next_tmp = cfg
+        .blocks
+        .iter()
+        .flat_map(|block| block.instructions.iter())
+        .filter_map(live::defined_tmp)
+        .max()
+        .map_or(0, |id| id + 1);
+
+    let mut reload = |operand: &mut TmpChild, instructions: &mut Vec<Instruction>| {
+        if let TmpChild::TmpRef(id, tipe, _) = operand {
+            if !spilled.contains(id) {
+                return;
+            }
+
+            let value = TmpChild::LoadVar(spill_slot(*id), tipe.clone(), None);
+            instructions.push(Instruction::TmpNode(
+                TmpNode::ValueTmp(ValueTmp::new(value, next_tmp)),
+                tipe.clone(),
+                None,
+            ));
+            *operand = TmpChild::TmpRef(next_tmp, tipe.clone(), None);
+            reloads.insert(next_tmp);
+            next_tmp += 1;
+        }
+    };
+
+    for block in &mut cfg.blocks {
+        let mut instructions = Vec::with_capacity(block.instructions.len());
+        for mut ins in std::mem::take(&mut block.instructions) {
+            for operand in ins.operands_mut() {
+                reload(operand, &mut instructions);
+            }
+
+            let store = match &ins {
+                Instruction::TmpNode(node, tipe, _) if spilled.contains(&node.id()) => {
+                    Some((node.id(), tipe.clone()))
+                }
+                _ => None,
+            };
+            instructions.push(ins);
+
+            if let Some((id, tipe)) = store {
+                instructions.push(Instruction::VarDecl(
+                    spill_slot(id),
+                    TmpChild::TmpRef(id, tipe.clone(), None),
+                    tipe,
+                    None,
+                ));
+            }
+        }
+
+        for operand in block.terminator.operands_mut() {
+            reload(operand, &mut instructions);
+        }
+        block.instructions = instructions;
+    }
+
+    reloads
+}
+
+/// Writes the chosen registers into the function and saves the callee-saved
+/// ones, returning the allocation statistics
+pub fn finish(
+    cfg: &mut Cfg,
+    registers: &HashMap<usize, RegisterLabel>,
+    spilled: usize,
+) -> RegisterManager {
+    apply_registers(cfg, registers);
+    insert_prologue(cfg, registers);
+
+    let mut stats = RegisterManager::new();
+    for label in registers.values() {
+        stats.record_assignment(label);
+    }
+    stats.record_spills(spilled);
+    stats
+}
+
+/// Writes the chosen registers into the temporaries and their uses
+fn apply_registers(cfg: &mut Cfg, registers: &HashMap<usize, RegisterLabel>) {
+    let label_use = |operand: &mut TmpChild| {
+        if let TmpChild::TmpRef(id, _, label) = operand {
+            *label = registers.get(id).cloned();
+        }
+    };
+
+    for block in &mut cfg.blocks {
+        for ins in &mut block.instructions {
+            for operand in ins.operands_mut() {
+                label_use(operand);
+            }
+            if let Instruction::TmpNode(node, _, label) = ins {
+                *label = registers.get(&node.id()).cloned();
+            }
+        }
+        for operand in block.terminator.operands_mut() {
+            label_use(operand);
+        }
+    }
+}
+
+/// Saves the callee-saved registers the allocation ended up using at the
+/// entry of the function, and restores them before every return
+fn insert_prologue(cfg: &mut Cfg, registers: &HashMap<usize, RegisterLabel>) {
+    let rmgr = RegisterManager::new();
+    let used = registers
+        .values()
+        .filter(|label| rmgr.is_callee_saved(label))
+        .map(|label| rmgr.view(label, &RegisterSize::Qword).unwrap())
+        .collect::<Vec<RegisterLabel>>();
+    let saved = rmgr
+        .callee_saved_registers()
+        .into_iter()
+        .filter(|label| used.contains(label))
+        .collect::<Vec<RegisterLabel>>();
+
+    cfg.blocks[0]
+        .instructions
+        .insert(0, Instruction::Prologue(saved.clone(), 0));
+    for block in &mut cfg.blocks {
+        if let Terminator::Ret(_) = block.terminator {
+            block
+                .instructions
+                .push(Instruction::Epilogue(saved.clone()));
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Registers the allocators hand out, checked against the liveness of the
+//! temporaries
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    reg::{RegisterLabel, RegisterManager, RegisterSize, INT_ARGUMENTS},
+    ssir::{
+        cfg::{Cfg, Terminator},
+        ins::Instruction,
+        live::{self, Liveness},
+        pass::{OptLevel, PassManager},
+        tests::lower,
+        tmp::{TmpChild, TmpNode},
+    },
+    typechecker::{TaggedType, TypeKind},
+};
+
+use super::{coloring, linear};
+
+fn optimized(source: &str, level: OptLevel) -> Cfg {
+    let mut cfgs = lower(source);
+    PassManager::new(level).run(&mut cfgs);
+    cfgs.remove(0)
+}
+
+/// The full register every temporary ended up in
+fn registers(cfg: &Cfg) -> HashMap<usize, RegisterLabel> {
+    let rmgr = RegisterManager::new();
+    cfg.blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|ins| match ins {
+            Instruction::TmpNode(node, _, Some(label)) => {
+                Some((node.id(), rmgr.view(label, &RegisterSize::Qword).unwrap()))
+            }
+            Instruction::TmpNode(node, _, None) => panic!("tmp{} has no register", node.id()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn copy_source(node: &TmpNode) -> Option<usize> {
+    let value = match node {
+        TmpNode::ValueTmp(value) => &value.value,
+        TmpNode::AssignTmp(assign) => &assign.value,
+        _ => return None,
+    };
+    match value {
+        TmpChild::TmpRef(id, _, _) => Some(*id),
+        _ => None,
+    }
+}
+
+/// Panics if a temporary is written to a register another live temporary is
+/// still in. A copy may share the register of its source, they hold the same
+/// value.
+fn assert_no_interference(cfg: &Cfg) {
+    let registers = registers(cfg);
+    let liveness = Liveness::compute(cfg);
+    for block in &cfg.blocks {
+        let mut live = liveness.live_out[block.id].clone();
+        live.extend(live::used_tmps(block.terminator.operands()));
+
+        for ins in block.instructions.iter().rev() {
+            if let Instruction::TmpNode(node, _, _) = ins {
+                let def = node.id();
+                live.remove(&def);
+                for other in &live {
+                    if copy_source(node) != Some(*other) {
+                        assert_ne!(
+                            registers[&def], registers[other],
+                            "tmp{} and tmp{} in {}",
+                            def, other, cfg.name
+                        );
+                    }
+                }
+            }
+            live.extend(live::used_tmps(ins.operands()));
+        }
+    }
+}
+
+/// Temporaries that were rewritten to go through the stack
+fn spilled(cfg: &Cfg) -> BTreeSet<String> {
+    cfg.blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|ins| ins.defined_var())
+        .filter(|name| name.starts_with("$spill"))
+        .cloned()
+        .collect()
+}
+
+const BRANCHES: &str = "func f(a: i32, s: []i32) -> i32 {
+    x := a * 3
+    y := x + a
+    for v in s {
+        if v > x {
+            y = y + v * 2
+        } else {
+            y = y - (v + x)
+        }
+    }
+    ret x + y
+}";
+
+/// `a * 1 + (a * 2 + (...))`, every product is live until the innermost one
+/// is computed
+fn nested_products(count: usize) -> String {
+    let mut expr = format!("a * {}", count);
+    for factor in (1..count).rev() {
+        expr = format!("a * {} + ({})", factor, expr);
+    }
+    format!("func f(a: i64) -> i64 {{ ret {}; }}", expr)
+}
+
+#[test]
+fn linear_scan_keeps_live_temporaries_apart() {
+    for level in [OptLevel::O0, OptLevel::O1] {
+        let mut cfg = optimized(BRANCHES, level);
+        linear::allocate(&mut cfg);
+        assert_no_interference(&cfg);
+        assert!(spilled(&cfg).is_empty(), "{:?}", spilled(&cfg));
+    }
+}
+
+#[test]
+fn linear_scan_spills_when_registers_run_out() {
+    let mut cfg = optimized(&nested_products(24), OptLevel::O1);
+    linear::allocate(&mut cfg);
+    assert!(!spilled(&cfg).is_empty());
+    assert_no_interference(&cfg);
+}
+
+/// The registers the parameters were read from, by index
+fn param_registers(cfg: &Cfg) -> Vec<RegisterLabel> {
+    let mut params = cfg
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|ins| match ins {
+            Instruction::TmpNode(TmpNode::ValueTmp(value), _, Some(label)) => match value.value {
+                TmpChild::Param(index, _) => Some((index, label.clone())),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    params.sort_by_key(|(index, _)| *index);
+    params.into_iter().map(|(_, label)| label).collect()
+}
+
+fn returned_registers(cfg: &Cfg) -> Vec<RegisterLabel> {
+    cfg.blocks
+        .iter()
+        .filter_map(|block| match &block.terminator {
+            Terminator::Ret(Some(TmpChild::TmpRef(_, _, label))) => label.clone(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The registers saved by the prologue, and by every epilogue
+fn saved_registers(cfg: &Cfg) -> (Vec<RegisterLabel>, Vec<Vec<RegisterLabel>>) {
+    let instructions = || cfg.blocks.iter().flat_map(|block| &block.instructions);
+    let prologue = instructions()
+        .find_map(|ins| match ins {
+            Instruction::Prologue(saved, _) => Some(saved.clone()),
+            _ => None,
+        })
+        .unwrap();
+    let epilogues = instructions()
+        .filter_map(|ins| match ins {
+            Instruction::Epilogue(saved) => Some(saved.clone()),
+            _ => None,
+        })
+        .collect();
+    (prologue, epilogues)
+}
+
+#[test]
+fn parameters_and_results_use_the_system_v_registers() {
+    use RegisterLabel::*;
+
+    let source = "func f(a: i32, b: f64, c: u8, d: f32, e: i64) -> f64 {
+        if a > 0 {
+            ret b
+        }
+        x := d as f64
+        ret x * (c as f64) + (e as f64)
+    }";
+    let mut cfg = optimized(source, OptLevel::O0);
+    linear::allocate(&mut cfg);
+    assert_eq!(param_registers(&cfg), [Edi, Xmm0, Sil, Xmm1, Rdx]);
+    assert_eq!(returned_registers(&cfg), [Xmm0, Xmm0]);
+
+    let mut cfg = optimized("func f(a: i16) -> i16 { ret a + 1; }", OptLevel::O0);
+    linear::allocate(&mut cfg);
+    assert_eq!(param_registers(&cfg), [Di]);
+    assert_eq!(returned_registers(&cfg), [Ax]);
+
+    let params = vec![TaggedType::new(8, TypeKind::Numeric, Some(true)); 7];
+    let arguments = RegisterManager::new().argument_registers(&params);
+    assert_eq!(arguments[..6], INT_ARGUMENTS.map(Some));
+    assert_eq!(arguments[6], None);
+}
+
+#[test]
+fn callee_saved_registers_are_saved_only_when_used() {
+    let mut cfg = optimized(BRANCHES, OptLevel::O0);
+    linear::allocate(&mut cfg);
+    let (prologue, epilogues) = saved_registers(&cfg);
+    assert!(prologue.is_empty());
+    assert!(epilogues.iter().all(Vec::is_empty));
+
+    let mut cfg = optimized(&nested_products(24), OptLevel::O0);
+    linear::allocate(&mut cfg);
+    let (prologue, epilogues) = saved_registers(&cfg);
+    assert_eq!(prologue, RegisterManager::new().callee_saved_registers());
+    assert_eq!(epilogues, [prologue]);
+}
+
+#[test]
+fn coloring_keeps_live_temporaries_apart() {
+    for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        let mut cfg = optimized(BRANCHES, level);
+        coloring::allocate(&mut cfg);
+        assert_no_interference(&cfg);
+        assert!(spilled(&cfg).is_empty(), "{:?}", spilled(&cfg));
+    }
+}
+
+#[test]
+fn coloring_spills_when_registers_run_out() {
+    let mut cfg = optimized(&nested_products(24), OptLevel::O2);
+    coloring::allocate(&mut cfg);
+    assert!(!spilled(&cfg).is_empty());
+    assert_no_interference(&cfg);
+
+    // Spilling the cheapest nodes leaves the rest in registers
+    let mut cfg = optimized(&nested_products(12), OptLevel::O2);
+    coloring::allocate(&mut cfg);
+    assert!(spilled(&cfg).is_empty(), "{:?}", spilled(&cfg));
+}
+
+#[test]
+fn coloring_coalesces_results_into_their_dying_operand() {
+    let mut cfg = optimized(
+        "func f(a: i64) -> i64 { ret ((a * 3 + 1) * 5 - 2) ^ 7; }",
+        OptLevel::O2,
+    );
+    coloring::allocate(&mut cfg);
+    let registers = registers(&cfg);
+
+    let mut chained = 0;
+    for ins in cfg.blocks.iter().flat_map(|block| &block.instructions) {
+        if let Instruction::TmpNode(TmpNode::BinaryTmp(binary), _, _) = ins {
+            if let TmpChild::TmpRef(lhs, _, _) = binary.lhs {
+                assert_eq!(registers[&binary.id], registers[&lhs], "{:?}", binary);
+                chained += 1;
+            }
+        }
+    }
+    assert_eq!(chained, 5);
+    assert_eq!(returned_registers(&cfg), [RegisterLabel::Rax]);
+}
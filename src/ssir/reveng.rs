@@ -1,33 +1,26 @@
-use crate::ast::{BinaryOp, Node};
+use crate::{
+    ast::{BinaryOp, Node},
+    typechecker::TypeKind,
+};
 
-pub fn reverse_binary(node: &mut Box<Node>) {
+/// Reverses a comparison (looking through groupings), so that it evaluates to
+/// the negation of the original expression. Returns false if `node` isn't a comparison.
+/// Float comparisons are left alone, they are all false when an operand is NaN.
+pub fn reverse_binary(node: &mut Box<Node>) -> bool {
     match &mut **node {
-        Node::Binary(binary) => {
-            reverse_binary(&mut binary.lhs);
-            reverse_binary(&mut binary.rhs);
-
-            match binary.op {
-                BinaryOp::Equal => {
-                    binary.op = BinaryOp::NotEqual;
-                }
-                BinaryOp::NotEqual => {
-                    binary.op = BinaryOp::Equal;
-                }
-                BinaryOp::Greater => {
-                    binary.op = BinaryOp::LessEq;
-                }
-                BinaryOp::GreaterEq => {
-                    binary.op = BinaryOp::Less;
-                }
-                BinaryOp::Less => {
-                    binary.op = BinaryOp::GreaterEq;
-                }
-                BinaryOp::LessEq => {
-                    binary.op = BinaryOp::Greater;
-                }
-                _ => (),
-            }
+        Node::Binary(binary) if binary.dtype.kind != TypeKind::Float => {
+            binary.op = match binary.op {
+                BinaryOp::Equal => BinaryOp::NotEqual,
+                BinaryOp::NotEqual => BinaryOp::Equal,
+                BinaryOp::Greater => BinaryOp::LessEq,
+                BinaryOp::GreaterEq => BinaryOp::Less,
+                BinaryOp::Less => BinaryOp::GreaterEq,
+                BinaryOp::LessEq => BinaryOp::Greater,
+                _ => return false,
+            };
+            true
         }
-        _ => (),
+        Node::Grouping(grouping) => reverse_binary(&mut grouping.expr),
+        _ => false,
     }
 }
@@ -0,0 +1,507 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::typechecker::TaggedType;
+
+use super::{
+    cfg::{BasicBlock, Cfg, Terminator},
+    dom::Dominators,
+    ins::Instruction,
+    tmp::TmpChild,
+};
+
+/// Converts the variables of a function into SSA form. Every definition of
+/// a variable `x` becomes a `VarDecl` of a fresh version `x.N`, loads are
+/// renamed to the reaching version and phis merge versions at the joins
+/// where the variable is still live (pruned SSA). Variables whose address is
+/// taken stay as they are, pointers keep referring to their one location.
+pub fn construct(cfg: &mut Cfg) {
+    let dom = Dominators::compute(cfg);
+    let address_taken = cfg.address_taken();
+
+    let mut var_types: BTreeMap<String, TaggedType> = BTreeMap::new();
+    let mut def_blocks: BTreeMap<String, BTreeSet<usize>> = BTreeMap::new();
+    for block in &cfg.blocks {
+        for ins in &block.instructions {
+            match ins {
+                Instruction::VarDecl(name, _, tipe, _)
+                | Instruction::VarAssign(name, _, tipe, _)
+                    if !address_taken.contains(name) =>
+                {
+                    var_types.insert(name.clone(), tipe.clone());
+                    def_blocks.entry(name.clone()).or_default().insert(block.id);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let live_in = live_in_vars(cfg);
+
+    // Phi placement on the iterated dominance frontier
+    let mut phi_vars: Vec<Vec<String>> = vec![Vec::new(); cfg.blocks.len()];
+    for (var, blocks) in &def_blocks {
+        let mut worklist = blocks.iter().cloned().collect::<Vec<usize>>();
+        let mut has_phi = BTreeSet::new();
+
+        while let Some(block) = worklist.pop() {
+            for frontier in &dom.frontiers[block] {
+                if has_phi.contains(frontier) || !live_in[*frontier].contains(var) {
+                    continue;
+                }
+
+                has_phi.insert(*frontier);
+                phi_vars[*frontier].push(var.clone());
+                if !blocks.contains(frontier) {
+                    worklist.push(*frontier);
+                }
+            }
+        }
+    }
+
+    for (block, vars) in phi_vars.iter().enumerate() {
+        let phis = vars
+            .iter()
+            .map(|var| Instruction::Phi(var.clone(), Vec::new(), var_types[var].clone()));
+        cfg.blocks[block].instructions.splice(0..0, phis);
+    }
+
+    let mut renamer = Renamer {
+        stacks: var_types
+            .keys()
+            .map(|var| (var.clone(), Vec::new()))
+            .collect(),
+        counters: HashMap::new(),
+        phi_vars,
+    };
+    renamer.rename(cfg, &dom, 0);
+}
+
+struct Renamer {
+    stacks: HashMap<String, Vec<String>>,
+    counters: HashMap<String, usize>,
+    /// Original variable of every phi, in the order the phis appear in the block
+    phi_vars: Vec<Vec<String>>,
+}
+
+impl Renamer {
+    fn new_version(&mut self, var: &String) -> String {
+        let counter = self.counters.entry(var.clone()).or_insert(0);
+        *counter += 1;
+
+        let version = format!("{}.{}", var, counter);
+        self.stacks.get_mut(var).unwrap().push(version.clone());
+        version
+    }
+
+    fn rename_use(&self, operand: &mut TmpChild) {
+        if let TmpChild::LoadVar(name, _, _) = operand {
+            if let Some(version) = self.stacks.get(name).and_then(|stack| stack.last()) {
+                *name = version.clone();
+            }
+        }
+    }
+
+    fn rename(&mut self, cfg: &mut Cfg, dom: &Dominators, block: usize) {
+        let mut defined = Vec::new();
+        let phi_count = self.phi_vars[block].len();
+
+        let instructions = std::mem::take(&mut cfg.blocks[block].instructions);
+        let mut renamed = Vec::with_capacity(instructions.len());
+        for (index, mut ins) in instructions.into_iter().enumerate() {
+            if index < phi_count {
+                let var = self.phi_vars[block][index].clone();
+                if let Instruction::Phi(name, _, _) = &mut ins {
+                    *name = self.new_version(&var);
+                }
+                defined.push(var);
+                renamed.push(ins);
+                continue;
+            }
+
+            for operand in ins.operands_mut() {
+                self.rename_use(operand);
+            }
+
+            match ins {
+                Instruction::VarDecl(var, value, tipe, _)
+                | Instruction::VarAssign(var, value, tipe, _)
+                    if self.stacks.contains_key(&var) =>
+                {
+                    let version = self.new_version(&var);
+                    defined.push(var);
+                    renamed.push(Instruction::VarDecl(version, value, tipe, None));
+                }
+                ins => renamed.push(ins),
+            }
+        }
+        cfg.blocks[block].instructions = renamed;
+
+        for operand in cfg.blocks[block].terminator.operands_mut() {
+            self.rename_use(operand);
+        }
+
+        for succ in cfg.blocks[block].succs.clone() {
+            for (index, var) in self.phi_vars[succ].iter().enumerate() {
+                let version = self.stacks[var].last().unwrap_or_else(|| {
+                    panic!("Variable {} is used before being defined", var);
+                });
+
+                if let Instruction::Phi(_, incoming, tipe) =
+                    &mut cfg.blocks[succ].instructions[index]
+                {
+                    incoming.push((
+                        block,
+                        TmpChild::LoadVar(version.clone(), tipe.clone(), None),
+                    ));
+                }
+            }
+        }
+
+        for child in dom.children[block].clone() {
+            self.rename(cfg, dom, child);
+        }
+
+        for var in defined {
+            self.stacks.get_mut(&var).unwrap().pop();
+        }
+    }
+}
+
+/// Variables whose current value may be read before being redefined, per block
+fn live_in_vars(cfg: &Cfg) -> Vec<BTreeSet<String>> {
+    let mut uses = vec![BTreeSet::new(); cfg.blocks.len()];
+    let mut defs = vec![BTreeSet::new(); cfg.blocks.len()];
+
+    for block in &cfg.blocks {
+        let block_uses = &mut uses[block.id];
+        let block_defs = &mut defs[block.id];
+
+        let operands = block
+            .instructions
+            .iter()
+            .map(|ins| (ins.operands(), ins.defined_var()))
+            .chain(std::iter::once((block.terminator.operands(), None)));
+
+        for (operands, defined) in operands {
+            for operand in operands {
+                if let TmpChild::LoadVar(name, _, _) = operand {
+                    if !block_defs.contains(name) {
+                        block_uses.insert(name.clone());
+                    }
+                }
+            }
+            if let Some(name) = defined {
+                block_defs.insert(name.clone());
+            }
+        }
+    }
+
+    let mut live_in = uses.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in cfg.blocks.iter().rev() {
+            let mut live = uses[block.id].clone();
+            for succ in &block.succs {
+                for var in &live_in[*succ] {
+                    if !defs[block.id].contains(var) {
+                        live.insert(var.clone());
+                    }
+                }
+            }
+
+            if live.len() != live_in[block.id].len() {
+                live_in[block.id] = live;
+                changed = true;
+            }
+        }
+    }
+
+    live_in
+}
+
+/// Leaves SSA form by replacing every phi with copies at the end of its
+/// predecessors. Critical edges are split first so that a copy never runs on
+/// a path that doesn't lead to the phi.
+pub fn destruct(cfg: &mut Cfg) {
+    split_critical_edges(cfg);
+
+    let mut copies: BTreeMap<usize, Vec<(String, TmpChild, TaggedType)>> = BTreeMap::new();
+    for block in &mut cfg.blocks {
+        block.instructions.retain(|ins| {
+            if let Instruction::Phi(name, incoming, tipe) = ins {
+                for (pred, value) in incoming {
+                    copies.entry(*pred).or_default().push((
+                        name.clone(),
+                        value.clone(),
+                        tipe.clone(),
+                    ));
+                }
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    for (pred, copies) in copies {
+        let instructions = &mut cfg.blocks[pred].instructions;
+
+        // The copies happen in parallel, if one of them reads a variable that
+        // another one writes, go through a temporary for every copy
+        let conflict = copies.iter().any(|(_, value, _)| match value {
+            TmpChild::LoadVar(source, _, _) => copies.iter().any(|(dest, _, _)| dest == source),
+            _ => false,
+        });
+
+        if conflict {
+            for (dest, value, tipe) in &copies {
+                instructions.push(Instruction::VarDecl(
+                    format!("{}.copy", dest),
+                    value.clone(),
+                    tipe.clone(),
+                    None,
+                ));
+            }
+            for (dest, _, tipe) in copies {
+                let source = TmpChild::LoadVar(format!("{}.copy", dest), tipe.clone(), None);
+                instructions.push(Instruction::VarAssign(dest, source, tipe, None));
+            }
+        } else {
+            for (dest, value, tipe) in copies {
+                instructions.push(Instruction::VarAssign(dest, value, tipe, None));
+            }
+        }
+    }
+}
+
+fn split_critical_edges(cfg: &mut Cfg) {
+    for block in 0..cfg.blocks.len() {
+        let has_phis = cfg.blocks[block]
+            .instructions
+            .iter()
+            .any(|ins| std::matches!(ins, Instruction::Phi(_, _, _)));
+        if !has_phis || cfg.blocks[block].preds.len() < 2 {
+            continue;
+        }
+
+        for pred in cfg.blocks[block].preds.clone() {
+            if cfg.blocks[pred].succs.len() < 2 {
+                continue;
+            }
+
+            let split = cfg.blocks.len();
+            cfg.blocks
+                .push(BasicBlock::new(split, Vec::new(), Terminator::Jump(block)));
+            cfg.blocks[pred].terminator.replace_target(block, split);
+
+            for ins in &mut cfg.blocks[block].instructions {
+                if let Instruction::Phi(_, incoming, _) = ins {
+                    for (from, _) in incoming.iter_mut() {
+                        if *from == pred {
+                            *from = split;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    cfg.compute_edges();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssir::tests::lower_one;
+
+    /// The phis of a block as their variable and incoming `(block, variable)`
+    fn phis(block: &BasicBlock) -> Vec<(String, Vec<(usize, String)>)> {
+        block
+            .instructions
+            .iter()
+            .filter_map(|ins| match ins {
+                Instruction::Phi(name, incoming, _) => Some((
+                    name.clone(),
+                    incoming
+                        .iter()
+                        .map(|(pred, value)| match value {
+                            TmpChild::LoadVar(var, _, _) => (*pred, var.clone()),
+                            _ => panic!("{:?}", value),
+                        })
+                        .collect(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The copies of variables in a block as `(dest, source)`
+    fn copies(block: &BasicBlock) -> Vec<(String, String)> {
+        block
+            .instructions
+            .iter()
+            .filter_map(|ins| match ins {
+                Instruction::VarDecl(dest, TmpChild::LoadVar(source, _, _), _, _)
+                | Instruction::VarAssign(dest, TmpChild::LoadVar(source, _, _), _, _) => {
+                    Some((dest.clone(), source.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn pair(dest: &str, source: &str) -> (String, String) {
+        (dest.to_string(), source.to_string())
+    }
+
+    const DIAMOND: &str = "func f(a: i32) -> i32 {
+        x := 1
+        y := 1
+        if a > 0 {
+            x = 2
+            y = 2
+        }
+        ret x
+    }";
+
+    #[test]
+    fn phis_only_merge_variables_that_are_still_live() {
+        let mut cfg = lower_one(DIAMOND);
+        construct(&mut cfg);
+
+        let Terminator::Branch(_, then_block, join) = cfg.blocks[0].terminator else {
+            panic!("{:#?}", cfg.blocks);
+        };
+        let phis = phis(&cfg.blocks[join]);
+        let expected = vec![(0, "x.1".to_string()), (then_block, "x.2".to_string())];
+        assert_eq!(phis, [("x.3".to_string(), expected)], "{:#?}", cfg.blocks);
+
+        // Every definition got its own version
+        let defined = cfg
+            .blocks
+            .iter()
+            .flat_map(|block| block.instructions.iter())
+            .filter_map(|ins| ins.defined_var().cloned())
+            .collect::<BTreeSet<_>>();
+        for version in ["x.1", "x.2", "x.3", "y.1", "y.2"] {
+            assert!(defined.contains(version), "{}: {:?}", version, defined);
+        }
+        assert!(!defined.contains("x") && !defined.contains("y"));
+    }
+
+    #[test]
+    fn copies_on_critical_edges_go_into_a_new_block() {
+        let mut cfg = lower_one(DIAMOND);
+        construct(&mut cfg);
+        let block_count = cfg.blocks.len();
+        let Terminator::Branch(_, then_block, join) = cfg.blocks[0].terminator else {
+            panic!("{:#?}", cfg.blocks);
+        };
+        destruct(&mut cfg);
+
+        // The entry branches to the join too, its copy can't go at its end
+        let split = block_count;
+        assert_eq!(cfg.blocks.len(), block_count + 1, "{:#?}", cfg.blocks);
+        assert!(std::matches!(
+            cfg.blocks[0].terminator,
+            Terminator::Branch(_, then, other) if then == then_block && other == split
+        ));
+        assert!(
+            std::matches!(cfg.blocks[split].terminator, Terminator::Jump(target) if target == join)
+        );
+        assert_eq!(copies(&cfg.blocks[split]), [pair("x.3", "x.1")]);
+        assert_eq!(copies(&cfg.blocks[then_block]), [pair("x.3", "x.2")]);
+        assert!(cfg.blocks.iter().all(|block| phis(block).is_empty()));
+    }
+
+    const SWAP: &str = "func f(s: []i32) -> i32 {
+        a := 1
+        b := 2
+        for v in s {
+            t := a
+            a = b
+            b = t
+        }
+        ret a - b
+    }";
+
+    #[test]
+    fn loop_headers_merge_the_entry_and_the_back_edge() {
+        let mut cfg = lower_one(SWAP);
+        construct(&mut cfg);
+
+        let header = match cfg.blocks[0].terminator {
+            Terminator::Jump(header) => header,
+            _ => panic!("{:#?}", cfg.blocks),
+        };
+        let body = cfg.blocks[header].succs[0];
+        let phis = phis(&cfg.blocks[header]);
+        for (var, entry, back) in [("a.2", "a.1", "a.3"), ("b.2", "b.1", "b.3")] {
+            let expected = vec![(0, entry.to_string()), (body, back.to_string())];
+            assert!(phis.contains(&(var.to_string(), expected)), "{:?}", phis);
+        }
+
+        destruct(&mut cfg);
+        let entry_copies = copies(&cfg.blocks[0]);
+        assert!(
+            entry_copies.contains(&pair("a.2", "a.1")),
+            "{:?}",
+            entry_copies
+        );
+        assert!(
+            entry_copies.contains(&pair("b.2", "b.1")),
+            "{:?}",
+            entry_copies
+        );
+        let body_copies = copies(&cfg.blocks[body]);
+        assert!(
+            body_copies.contains(&pair("a.2", "a.3")),
+            "{:?}",
+            body_copies
+        );
+        assert!(
+            body_copies.contains(&pair("b.2", "b.3")),
+            "{:?}",
+            body_copies
+        );
+    }
+
+    #[test]
+    fn phis_reading_each_other_are_copied_through_temporaries() {
+        let mut cfg = lower_one(SWAP);
+        construct(&mut cfg);
+
+        // What copy propagation makes of the swap, `a.2` and `b.2` trade
+        // places on the back edge
+        let header = cfg.blocks[0].succs[0];
+        let body = cfg.blocks[header].succs[0];
+        for ins in &mut cfg.blocks[header].instructions {
+            if let Instruction::Phi(name, incoming, _) = ins {
+                let other = match name.as_str() {
+                    "a.2" => "b.2",
+                    "b.2" => "a.2",
+                    _ => continue,
+                };
+                for (pred, value) in incoming.iter_mut() {
+                    if let (true, TmpChild::LoadVar(var, _, _)) = (*pred == body, value) {
+                        *var = other.to_string();
+                    }
+                }
+            }
+        }
+        destruct(&mut cfg);
+
+        // Both are read before either is written
+        let body_copies = copies(&cfg.blocks[body]);
+        let position = |copy: (String, String)| {
+            body_copies
+                .iter()
+                .position(|other| *other == copy)
+                .unwrap_or_else(|| panic!("{:?} in {:?}", copy, body_copies))
+        };
+        let reads = position(pair("a.2.copy", "b.2")).max(position(pair("b.2.copy", "a.2")));
+        let writes = position(pair("a.2", "a.2.copy")).min(position(pair("b.2", "b.2.copy")));
+        assert!(reads < writes, "{:?}", body_copies);
+    }
+}
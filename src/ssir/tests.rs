@@ -0,0 +1,231 @@
+//! Lowering of control flow, and the pipeline the pass tests start from
+
+use crate::{parser::Parser, tokenizer::Tokenizer, typechecker::TypeCheck};
+
+use super::{
+    cfg::{BasicBlock, Cfg, Terminator},
+    ins::Instruction,
+    tmp::{TmpChild, TmpNode},
+    SSir,
+};
+
+/// Lowers `source` into one control-flow graph per function, without running
+/// any pass
+pub fn lower(source: &str) -> Vec<Cfg> {
+    let source = source.to_string();
+    let mut parser = Parser::new(Tokenizer::new(&source), &source);
+    parser.parse();
+
+    let mut typecheck = TypeCheck::new();
+    for decl in &mut parser.declarations {
+        typecheck.check(decl);
+    }
+    assert!(!typecheck.has_errors());
+
+    let mut ssir = SSir::new();
+    ssir.generate(&mut parser.declarations);
+    ssir.get_functions().into_iter().map(Cfg::build).collect()
+}
+
+/// The only function of `source`
+pub fn lower_one(source: &str) -> Cfg {
+    lower(source).remove(0)
+}
+
+fn assigns(block: &BasicBlock, name: &str) -> bool {
+    block.instructions.iter().any(|ins| match ins {
+        Instruction::VarAssign(var, _, _, _) => var == name,
+        _ => false,
+    })
+}
+
+#[test]
+fn else_if_chains_branch_once_per_condition_and_join_once() {
+    let cfg = lower_one(
+        "func f(a: i32) -> i32 {
+            r := 0
+            if a < 0 {
+                r = 1
+            } else if a == 0 {
+                r = 2
+            } else {
+                r = 3
+            }
+            ret r
+        }",
+    );
+
+    let branches = cfg
+        .blocks
+        .iter()
+        .filter(|block| std::matches!(block.terminator, Terminator::Branch(..)))
+        .count();
+    assert_eq!(branches, 2, "{:#?}", cfg.blocks);
+
+    let joins = cfg
+        .blocks
+        .iter()
+        .filter(|block| assigns(block, "r"))
+        .map(|block| match block.terminator {
+            Terminator::Jump(target) => target,
+            _ => panic!("{:#?}", block),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(joins.len(), 3, "{:#?}", cfg.blocks);
+    assert!(joins.iter().all(|join| *join == joins[0]), "{:?}", joins);
+    assert!(std::matches!(
+        cfg.blocks[joins[0]].terminator,
+        Terminator::Ret(Some(_))
+    ));
+}
+
+fn loads_memory(block: &BasicBlock) -> bool {
+    block
+        .instructions
+        .iter()
+        .any(|ins| std::matches!(ins, Instruction::TmpNode(TmpNode::LoadTmp(_), _, _)))
+}
+
+#[test]
+fn the_rhs_of_a_logical_operator_only_runs_when_it_decides() {
+    // `&&` only looks at the rhs when the lhs is true, `||` when it's false
+    for (op, compare, decides_on_true) in [("&&", "!=", true), ("||", "==", false)] {
+        let cfg = lower_one(&format!(
+            "func f(p: ptr<i32>) -> bool {{ ret p {} 0 {} *p > 1; }}",
+            compare, op
+        ));
+
+        let entry = &cfg.blocks[0];
+        assert!(!loads_memory(entry), "{}: {:#?}", op, cfg.blocks);
+        let rhs = match entry.terminator {
+            Terminator::Branch(_, then_block, _) if decides_on_true => then_block,
+            Terminator::Branch(_, _, else_block) => else_block,
+            _ => panic!("{}: {:#?}", op, cfg.blocks),
+        };
+        assert!(loads_memory(&cfg.blocks[rhs]), "{}: {:#?}", op, cfg.blocks);
+
+        let loads = cfg
+            .blocks
+            .iter()
+            .filter(|block| loads_memory(block))
+            .count();
+        assert_eq!(loads, 1, "{}: {:#?}", op, cfg.blocks);
+    }
+}
+
+#[test]
+fn any_boolean_expression_is_a_condition() {
+    let cfg = lower_one(
+        "func f(flag: bool) -> i32 {
+            r := 0
+            if flag {
+                r = 1
+            }
+            if true {
+                r = 2
+            }
+            ret r
+        }",
+    );
+
+    // The variable is branched on directly, the literal needs no branch
+    let branches = cfg
+        .blocks
+        .iter()
+        .filter_map(|block| match &block.terminator {
+            Terminator::Branch(cond, _, _) => Some(cond),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(branches.len(), 1, "{:#?}", cfg.blocks);
+    let entry = &cfg.blocks[0];
+    assert!(std::matches!(entry.terminator, Terminator::Branch(..)));
+    assert!(!entry.instructions.iter().any(|ins| std::matches!(
+        ins,
+        Instruction::TmpNode(TmpNode::BinaryTmp(_), _, _)
+            | Instruction::TmpNode(TmpNode::UnaryTmp(_), _, _)
+    )));
+}
+
+/// Index computations and reads of `p` in the function
+fn place_evaluations(cfg: &Cfg) -> (usize, usize) {
+    let instructions = || cfg.blocks.iter().flat_map(|block| &block.instructions);
+    let indices = instructions()
+        .filter(|ins| std::matches!(ins, Instruction::TmpNode(TmpNode::IndexTmp(_), _, _)))
+        .count();
+    let pointer_reads = instructions()
+        .flat_map(|ins| ins.operands())
+        .filter(|operand| std::matches!(operand, TmpChild::LoadVar(name, _, _) if name == "p"))
+        .count();
+    (indices, pointer_reads)
+}
+
+#[test]
+fn compound_assignments_compute_the_address_once() {
+    let function = |op: &str| {
+        format!(
+            "func f(i: i64) -> i32 {{
+                var a: i32[3] = {{1, 2, 3}}
+                a[i] {0} 5
+                x := 4
+                p := &x
+                *p {0} 3
+                ret x
+            }}",
+            op
+        )
+    };
+
+    let plain = place_evaluations(&lower_one(&function("=")));
+    for op in ["+=", "-=", "*=", "%=", "<<=", "^="] {
+        assert_eq!(
+            place_evaluations(&lower_one(&function(op))),
+            plain,
+            "{}",
+            op
+        );
+    }
+}
+
+#[test]
+fn shadowing_variables_get_their_own_name() {
+    let cfg = lower_one(
+        "func f(a: []i32) -> i32 {
+            x := 7
+            if len(a) > 0 {
+                x := 100
+                x = x + 1
+            }
+            for x in a {
+                x = x + 1
+            }
+            ret x
+        }",
+    );
+
+    let instructions = || cfg.blocks.iter().flat_map(|block| &block.instructions);
+    let mut declared: Vec<_> = instructions()
+        .filter_map(|ins| match ins {
+            Instruction::VarDecl(name, _, _, _) if name.starts_with('x') => Some(name),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(declared.len(), 3);
+    declared.sort();
+    declared.dedup();
+    assert_eq!(declared.len(), 3);
+
+    // Only the shadowing variables are assigned, the outer `x` is returned
+    // untouched
+    assert!(!cfg.blocks.iter().any(|block| assigns(block, "x")));
+    let exit = cfg
+        .blocks
+        .iter()
+        .find(|block| std::matches!(block.terminator, Terminator::Ret(_)))
+        .unwrap();
+    assert!(exit
+        .instructions
+        .iter()
+        .flat_map(|ins| ins.operands())
+        .any(|operand| std::matches!(operand, TmpChild::LoadVar(name, _, _) if name == "x")));
+}
@@ -1,13 +1,18 @@
 use crate::{
-    ast::{BinaryOp, LogicalOp, UnaryOp},
+    ast::{BinaryOp, Overflow, UnaryOp},
     reg::RegisterLabel,
     typechecker::TaggedType,
 };
 
+use super::frame::Slot;
+
 #[derive(Debug, Clone)]
 pub enum TmpChild {
     Literal(String, TaggedType),
-    LoadVar(String, TaggedType),
+    /// Read of a variable, annotated with its stack slot by the frame layout
+    LoadVar(String, TaggedType, Option<Slot>),
+    /// Incoming argument of the function, by position
+    Param(usize, TaggedType),
     TmpRef(usize, TaggedType, Option<RegisterLabel>),
     None,
 }
@@ -16,7 +21,14 @@ impl std::fmt::Display for TmpChild {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Literal(l, tipe) => write!(f, "{}{{{}}}", tipe, l),
-            Self::LoadVar(var, tipe) => write!(f, "LOAD {}{{{}}}", tipe, var),
+            Self::LoadVar(var, tipe, slot) => {
+                if let Some(s) = slot {
+                    write!(f, "LOAD {} -> {}{{{}}}", s, tipe, var)
+                } else {
+                    write!(f, "LOAD {}{{{}}}", tipe, var)
+                }
+            }
+            Self::Param(index, tipe) => write!(f, "PARAM {}{{{}}}", tipe, index),
             Self::TmpRef(tmp, tipe, label) => {
                 if let Some(l) = label {
                     write!(f, "{} -> {}{{tmp{}}}", l, tipe, tmp)
@@ -29,23 +41,82 @@ impl std::fmt::Display for TmpChild {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TmpNode {
     BinaryTmp(BinaryTmp),
     ValueTmp(ValueTmp),
     UnaryTmp(UnaryTmp),
-    LogicalTmp(LogicalTmp),
     AssignTmp(AssignTmp),
-    GroupingTmp(GroupingTmp),
+    /// Conversion of the value to the type of the temporary
+    CastTmp(CastTmp),
+    /// Address of a variable, which keeps it in memory
+    AddressTmp(AddressTmp),
+    /// Read of the memory an address points to, as the type of the temporary
+    LoadTmp(LoadTmp),
+    /// Address of an array element, panics when the index is out of bounds.
+    /// Ranges check their bounds the same way.
+    IndexTmp(IndexTmp),
+}
+
+impl TmpNode {
+    pub fn id(&self) -> usize {
+        match self {
+            Self::BinaryTmp(binary) => binary.id,
+            Self::ValueTmp(value) => value.id,
+            Self::UnaryTmp(unary) => unary.id,
+            Self::AssignTmp(assign) => assign.id,
+            Self::CastTmp(cast) => cast.id,
+            Self::AddressTmp(address) => address.id,
+            Self::LoadTmp(load) => load.id,
+            Self::IndexTmp(index) => index.id,
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&TmpChild> {
+        match self {
+            Self::BinaryTmp(binary) => vec![&binary.lhs, &binary.rhs],
+            Self::ValueTmp(value) => vec![&value.value],
+            Self::UnaryTmp(unary) => vec![&unary.value],
+            Self::AssignTmp(assign) => vec![&assign.value],
+            Self::CastTmp(cast) => vec![&cast.value],
+            Self::AddressTmp(_) => vec![],
+            Self::LoadTmp(load) => vec![&load.address],
+            Self::IndexTmp(index) => vec![&index.base, &index.index, &index.length],
+        }
+    }
+
+    /// Whether computing the node can panic, it has to stay even when its
+    /// value is never used
+    pub fn may_trap(&self) -> bool {
+        match self {
+            Self::BinaryTmp(binary) => binary.overflow == Overflow::Checked,
+            Self::IndexTmp(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut TmpChild> {
+        match self {
+            Self::BinaryTmp(binary) => vec![&mut binary.lhs, &mut binary.rhs],
+            Self::ValueTmp(value) => vec![&mut value.value],
+            Self::UnaryTmp(unary) => vec![&mut unary.value],
+            Self::AssignTmp(assign) => vec![&mut assign.value],
+            Self::CastTmp(cast) => vec![&mut cast.value],
+            Self::AddressTmp(_) => vec![],
+            Self::LoadTmp(load) => vec![&mut load.address],
+            Self::IndexTmp(index) => vec![&mut index.base, &mut index.index, &mut index.length],
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BinaryTmp {
     pub lhs: TmpChild,
     pub rhs: TmpChild,
     pub op: BinaryOp,
     pub id: usize,
     pub tipe: TaggedType,
+    pub overflow: Overflow,
 }
 
 impl BinaryTmp {
@@ -55,6 +126,7 @@ impl BinaryTmp {
         op: BinaryOp,
         id: usize,
         tipe: TaggedType,
+        overflow: Overflow,
     ) -> BinaryTmp {
         BinaryTmp {
             lhs,
@@ -62,11 +134,12 @@ impl BinaryTmp {
             op,
             id,
             tipe,
+            overflow,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ValueTmp {
     pub value: TmpChild,
     pub id: usize,
@@ -78,7 +151,7 @@ impl ValueTmp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UnaryTmp {
     pub value: TmpChild,
     pub op: UnaryOp,
@@ -91,41 +164,108 @@ impl UnaryTmp {
     }
 }
 
-#[derive(Debug)]
-pub struct LogicalTmp {
-    pub lhs: TmpChild,
-    pub rhs: TmpChild,
-    pub op: LogicalOp,
+#[derive(Debug, Clone)]
+pub struct AssignTmp {
+    pub value: TmpChild,
     pub id: usize,
 }
 
-impl LogicalTmp {
-    pub fn new(lhs: TmpChild, rhs: TmpChild, op: LogicalOp, id: usize) -> LogicalTmp {
-        LogicalTmp { lhs, rhs, op, id }
+impl AssignTmp {
+    pub fn new(value: TmpChild, id: usize) -> AssignTmp {
+        AssignTmp { value, id }
     }
 }
 
-#[derive(Debug)]
-pub struct AssignTmp {
+#[derive(Debug, Clone)]
+pub struct CastTmp {
     pub value: TmpChild,
     pub id: usize,
 }
 
-impl AssignTmp {
-    pub fn new(value: TmpChild, id: usize) -> AssignTmp {
-        AssignTmp { value, id }
+impl CastTmp {
+    pub fn new(value: TmpChild, id: usize) -> CastTmp {
+        CastTmp { value, id }
     }
 }
 
-#[derive(Debug)]
-pub struct GroupingTmp {
-    pub expr: TmpChild,
+#[derive(Debug, Clone)]
+pub struct AddressTmp {
+    pub var: String,
     pub id: usize,
-    pub tipe: TaggedType,
+    /// Filled in by the frame layout, like the slot of a `LoadVar`
+    pub slot: Option<Slot>,
+}
+
+impl AddressTmp {
+    pub fn new(var: String, id: usize) -> AddressTmp {
+        AddressTmp {
+            var,
+            id,
+            slot: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadTmp {
+    pub address: TmpChild,
+    pub id: usize,
+}
+
+impl LoadTmp {
+    pub fn new(address: TmpChild, id: usize) -> LoadTmp {
+        LoadTmp { address, id }
+    }
+}
+
+/// What the index of an `IndexTmp` is checked against its length for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    /// An element, the index has to be below the length
+    Element,
+    /// The end of a range, which can be the length itself
+    RangeEnd,
+    /// The start of a range, whose "length" is the end of the range
+    RangeStart,
+}
+
+impl std::fmt::Display for Bound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Element => write!(f, "below"),
+            Self::RangeEnd | Self::RangeStart => write!(f, "up to"),
+        }
+    }
 }
 
-impl GroupingTmp {
-    pub fn new(expr: TmpChild, tipe: TaggedType, id: usize) -> GroupingTmp {
-        GroupingTmp { expr, id, tipe }
+#[derive(Debug, Clone)]
+pub struct IndexTmp {
+    /// Address of the array
+    pub base: TmpChild,
+    pub index: TmpChild,
+    pub length: TmpChild,
+    /// Size of an element
+    pub stride: usize,
+    pub bound: Bound,
+    pub id: usize,
+}
+
+impl IndexTmp {
+    pub fn new(
+        base: TmpChild,
+        index: TmpChild,
+        length: TmpChild,
+        stride: usize,
+        bound: Bound,
+        id: usize,
+    ) -> IndexTmp {
+        IndexTmp {
+            base,
+            index,
+            length,
+            stride,
+            bound,
+            id,
+        }
     }
 }
@@ -3,6 +3,9 @@ use crate::typechecker::TaggedType;
 #[derive(Debug, Clone)]
 pub struct Variable {
     name: String,
+    /// Name in the SSIR, a variable shadowing another one gets a suffix so
+    /// that the two don't share a slot
+    pub ssir_name: String,
     scope_level: usize,
     pub tagged_type: TaggedType,
 }
@@ -10,6 +13,7 @@ pub struct Variable {
 impl Variable {
     pub fn new(name: String, tagged_type: TaggedType) -> Variable {
         Variable {
+            ssir_name: name.clone(),
             name,
             scope_level: 0,
             tagged_type,
@@ -21,6 +25,8 @@ impl Variable {
 pub struct VarTable {
     variables: Vec<Variable>,
     scope_level: usize,
+    /// Number of variables that shadowed another one so far
+    shadowing: usize,
 }
 
 impl VarTable {
@@ -28,12 +34,21 @@ impl VarTable {
         VarTable {
             variables: Vec::new(),
             scope_level: 0,
+            shadowing: 0,
         }
     }
 
-    pub fn add_var(&mut self, mut var: Variable) {
+    /// Declares the variable in the current scope and returns its SSIR name
+    pub fn add_var(&mut self, mut var: Variable) -> String {
+        if self.get_var(var.name.clone()).is_some() {
+            self.shadowing += 1;
+            var.ssir_name = format!("{}${}", var.name, self.shadowing);
+        }
+
         var.scope_level = self.scope_level;
+        let ssir_name = var.ssir_name.clone();
         self.variables.push(var);
+        ssir_name
     }
 
     pub fn add_scope(&mut self) {
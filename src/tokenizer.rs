@@ -23,6 +23,22 @@ pub enum TokenKind {
     Minus(usize, usize),
     Star(usize, usize),
     Slash(usize, usize),
+    Percent(usize, usize),
+    Pipe(usize, usize),
+    Caret(usize, usize),
+    Tilde(usize, usize),
+    ShiftLeft(usize, usize),
+    ShiftRight(usize, usize),
+    PlusEq(usize, usize),
+    MinusEq(usize, usize),
+    StarEq(usize, usize),
+    SlashEq(usize, usize),
+    PercentEq(usize, usize),
+    AmpersandEq(usize, usize),
+    PipeEq(usize, usize),
+    CaretEq(usize, usize),
+    ShiftLeftEq(usize, usize),
+    ShiftRightEq(usize, usize),
     True(usize, usize),
     False(usize, usize),
     Bang(usize, usize),
@@ -37,11 +53,13 @@ pub enum TokenKind {
     Equal(usize, usize),
     For(usize, usize),
     In(usize, usize),
+    As(usize, usize),
     If(usize, usize),
     Else(usize, usize),
     Var(usize, usize),
     ExprDelimiter(usize, usize),
-    GetPtr(usize, usize),
+    /// Address-of as a prefix, bitwise and between two operands
+    Ampersand(usize, usize),
     Eof,
 }
 
@@ -80,13 +98,30 @@ pub fn get_tok_loc(token: &TokenKind) -> (usize, usize) {
         TokenKind::Equal(a, b) => (*a, *b),
         TokenKind::And(a, b) => (*a, *b),
         TokenKind::Or(a, b) => (*a, *b),
+        TokenKind::Percent(a, b) => (*a, *b),
+        TokenKind::Pipe(a, b) => (*a, *b),
+        TokenKind::Caret(a, b) => (*a, *b),
+        TokenKind::Tilde(a, b) => (*a, *b),
+        TokenKind::ShiftLeft(a, b) => (*a, *b),
+        TokenKind::ShiftRight(a, b) => (*a, *b),
+        TokenKind::PlusEq(a, b) => (*a, *b),
+        TokenKind::MinusEq(a, b) => (*a, *b),
+        TokenKind::StarEq(a, b) => (*a, *b),
+        TokenKind::SlashEq(a, b) => (*a, *b),
+        TokenKind::PercentEq(a, b) => (*a, *b),
+        TokenKind::AmpersandEq(a, b) => (*a, *b),
+        TokenKind::PipeEq(a, b) => (*a, *b),
+        TokenKind::CaretEq(a, b) => (*a, *b),
+        TokenKind::ShiftLeftEq(a, b) => (*a, *b),
+        TokenKind::ShiftRightEq(a, b) => (*a, *b),
         TokenKind::For(a, b) => (*a, *b),
         TokenKind::In(a, b) => (*a, *b),
+        TokenKind::As(a, b) => (*a, *b),
         TokenKind::If(a, b) => (*a, *b),
         TokenKind::Else(a, b) => (*a, *b),
         TokenKind::Var(a, b) => (*a, *b),
         TokenKind::ExprDelimiter(a, b) => (*a, *b),
-        TokenKind::GetPtr(a, b) => (*a, *b),
+        TokenKind::Ampersand(a, b) => (*a, *b),
         TokenKind::LeftBracket(a, b) => (*a, *b),
         TokenKind::RightBracket(a, b) => (*a, *b),
         TokenKind::Eof => panic!("Unsupported token"),
@@ -116,6 +151,22 @@ pub fn get_tok_len(token: &TokenKind) -> usize {
         TokenKind::Minus(_, _) => 1,
         TokenKind::Star(_, _) => 1,
         TokenKind::Slash(_, _) => 1,
+        TokenKind::Percent(_, _) => 1,
+        TokenKind::Pipe(_, _) => 1,
+        TokenKind::Caret(_, _) => 1,
+        TokenKind::Tilde(_, _) => 1,
+        TokenKind::ShiftLeft(_, _) => 2,
+        TokenKind::ShiftRight(_, _) => 2,
+        TokenKind::PlusEq(_, _) => 2,
+        TokenKind::MinusEq(_, _) => 2,
+        TokenKind::StarEq(_, _) => 2,
+        TokenKind::SlashEq(_, _) => 2,
+        TokenKind::PercentEq(_, _) => 2,
+        TokenKind::AmpersandEq(_, _) => 2,
+        TokenKind::PipeEq(_, _) => 2,
+        TokenKind::CaretEq(_, _) => 2,
+        TokenKind::ShiftLeftEq(_, _) => 3,
+        TokenKind::ShiftRightEq(_, _) => 3,
         TokenKind::True(_, _) => 4,
         TokenKind::False(_, _) => 5,
         TokenKind::Bang(_, _) => 1,
@@ -130,11 +181,12 @@ pub fn get_tok_len(token: &TokenKind) -> usize {
         TokenKind::Or(_, _) => 2,
         TokenKind::For(_, _) => 3,
         TokenKind::In(_, _) => 2,
+        TokenKind::As(_, _) => 2,
         TokenKind::If(_, _) => 2,
         TokenKind::Else(_, _) => 4,
         TokenKind::Var(_, _) => 3,
         TokenKind::ExprDelimiter(_, _) => 1,
-        TokenKind::GetPtr(_, _) => 1,
+        TokenKind::Ampersand(_, _) => 1,
         TokenKind::LeftBracket(_, _) => 1,
         TokenKind::RightBracket(_, _) => 1,
         TokenKind::Eof => 0,
@@ -320,6 +372,7 @@ impl<'a> Tokenizer<'a> {
             "false" => return TokenKind::False(self.line, self.column),
             "for" => return TokenKind::For(self.line, self.column),
             "in" => return TokenKind::In(self.line, self.column),
+            "as" => return TokenKind::As(self.line, self.column),
             "if" => return TokenKind::If(self.line, self.column),
             "else" => return TokenKind::Else(self.line, self.column),
             "var" => return TokenKind::Var(self.line, self.column),
@@ -382,14 +435,39 @@ impl<'a> Iterator for Tokenizer<'a> {
             }),
             '.' => Some(TokenKind::Dot(self.line, self.column)),
             ',' => Some(TokenKind::Comma(self.line, self.column)),
-            '+' => Some(TokenKind::Plus(self.line, self.column)),
+            '+' => Some(if self.matches('=') {
+                TokenKind::PlusEq(self.line, self.column)
+            } else {
+                TokenKind::Plus(self.line, self.column)
+            }),
             '-' => Some(if self.matches('>') {
                 TokenKind::Arrow(self.line, self.column)
+            } else if self.matches('=') {
+                TokenKind::MinusEq(self.line, self.column)
             } else {
                 TokenKind::Minus(self.line, self.column)
             }),
-            '*' => Some(TokenKind::Star(self.line, self.column)),
-            '/' => Some(TokenKind::Slash(self.line, self.column)),
+            '*' => Some(if self.matches('=') {
+                TokenKind::StarEq(self.line, self.column)
+            } else {
+                TokenKind::Star(self.line, self.column)
+            }),
+            '/' => Some(if self.matches('=') {
+                TokenKind::SlashEq(self.line, self.column)
+            } else {
+                TokenKind::Slash(self.line, self.column)
+            }),
+            '%' => Some(if self.matches('=') {
+                TokenKind::PercentEq(self.line, self.column)
+            } else {
+                TokenKind::Percent(self.line, self.column)
+            }),
+            '^' => Some(if self.matches('=') {
+                TokenKind::CaretEq(self.line, self.column)
+            } else {
+                TokenKind::Caret(self.line, self.column)
+            }),
+            '~' => Some(TokenKind::Tilde(self.line, self.column)),
             '"' => Some(self.string()),
             '!' => Some(if self.matches('=') {
                 TokenKind::NotEqual(self.line, self.column)
@@ -398,11 +476,23 @@ impl<'a> Iterator for Tokenizer<'a> {
             }),
             '>' => Some(if self.matches('=') {
                 TokenKind::GreaterEq(self.line, self.column)
+            } else if self.matches('>') {
+                if self.matches('=') {
+                    TokenKind::ShiftRightEq(self.line, self.column)
+                } else {
+                    TokenKind::ShiftRight(self.line, self.column)
+                }
             } else {
                 TokenKind::Greater(self.line, self.column)
             }),
             '<' => Some(if self.matches('=') {
                 TokenKind::LessEq(self.line, self.column)
+            } else if self.matches('<') {
+                if self.matches('=') {
+                    TokenKind::ShiftLeftEq(self.line, self.column)
+                } else {
+                    TokenKind::ShiftLeft(self.line, self.column)
+                }
             } else {
                 TokenKind::Less(self.line, self.column)
             }),
@@ -413,13 +503,17 @@ impl<'a> Iterator for Tokenizer<'a> {
             }),
             '|' => Some(if self.matches('|') {
                 TokenKind::Or(self.line, self.column)
+            } else if self.matches('=') {
+                TokenKind::PipeEq(self.line, self.column)
             } else {
-                return None;
+                TokenKind::Pipe(self.line, self.column)
             }),
             '&' => Some(if self.matches('&') {
                 TokenKind::And(self.line, self.column)
+            } else if self.matches('=') {
+                TokenKind::AmpersandEq(self.line, self.column)
             } else {
-                TokenKind::GetPtr(self.line, self.column)
+                TokenKind::Ampersand(self.line, self.column)
             }),
             ';' => Some(TokenKind::ExprDelimiter(self.line, self.column)),
             '\n' => {
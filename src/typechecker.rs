@@ -1,10 +1,7 @@
-use std::{
-    collections::HashMap,
-    num::{ParseFloatError, ParseIntError},
-};
+use std::collections::HashMap;
 
 use crate::{
-    ast::{BinaryOp, Node, UnaryOp},
+    ast::{BinaryOp, Cast, Deref, Index, Node, Overflow, Slice, UnaryOp},
     error::{self},
 };
 
@@ -14,6 +11,9 @@ pub enum TypeKind {
     Float,
     Bool,
     Textual,
+    Pointer,
+    Array,
+    Slice,
     None,
 }
 
@@ -23,6 +23,12 @@ pub struct Type {
     pub size: usize,
     pub kind: TypeKind,
     pub signed: Option<bool>,
+    /// Type a pointer points to
+    pub pointee: Option<Box<Type>>,
+    /// Type of the elements of an array or a slice
+    pub element: Option<Box<Type>>,
+    /// Number of elements of an array
+    pub length: usize,
 }
 
 impl Type {
@@ -32,8 +38,75 @@ impl Type {
             size,
             kind,
             signed,
+            pointee: None,
+            element: None,
+            length: 0,
+        }
+    }
+
+    /// `ptr<T>`, an address that is 8 bytes wide whatever it points to
+    pub fn pointer(pointee: Type) -> Type {
+        Type {
+            name: format!("ptr<{}>", pointee.name),
+            size: 8,
+            kind: TypeKind::Pointer,
+            signed: Some(false),
+            pointee: Some(Box::new(pointee)),
+            element: None,
+            length: 0,
+        }
+    }
+
+    /// `T[N]`, `N` elements of `T` one after the other
+    pub fn array(element: Type, length: usize) -> Type {
+        // The outermost length is written first, `i32[3][4]` is three `i32[4]`
+        let name = match array_bracket(&element.name) {
+            Some(bracket) => format!(
+                "{}[{}]{}",
+                &element.name[..bracket],
+                length,
+                &element.name[bracket..]
+            ),
+            None => format!("{}[{}]", element.name, length),
+        };
+        Type {
+            name,
+            size: element.size * length,
+            kind: TypeKind::Array,
+            signed: None,
+            pointee: None,
+            element: Some(Box::new(element)),
+            length,
+        }
+    }
+
+    /// `[]T`, the address of the first element and the number of elements
+    pub fn slice(element: Type) -> Type {
+        Type {
+            name: format!("[]{}", element.name),
+            size: 16,
+            kind: TypeKind::Slice,
+            signed: None,
+            pointee: None,
+            element: Some(Box::new(element)),
+            length: 0,
+        }
+    }
+}
+
+/// Position of the bracket holding the outermost length of an array type's
+/// name, brackets of a pointee don't count
+pub fn array_bracket(name: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (position, character) in name.char_indices() {
+        match character {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            '[' if depth == 0 => return Some(position),
+            _ => (),
         }
     }
+    None
 }
 
 impl PartialEq for Type {
@@ -78,6 +151,11 @@ impl TaggedType {
     pub fn new(size: usize, kind: TypeKind, signed: Option<bool>) -> TaggedType {
         TaggedType { size, kind, signed }
     }
+
+    /// Booleans count as unsigned
+    pub fn is_signed(&self) -> bool {
+        self.signed == Some(true)
+    }
 }
 
 impl Default for TaggedType {
@@ -92,13 +170,20 @@ impl Default for TaggedType {
 
 impl From<Type> for TaggedType {
     fn from(t: Type) -> Self {
-        Self::new(t.size, t.kind, t.signed)
+        Self::from(&t)
     }
 }
 
+/// Past the typechecker a pointer is just an unsigned 64 bit address and an
+/// array or a slice just the memory it takes
 impl From<&Type> for TaggedType {
     fn from(t: &Type) -> Self {
-        Self::new(t.size, t.kind, t.signed)
+        match t.kind {
+            TypeKind::Pointer => Self::new(8, TypeKind::Numeric, Some(false)),
+            TypeKind::Array => Self::new(t.size, TypeKind::Array, None),
+            TypeKind::Slice => Self::new(t.size, TypeKind::Slice, None),
+            kind => Self::new(t.size, kind, t.signed),
+        }
     }
 }
 
@@ -114,26 +199,74 @@ impl std::fmt::Display for TaggedType {
                 }
                 write!(f, "{}", self.size * 8)
             }
+            TypeKind::Float => {
+                if self.size == 4 {
+                    write!(f, "f32")
+                } else {
+                    write!(f, "f64")
+                }
+            }
             TypeKind::Bool => {
                 write!(f, "bool")
             }
+            TypeKind::Array => write!(f, "[u8; {}]", self.size),
+            TypeKind::Slice => write!(f, "slice"),
             _ => unimplemented!(),
         }
     }
 }
 
-fn bad_int(err: ParseIntError, literal: String, type_name: &'static str) -> ! {
-    error::panic(format!(
-        "Failed to parse {} as an {} because {}",
-        literal, type_name, err
-    ))
+/// Range of the values an integer type holds
+fn int_range(tipe: &Type) -> (i128, i128) {
+    let bits = tipe.size as u32 * 8;
+    if tipe.signed.unwrap_or(false) {
+        (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+    } else {
+        (0, (1 << bits) - 1)
+    }
 }
 
-fn bad_float(err: ParseFloatError, literal: String, type_name: &'static str) -> ! {
-    error::panic(format!(
-        "Failed to parse {} as an {} because {}",
-        literal, type_name, err
-    ))
+/// Whether every value of the integer type `from` is also a value of `to`
+fn holds_all_of(to: &Type, from: &Type) -> bool {
+    match (from.signed.unwrap_or(false), to.signed.unwrap_or(false)) {
+        (true, false) => false,
+        (false, true) => to.size > from.size,
+        _ => to.size >= from.size,
+    }
+}
+
+/// Value of an index or a range bound that is a literal
+fn literal_position(node: &Node) -> Option<usize> {
+    match node {
+        Node::Number(literal, _, _, _) => literal.parse::<usize>().ok(),
+        _ => None,
+    }
+}
+
+/// Whether the expression only combines literals, it then takes its type from
+/// where it is used
+fn is_literal(node: &Node) -> bool {
+    match node {
+        Node::Number(_, _, _, _) | Node::Float(_, _, _, _) => true,
+        Node::Unary(unary) => match unary.op {
+            UnaryOp::Negate | UnaryOp::BitNot => is_literal(&unary.expr),
+            _ => false,
+        },
+        Node::Binary(binary) => {
+            !binary.op.is_comparison() && is_literal(&binary.lhs) && is_literal(&binary.rhs)
+        }
+        Node::Grouping(grouping) => is_literal(&grouping.expr),
+        _ => false,
+    }
+}
+
+/// `0`, which converts to the null pointer
+fn is_null(node: &Node) -> bool {
+    match node {
+        Node::Number(value, _, _, _) => value == "0",
+        Node::Grouping(grouping) => is_null(&grouping.expr),
+        _ => false,
+    }
 }
 
 type TypeMap = HashMap<String, Type>;
@@ -142,7 +275,14 @@ type LocalsMap = HashMap<String, Type>;
 pub struct TypeCheck {
     types: TypeMap,
     locals: LocalsMap,
-    created_locals: Option<Vec<String>>,
+    /// Locals declared in the current block, with the type of the local they
+    /// shadow
+    created_locals: Option<Vec<(String, Option<Type>)>>,
+    ret_type: Option<Type>,
+    /// Type the context wants the node being checked to have, literals
+    /// without one of their own take it
+    expected: Option<Type>,
+    errors: usize,
 }
 
 impl TypeCheck {
@@ -151,6 +291,9 @@ impl TypeCheck {
             types: HashMap::new(),
             locals: HashMap::new(),
             created_locals: None,
+            ret_type: None,
+            expected: None,
+            errors: 0,
         };
 
         container.create_type(Type::new(
@@ -202,18 +345,7 @@ impl TypeCheck {
             TypeKind::Numeric,
             Some(false),
         ));
-        container.create_type(Type::new(
-            "f64".to_string(),
-            16,
-            TypeKind::Float,
-            Some(true),
-        ));
-        container.create_type(Type::new(
-            "ptr".to_string(),
-            8,
-            TypeKind::Numeric,
-            Some(false),
-        ));
+        container.create_type(Type::new("f64".to_string(), 8, TypeKind::Float, Some(true)));
         container.create_type(Type::new("bool".to_string(), 1, TypeKind::Bool, None));
         container.create_type(Type::new("void".to_string(), 0, TypeKind::None, None));
         container
@@ -226,6 +358,24 @@ impl TypeCheck {
     pub fn resolve_type(&self, name: &String) -> Type {
         if let Some(tipe) = self.types.get(name) {
             tipe.clone()
+        } else if let Some(element) = name.strip_prefix("[]") {
+            Type::slice(self.resolve_type(&element.to_string()))
+        } else if let Some(bracket) = array_bracket(name) {
+            let close = bracket + name[bracket..].find(']').unwrap();
+            let element = format!("{}{}", &name[..bracket], &name[close + 1..]);
+            let length = match name[bracket + 1..close].parse::<usize>() {
+                Ok(length) => length,
+                Err(_) => error::panic(format!(
+                    "Array type {} needs a length, only an array literal can leave it out",
+                    name
+                )),
+            };
+            Type::array(self.resolve_type(&element), length)
+        } else if let Some(pointee) = name
+            .strip_prefix("ptr<")
+            .and_then(|name| name.strip_suffix('>'))
+        {
+            Type::pointer(self.resolve_type(&pointee.to_string()))
         } else {
             error::panic(format!("Undefined reference to type: {}", name));
         }
@@ -239,71 +389,187 @@ impl TypeCheck {
         }
     }
 
-    fn overwrite_type(&mut self, node: &mut Box<Node>, new_type: &Type) {
+    /// Logs an error the checking can continue after, compilation stops once
+    /// the whole program has been checked
+    fn report(&mut self, message: String) {
+        log::error!("{}", message);
+        self.errors += 1;
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+
+    /// Arrays are only used through their elements or their address, there
+    /// are no copies of a whole array
+    fn check_not_array(&self, node: &Node, tipe: &Type) {
+        if tipe.kind == TypeKind::Array {
+            let (line, column) = node.loc().unwrap_or_default();
+            error::panic(format!(
+                "{}:{} Cannot use the array {} as a value, only its elements or its address",
+                line, column, tipe.name
+            ));
+        }
+    }
+
+    /// Type of a node that names memory, which may be a whole array when its
+    /// elements or its address are taken next
+    fn check_place(&mut self, node: &mut Box<Node>) -> Type {
         match &mut **node {
-            Node::Number(literal, size, _, _) => {
-                *size = new_type.into();
+            Node::VarGet(name, _, _) => self.resolve_local(name),
+            Node::Index(index) => self.check_index(index),
+            Node::Deref(deref) => self.check_deref(deref),
+            Node::Grouping(grouping) => self.check_place(&mut grouping.expr),
+            _ => self.check(node),
+        }
+    }
 
-                match new_type.name.as_str() {
-                    "u8" => {
-                        literal
-                            .parse::<u8>()
-                            .unwrap_or_else(|err| bad_int(err, literal.clone(), "u8"));
-                    }
-                    "i8" => {
-                        literal
-                            .parse::<i8>()
-                            .unwrap_or_else(|err| bad_int(err, literal.clone(), "i8"));
-                    }
-                    "u16" => {
-                        literal
-                            .parse::<u16>()
-                            .unwrap_or_else(|err| bad_int(err, literal.clone(), "u16"));
-                    }
-                    "i16" => {
-                        literal
-                            .parse::<i16>()
-                            .unwrap_or_else(|err| bad_int(err, literal.clone(), "i16"));
-                    }
-                    "u32" => {
-                        literal
-                            .parse::<u32>()
-                            .unwrap_or_else(|err| bad_int(err, literal.clone(), "u32"));
-                    }
-                    "i32" => {
-                        literal
-                            .parse::<i32>()
-                            .unwrap_or_else(|err| bad_int(err, literal.clone(), "i32"));
-                    }
-                    "u64" => {
-                        literal
-                            .parse::<u64>()
-                            .unwrap_or_else(|err| bad_int(err, literal.clone(), "u64"));
-                    }
-                    "i64" => {
-                        literal
-                            .parse::<i64>()
-                            .unwrap_or_else(|err| bad_int(err, literal.clone(), "i64"));
-                    }
-                    _ => (),
-                };
+    fn check_deref(&mut self, deref: &mut Deref) -> Type {
+        let tipe = self.check(&mut deref.expr);
+        let Some(pointee) = tipe.pointee else {
+            error::panic(format!(
+                "{}:{} Cannot dereference {}, it isn't a pointer",
+                deref.loc.0, deref.loc.1, tipe.name
+            ));
+        };
+        deref.dtype = (&*pointee).into();
+        *pointee
+    }
+
+    fn check_index(&mut self, index: &mut Index) -> Type {
+        let tipe = self.check_place(&mut index.expr);
+        let Some(element) = tipe.element.clone() else {
+            error::panic(format!(
+                "{}:{} Cannot index {}, it isn't an array or a slice",
+                index.loc.0, index.loc.1, tipe.name
+            ));
+        };
+        self.check_position(&mut index.index, index.loc);
+
+        // Only the length of an array is known
+        let length = (tipe.kind == TypeKind::Array).then_some(tipe.length);
+        if let (Some(position), Some(length)) = (literal_position(&index.index), length) {
+            if position >= length {
+                let (line, column) = index.index.loc().unwrap_or_default();
+                self.report(format!(
+                    "{}:{} Index {} is out of bounds for {}, whose length is {}",
+                    line, column, position, tipe.name, length
+                ));
             }
-            Node::Float(literal, size, _, _) => {
-                *size = new_type.into();
+        }
 
-                match new_type.name.as_str() {
-                    "f64" => {
-                        literal
-                            .parse::<f64>()
-                            .unwrap_or_else(|err| bad_float(err, literal.clone(), "f64"));
-                    }
-                    "f32" => {
-                        literal
-                            .parse::<f64>()
-                            .unwrap_or_else(|err| bad_float(err, literal.clone(), "f32"));
-                    }
-                    _ => (),
+        index.dtype = (&*element).into();
+        index.length = length;
+        *element
+    }
+
+    fn check_slice(&mut self, slice: &mut Slice) -> Type {
+        let tipe = self.check_place(&mut slice.expr);
+        let Some(element) = tipe.element.clone() else {
+            error::panic(format!(
+                "{}:{} Cannot slice {}, it isn't an array or a slice",
+                slice.loc.0, slice.loc.1, tipe.name
+            ));
+        };
+        for bound in [&mut slice.start, &mut slice.end].into_iter().flatten() {
+            self.check_position(bound, slice.loc);
+        }
+
+        let length = (tipe.kind == TypeKind::Array).then_some(tipe.length);
+        let start = slice
+            .start
+            .as_ref()
+            .map_or(Some(0), |start| literal_position(start));
+        let end = match &slice.end {
+            Some(end) => literal_position(end),
+            None => length,
+        };
+        let (line, column) = slice.loc;
+        if let (Some(end), Some(length)) = (end, length) {
+            if end > length {
+                self.report(format!(
+                    "{}:{} Range end {} is out of bounds for {}, whose length is {}",
+                    line, column, end, tipe.name, length
+                ));
+            }
+        }
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                self.report(format!(
+                    "{}:{} Range starts at {} but ends at {}",
+                    line, column, start, end
+                ));
+            }
+        }
+
+        slice.dtype = (&*element).into();
+        slice.length = length;
+        Type::slice(*element)
+    }
+
+    /// Indices and range bounds can be any integer, they are converted to
+    /// `u64` and negative ones end up out of bounds
+    fn check_position(&mut self, node: &mut Box<Node>, loc: (usize, usize)) {
+        let u64_type = self.resolve_type(&"u64".to_string());
+        let tipe = self.check_expecting(node, Some(u64_type.clone()));
+        if tipe.kind != TypeKind::Numeric {
+            error::panic(format!(
+                "{}:{} Array index must be an integer, not {}",
+                loc.0, loc.1, tipe.name
+            ));
+        }
+        if is_literal(node) {
+            self.coerce(node, &tipe, &u64_type);
+        } else if tipe != u64_type {
+            self.insert_cast(node, &u64_type);
+        }
+    }
+
+    /// Reports a literal that doesn't fit in its type
+    fn check_literal(&mut self, node: &Node, tipe: &Type) {
+        let (line, column) = node.loc().unwrap_or_default();
+        match node {
+            Node::Number(literal, _, _, _) => {
+                let (min, max) = int_range(tipe);
+                let fits = literal
+                    .parse::<i128>()
+                    .is_ok_and(|value| value >= min && value <= max);
+                if !fits {
+                    self.report(format!(
+                        "{}:{} Literal {} doesn't fit in {}, whose range is {}..={}",
+                        line, column, literal, tipe.name, min, max
+                    ));
+                }
+            }
+            Node::Float(literal, _, _, _) => {
+                let value = literal.parse::<f64>().unwrap_or(f64::INFINITY);
+                let value = if tipe.size == 4 {
+                    value as f32 as f64
+                } else {
+                    value
                 };
+                if value.is_infinite() {
+                    self.report(format!(
+                        "{}:{} Literal {} overflows {}",
+                        line, column, literal, tipe.name
+                    ));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Checks `node` where a value of type `expected` is wanted
+    fn check_expecting(&mut self, node: &mut Box<Node>, expected: Option<Type>) -> Type {
+        self.expected = expected;
+        self.check(node)
+    }
+
+    fn overwrite_type(&mut self, node: &mut Box<Node>, new_type: &Type) {
+        match &mut **node {
+            Node::Number(_, size, _, _) | Node::Float(_, size, _, _) => {
+                *size = new_type.into();
+                self.check_literal(node, new_type);
             }
             Node::Binary(binary) => {
                 self.overwrite_type(&mut binary.lhs, new_type);
@@ -319,20 +585,82 @@ impl TypeCheck {
             Node::Assign(assign) => {
                 self.overwrite_type(&mut assign.value, new_type);
             }
+            Node::Grouping(grouping) => {
+                self.overwrite_type(&mut grouping.expr, new_type);
+            }
             _ => (),
         }
     }
 
+    /// Converts the value of `node` from `from` to `to` where that doesn't
+    /// need an `as` cast: literals take the new type and values of a type
+    /// `to` can represent all of are widened by an inserted `Cast`. Returns
+    /// false if the types are of different kinds, a conversion that can change
+    /// the value is an error.
+    fn coerce(&mut self, node: &mut Box<Node>, from: &Type, to: &Type) -> bool {
+        if from == to {
+            return true;
+        }
+        if to.kind == TypeKind::Pointer && is_null(node) {
+            let u64_type = self.resolve_type(&"u64".to_string());
+            self.overwrite_type(node, &u64_type);
+            return true;
+        }
+        if from.kind != to.kind || !matches!(from.kind, TypeKind::Numeric | TypeKind::Float) {
+            return false;
+        }
+
+        if is_literal(node) {
+            self.overwrite_type(node, to);
+            return true;
+        }
+
+        let (line, column) = node.loc().unwrap_or_default();
+        if to.size < from.size {
+            error::panic(format!(
+                "{}:{} Implicit narrowing from {} to {}, convert with `as {}`",
+                line, column, from.name, to.name, to.name
+            ));
+        }
+        if from.kind == TypeKind::Numeric && !holds_all_of(to, from) {
+            error::panic(format!(
+                "{}:{} Implicit conversion from {} to {} can change the value, convert with `as {}`",
+                line, column, from.name, to.name, to.name
+            ));
+        }
+
+        self.insert_cast(node, to);
+        true
+    }
+
+    fn insert_cast(&mut self, node: &mut Box<Node>, to: &Type) {
+        let value = std::mem::replace(
+            node,
+            Box::new(Node::BoolLiteral(false, Default::default(), 0, 0)),
+        );
+        let loc = value.loc().unwrap_or_default();
+        *node = Cast::new(value, loc, to.name.clone(), to.into());
+    }
+
     pub fn check(&mut self, node: &mut Box<Node>) -> Type {
+        let expected = self.expected.take();
         match &mut **node {
             Node::Number(_, size, _, _) => {
-                let tipe = self.resolve_type(&"i32".to_string());
+                let tipe = match expected {
+                    Some(tipe) if tipe.kind == TypeKind::Numeric => tipe,
+                    _ => self.resolve_type(&"i32".to_string()),
+                };
                 *size = (&tipe).into();
+                self.check_literal(node, &tipe);
                 tipe
             }
             Node::Float(_, size, _, _) => {
-                let tipe = self.resolve_type(&"f64".to_string());
+                let tipe = match expected {
+                    Some(tipe) if tipe.kind == TypeKind::Float => tipe,
+                    _ => self.resolve_type(&"f64".to_string()),
+                };
                 *size = (&tipe).into();
+                self.check_literal(node, &tipe);
                 tipe
             }
             Node::BoolLiteral(_, size, _, _) => {
@@ -340,12 +668,46 @@ impl TypeCheck {
                 *size = (&tipe).into();
                 tipe
             }
-            Node::ArrayLiteral(items, size, _, _) => {
-                let tipe = self.check(&mut items[0]);
-                *size = (&tipe).into();
-                size.size = size.size * items.len();
+            Node::ArrayLiteral(items, element_type, _, _) => {
+                // Items take the element type of the declared array, or the
+                // one of the first item
+                let mut element = expected.and_then(|tipe| tipe.element).map(|tipe| *tipe);
+                for item in items.iter_mut() {
+                    let item_type = self.check_expecting(item, element.clone());
+                    let element = element.get_or_insert_with(|| item_type.clone()).clone();
+                    if !self.coerce(item, &item_type, &element) {
+                        let (line, column) = item.loc().unwrap_or_default();
+                        self.report(format!(
+                            "{}:{} Array item of type {} where {} is expected",
+                            line, column, item_type.name, element.name
+                        ));
+                    }
+                }
 
-                tipe
+                let Some(element) = element else {
+                    let (line, column) = node.loc().unwrap_or_default();
+                    error::panic(format!(
+                        "{}:{} The element type of an empty array literal has to be declared",
+                        line, column
+                    ));
+                };
+                *element_type = (&element).into();
+                Type::array(element, items.len())
+            }
+            Node::ArrayRepeat(repeat) => {
+                let element = expected.and_then(|tipe| tipe.element).map(|tipe| *tipe);
+                let value_type = self.check_expecting(&mut repeat.value, element.clone());
+                let element = element.unwrap_or_else(|| value_type.clone());
+                if !self.coerce(&mut repeat.value, &value_type, &element) {
+                    let (line, column) = repeat.value.loc().unwrap_or_default();
+                    self.report(format!(
+                        "{}:{} Array item of type {} where {} is expected",
+                        line, column, value_type.name, element.name
+                    ));
+                }
+
+                repeat.element = (&element).into();
+                Type::array(element, repeat.count)
             }
             Node::StringLiteral(literal, _, _) => {
                 self.create_type(Type::new(
@@ -356,20 +718,106 @@ impl TypeCheck {
                 ));
                 self.resolve_type(&"str".to_string())
             }
-            Node::VarGet(name, _, _) => self.resolve_local(name),
-            Node::Binary(binary) => {
-                let mut l_type = self.check(&mut binary.lhs);
+            Node::VarGet(_, _, _) | Node::Index(_) | Node::Deref(_) => {
+                let tipe = self.check_place(node);
+
+                // An array where a slice is expected is a slice of all of its
+                // elements
+                let expected = expected.filter(|expected| expected.kind == TypeKind::Slice);
+                if let Some(expected) = expected {
+                    if tipe.kind == TypeKind::Array && tipe.element == expected.element {
+                        let loc = node.loc().unwrap_or_default();
+                        let array = std::mem::replace(
+                            node,
+                            Box::new(Node::BoolLiteral(false, Default::default(), 0, 0)),
+                        );
+                        *node = Slice::new(array, None, None, loc);
+                        return self.check(node);
+                    }
+                }
+
+                self.check_not_array(node, &tipe);
+                tipe
+            }
+            Node::Slice(slice) => self.check_slice(slice),
+            Node::Binary(binary) if std::matches!(binary.op, BinaryOp::Shl | BinaryOp::Shr) => {
+                // The shifted value gives the type, the count can be any integer
+                let l_type = self.check_expecting(&mut binary.lhs, expected);
                 let r_type = self.check(&mut binary.rhs);
+                if l_type.kind != TypeKind::Numeric || r_type.kind != TypeKind::Numeric {
+                    let (line, column) = binary.lhs.loc().unwrap_or_default();
+                    error::panic(format!(
+                        "{}:{} Cannot shift {} by {}, shifts need integers",
+                        line, column, l_type.name, r_type.name
+                    ));
+                }
+                binary.dtype = (&l_type).into();
+                l_type
+            }
+            Node::Binary(binary) => {
+                // A literal operand takes the type of the other one, two
+                // literals take the type the result is expected to have
+                let (l_literal, r_literal) = (is_literal(&binary.lhs), is_literal(&binary.rhs));
+                let (mut l_type, r_type) = if l_literal && !r_literal {
+                    let r_type = self.check(&mut binary.rhs);
+                    let l_type = self.check_expecting(&mut binary.lhs, Some(r_type.clone()));
+                    (l_type, r_type)
+                } else if r_literal && !l_literal {
+                    let l_type = self.check(&mut binary.lhs);
+                    let r_type = self.check_expecting(&mut binary.rhs, Some(l_type.clone()));
+                    (l_type, r_type)
+                } else {
+                    let expected = match binary.op.is_comparison() {
+                        true => None,
+                        false => expected,
+                    };
+                    let l_type = self.check_expecting(&mut binary.lhs, expected.clone());
+                    let r_type = self.check_expecting(&mut binary.rhs, expected);
+                    (l_type, r_type)
+                };
 
+                // Otherwise the smaller operand is widened
                 if l_type != r_type {
-                    if l_type.kind != TypeKind::Numeric {
-                        error::panic_str("Binary operands are of different types");
-                    }
+                    let convert_lhs = if l_literal != r_literal {
+                        l_literal
+                    } else {
+                        l_type < r_type
+                    };
 
-                    if l_type < r_type {
+                    let converted = if convert_lhs {
+                        self.coerce(&mut binary.lhs, &l_type, &r_type)
+                    } else {
+                        self.coerce(&mut binary.rhs, &r_type, &l_type)
+                    };
+
+                    if !converted {
+                        let (line, column) = binary.lhs.loc().unwrap_or_default();
+                        error::panic(format!(
+                            "{}:{} Binary operands are of different types, {} and {}",
+                            line, column, l_type.name, r_type.name
+                        ));
+                    }
+                    if convert_lhs {
                         l_type = r_type.clone();
                     }
                 }
+                binary.dtype = (&l_type).into();
+
+                if l_type.kind == TypeKind::Slice {
+                    let (line, column) = binary.lhs.loc().unwrap_or_default();
+                    error::panic(format!(
+                        "{}:{} Operator '{}' can't be used on slices",
+                        line, column, binary.op
+                    ));
+                }
+
+                if binary.overflow != Overflow::Unchecked && l_type.kind != TypeKind::Numeric {
+                    let (line, column) = binary.lhs.loc().unwrap_or_default();
+                    error::panic(format!(
+                        "{}:{} {} arithmetic needs integers, not {}",
+                        line, column, binary.overflow, l_type.name
+                    ));
+                }
 
                 match binary.op {
                     BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
@@ -379,12 +827,17 @@ impl TypeCheck {
                         }
                         l_type
                     }
-                    BinaryOp::Greater
-                    | BinaryOp::GreaterEq
-                    | BinaryOp::Less
-                    | BinaryOp::LessEq
-                    | BinaryOp::Equal
-                    | BinaryOp::NotEqual => self.resolve_type(&"bool".to_string()),
+                    BinaryOp::Rem | BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => {
+                        if l_type.kind != TypeKind::Numeric {
+                            let (line, column) = binary.lhs.loc().unwrap_or_default();
+                            error::panic(format!(
+                                "{}:{} Operator '{}' needs integers, not {}",
+                                line, column, binary.op, l_type.name
+                            ));
+                        }
+                        l_type
+                    }
+                    _ => self.resolve_type(&"bool".to_string()),
                 }
             }
             Node::Function(func) => {
@@ -394,17 +847,32 @@ impl TypeCheck {
                 };
 
                 let tipe = self.resolve_type(&ret_type);
+                if std::matches!(tipe.kind, TypeKind::Array | TypeKind::Slice) {
+                    error::panic(format!(
+                        "{}:{} Cannot return {}, only pointers to arrays and slices",
+                        func.loc.0, func.loc.1, tipe.name
+                    ));
+                }
                 func.ret_type = (&tipe).into();
 
                 self.locals.insert(func.name.clone(), tipe.clone());
 
                 for arg in &mut func.args {
                     let arg_type = self.resolve_type(&arg.dtype);
+                    if arg_type.kind == TypeKind::Array {
+                        error::panic(format!(
+                            "{}:{} Cannot pass the array {} by value, take a ptr<{}>",
+                            arg.name_loc.0, arg.name_loc.1, arg_type.name, arg_type.name
+                        ));
+                    }
                     arg.size = arg_type.size;
+                    arg.tipe = (&arg_type).into();
                     self.locals.insert(arg.name.clone(), arg_type);
                 }
 
+                self.ret_type = Some(tipe.clone());
                 self.check(&mut func.body);
+                self.ret_type = None;
 
                 self.locals.remove(&func.name);
                 for arg in &func.args {
@@ -414,44 +882,43 @@ impl TypeCheck {
                 tipe
             }
             Node::VarDecl(decl) => {
-                let val_type = self.check(&mut decl.value);
+                let literal_length = match &*decl.value {
+                    Node::ArrayLiteral(items, _, _, _) => Some(items.len()),
+                    Node::ArrayRepeat(repeat) => Some(repeat.count),
+                    _ => None,
+                };
+                let ex_type = decl.dtype_str.as_ref().map(|name| {
+                    // `i32[]` takes the length of the literal
+                    match (array_bracket(name), literal_length) {
+                        (Some(bracket), Some(length)) if name[bracket..].starts_with("[]") => {
+                            let name =
+                                format!("{}[{}]{}", &name[..bracket], length, &name[bracket + 2..]);
+                            self.resolve_type(&name)
+                        }
+                        _ => self.resolve_type(name),
+                    }
+                });
+                if let (Some(ex_type), Some(length)) = (&ex_type, literal_length) {
+                    if ex_type.length != length {
+                        let (line, column) = decl.value.loc().unwrap_or_default();
+                        error::panic(format!(
+                            "{}:{} Declared array size is {} while the array literal size is {}",
+                            line, column, ex_type.length, length
+                        ));
+                    }
+                }
+
+                let val_type = self.check_expecting(&mut decl.value, ex_type.clone());
                 if let Some(locals) = &mut self.created_locals {
-                    locals.push(decl.name.clone());
+                    locals.push((decl.name.clone(), self.locals.get(&decl.name).cloned()));
                 }
 
                 // If we got an explicit type
-                if let Some(ex_dt) = &decl.dtype_str {
-                    let mut ex_type = self.resolve_type(ex_dt);
-
-                    if ex_type.kind == TypeKind::Numeric {
-                        if ex_type.kind != val_type.kind {
-                            error::panic_str(
-                                "Explicit variable type, doesn't equal the value type",
-                            );
-                        }
-                        self.overwrite_type(&mut decl.value, &ex_type);
-                    } else if ex_type.kind == TypeKind::Float {
-                        if ex_type.kind != val_type.kind {
-                            error::panic_str(
-                                "Explicit variable type, doesn't equal the value type",
-                            );
-                        }
-                    } else {
-                        if ex_type != val_type {
-                            error::panic_str(
-                                "Explicit variable type, doesn't equal the value type",
-                            );
-                        }
-                    }
-
-                    let mut array_size = 1;
-                    match &*decl.value {
-                        Node::ArrayLiteral(it, _, _, _) => array_size = it.len(),
-                        _ => (),
+                if let Some(ex_type) = ex_type {
+                    if !self.coerce(&mut decl.value, &val_type, &ex_type) {
+                        error::panic_str("Explicit variable type, doesn't equal the value type");
                     }
 
-                    ex_type.size = ex_type.size * array_size;
-
                     decl.dtype = (&ex_type).into();
                     self.locals.insert(decl.name.clone(), ex_type.clone());
                     ex_type
@@ -462,39 +929,46 @@ impl TypeCheck {
                 }
             }
             Node::Unary(unary) => {
+                let expected = match unary.op {
+                    UnaryOp::Negate | UnaryOp::BitNot => expected,
+                    _ => None,
+                };
+                let expr_type = self.check_expecting(&mut unary.expr, expected);
+                if unary.op == UnaryOp::BitNot && expr_type.kind != TypeKind::Numeric {
+                    error::panic(format!(
+                        "{}:{} Operator '~' needs an integer, not {}",
+                        unary.op_loc.0, unary.op_loc.1, expr_type.name
+                    ));
+                }
                 if unary.op == UnaryOp::Not {
                     self.resolve_type(&"bool".to_string())
                 } else {
-                    self.check(&mut unary.expr)
+                    expr_type
                 }
             }
             Node::Logical(logical) => {
+                let bool_type = self.resolve_type(&"bool".to_string());
                 let l_type = self.check(&mut logical.lhs);
                 let r_type = self.check(&mut logical.rhs);
 
-                if l_type != r_type {
-                    error::panic_str("Logical expression has invalid operands");
+                if l_type != bool_type || r_type != bool_type {
+                    error::panic(format!("Operands of '{}' must be of type bool", logical.op));
                 }
 
-                l_type
+                bool_type
             }
             Node::Assign(assign) => {
                 let local = self.resolve_local(&assign.name);
-                let val_type = self.check(&mut assign.value);
+                if local.kind == TypeKind::Array {
+                    error::panic(format!(
+                        "{}:{} Cannot assign to the array {}, only to its elements",
+                        assign.name_loc.0, assign.name_loc.1, assign.name
+                    ));
+                }
+                let val_type = self.check_expecting(&mut assign.value, Some(local.clone()));
 
-                if val_type.kind == TypeKind::Numeric {
-                    if val_type.kind != local.kind {
-                        error::panic_str("Original variable type, doesn't equal the value type");
-                    }
-                } else if val_type.kind == TypeKind::Float {
-                    if val_type.kind != local.kind {
-                        error::panic_str("Original variable type, doesn't equal the value type");
-                    }
-                } else {
-                    if val_type != local {
-                        println!("Val type {:#?} local {:#?}", val_type, local);
-                        error::panic_str("Original variable type, doesn't equal the value type");
-                    }
+                if !self.coerce(&mut assign.value, &val_type, &local) {
+                    error::panic_str("Original variable type, doesn't equal the value type");
                 }
 
                 local
@@ -511,9 +985,12 @@ impl TypeCheck {
                     self.check(node);
                 }
 
-                if let Some(locals) = &self.created_locals {
-                    for local in locals {
-                        self.locals.remove(local);
+                if let Some(locals) = self.created_locals.take() {
+                    for (local, shadowed) in locals.into_iter().rev() {
+                        match shadowed {
+                            Some(tipe) => self.locals.insert(local, tipe),
+                            None => self.locals.remove(&local),
+                        };
                     }
                 }
 
@@ -534,9 +1011,261 @@ impl TypeCheck {
 
                 self.resolve_type(&"void".to_string())
             }
-            Node::GetPtr(_) => self.resolve_type(&"ptr".to_string()),
-            Node::Grouping(grouping) => self.check(&mut grouping.expr),
-            _ => todo!(),
+            Node::Ret(ret) => {
+                let ret_type = match &self.ret_type {
+                    Some(tipe) => tipe.clone(),
+                    None => error::panic_str("Return statement outside of a function"),
+                };
+
+                match &mut ret.value {
+                    Some(value) => {
+                        let val_type = self.check_expecting(value, Some(ret_type.clone()));
+                        if !self.coerce(value, &val_type, &ret_type) {
+                            error::panic(format!(
+                                "{}:{} Returned value doesn't match the function return type",
+                                ret.loc.0, ret.loc.1
+                            ));
+                        }
+                    }
+                    None => {
+                        if ret_type.kind != TypeKind::None {
+                            error::panic(format!(
+                                "{}:{} Expected a return value",
+                                ret.loc.0, ret.loc.1
+                            ));
+                        }
+                    }
+                }
+
+                self.resolve_type(&"void".to_string())
+            }
+            Node::GetPtr(get_ptr) => {
+                if !std::matches!(
+                    *get_ptr.expr,
+                    Node::VarGet(_, _, _) | Node::Deref(_) | Node::Index(_)
+                ) {
+                    let (line, column) = get_ptr.expr.loc().unwrap_or_default();
+                    error::panic(format!(
+                        "{}:{} Cannot take the address of a temporary value, only of variables, dereferenced pointers and array elements",
+                        line, column
+                    ));
+                }
+                Type::pointer(self.check_place(&mut get_ptr.expr))
+            }
+            Node::Store(store) => {
+                let target = self.check(&mut store.target);
+                let val_type = self.check_expecting(&mut store.value, Some(target.clone()));
+
+                if !self.coerce(&mut store.value, &val_type, &target) {
+                    let (line, column) = store.target.loc().unwrap_or_default();
+                    error::panic(format!(
+                        "{}:{} Cannot store {} where {} is expected",
+                        line, column, val_type.name, target.name
+                    ));
+                }
+
+                target
+            }
+            Node::Len(len) => {
+                let tipe = self.check_place(&mut len.expr);
+                match tipe.kind {
+                    TypeKind::Array => (),
+                    TypeKind::Slice => return self.resolve_type(&"u64".to_string()),
+                    _ => error::panic(format!(
+                        "{}:{} Cannot take the length of {}, it isn't an array or a slice",
+                        len.loc.0, len.loc.1, tipe.name
+                    )),
+                }
+
+                // The length of an array is known, it becomes a literal
+                let length_type = match expected {
+                    Some(tipe) if tipe.kind == TypeKind::Numeric => tipe,
+                    _ => self.resolve_type(&"u64".to_string()),
+                };
+                let (line, column) = len.loc;
+                **node = Node::Number(tipe.length.to_string(), (&length_type).into(), line, column);
+                self.check_literal(node, &length_type);
+                length_type
+            }
+            Node::Cast(cast) => {
+                let from = self.check(&mut cast.expr);
+                let to = self.resolve_type(&cast.dtype_str);
+                if to.kind == TypeKind::Pointer && self.coerce(&mut cast.expr, &from, &to) {
+                    cast.dtype = (&to).into();
+                    return to;
+                }
+
+                let convertible = std::matches!(
+                    (from.kind, to.kind),
+                    (
+                        TypeKind::Numeric | TypeKind::Float,
+                        TypeKind::Numeric | TypeKind::Float
+                    ) | (TypeKind::Bool, TypeKind::Numeric)
+                );
+                if !convertible {
+                    error::panic(format!(
+                        "{}:{} Cannot cast {} to {}",
+                        cast.loc.0, cast.loc.1, from.name, to.name
+                    ));
+                }
+
+                cast.dtype = (&to).into();
+                to
+            }
+            Node::For(fr) => {
+                let tipe = self.check_place(&mut fr.target);
+                let Some(element) = tipe.element.clone() else {
+                    error::panic(format!(
+                        "{}:{} Cannot loop over {}, it isn't an array or a slice",
+                        fr.name_loc.0, fr.name_loc.1, tipe.name
+                    ));
+                };
+                if std::matches!(element.kind, TypeKind::Array | TypeKind::Slice) {
+                    error::panic(format!(
+                        "{}:{} Cannot loop over the elements of {}, they aren't single values",
+                        fr.name_loc.0, fr.name_loc.1, tipe.name
+                    ));
+                }
+                fr.dtype = (&*element).into();
+                fr.length = (tipe.kind == TypeKind::Array).then_some(tipe.length);
+
+                // The element is only in scope in the body
+                let shadowed = self.locals.insert(fr.name.clone(), *element);
+                self.check(&mut fr.body);
+                match shadowed {
+                    Some(tipe) => self.locals.insert(fr.name.clone(), tipe),
+                    None => self.locals.remove(&fr.name),
+                };
+
+                self.resolve_type(&"void".to_string())
+            }
+            Node::Grouping(grouping) => self.check_expecting(&mut grouping.expr, expected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Block, parser::Parser, tokenizer::Tokenizer};
+
+    /// Checks every declaration of `source`, returning the checker and the
+    /// body of the first function
+    fn check(source: &str) -> (TypeCheck, Block) {
+        let source = source.to_string();
+        let mut parser = Parser::new(Tokenizer::new(&source), &source);
+        parser.parse();
+
+        let mut typecheck = TypeCheck::new();
+        for decl in &mut parser.declarations {
+            typecheck.check(decl);
+        }
+        let statements = match *parser.declarations.remove(0) {
+            Node::Function(function) => match *function.body {
+                Node::Block(block) => block,
+                body => panic!("{:?}", body),
+            },
+            decl => panic!("{:?}", decl),
+        };
+        (typecheck, statements)
+    }
+
+    /// The value returned by the last statement of the function
+    fn returned(source: &str) -> Box<Node> {
+        let (typecheck, mut body) = check(source);
+        assert!(!typecheck.has_errors());
+        match *body.statements.pop().unwrap() {
+            Node::Ret(ret) => ret.value.unwrap(),
+            statement => panic!("{:?}", statement),
+        }
+    }
+
+    fn literal_type(node: &Node) -> &TaggedType {
+        match node {
+            Node::Number(_, tipe, _, _) | Node::Float(_, tipe, _, _) => tipe,
+            Node::Grouping(grouping) => literal_type(&grouping.expr),
+            _ => panic!("{:?}", node),
         }
     }
+
+    fn numeric(size: usize, signed: bool) -> TaggedType {
+        TaggedType::new(size, TypeKind::Numeric, Some(signed))
+    }
+
+    #[test]
+    fn literals_take_the_type_of_the_other_operand() {
+        let u64_type = numeric(8, false);
+        for source in [
+            "func f(x: u64) -> u64 { ret x + 1; }",
+            "func f(x: u64) -> u64 { ret 1 + x; }",
+        ] {
+            let Node::Binary(binary) = *returned(source) else {
+                panic!("{}", source);
+            };
+            assert_eq!(binary.dtype, u64_type, "{}", source);
+            let literal = match *binary.lhs {
+                Node::Number(_, _, _, _) => &binary.lhs,
+                _ => &binary.rhs,
+            };
+            assert_eq!(*literal_type(literal), u64_type, "{}", source);
+        }
+
+        let Node::Binary(binary) = *returned("func f(y: f32) -> f32 { ret 2.5 * y; }") else {
+            panic!();
+        };
+        let tipe = literal_type(&binary.lhs);
+        assert_eq!((tipe.size, tipe.kind), (4, TypeKind::Float));
+    }
+
+    #[test]
+    fn shifts_have_the_type_of_the_shifted_value() {
+        for source in [
+            "func f(x: u16, n: i64) -> u16 { ret x << n; }",
+            "func f(x: u16) -> u16 { ret x >> 3; }",
+        ] {
+            let Node::Binary(binary) = *returned(source) else {
+                panic!("{}", source);
+            };
+            assert_eq!(binary.dtype, numeric(2, false), "{}", source);
+        }
+    }
+
+    #[test]
+    fn literals_take_the_type_they_are_stored_or_returned_as() {
+        let value = returned("func f(a: i8) -> i16 { ret 7; }");
+        assert_eq!(*literal_type(&value), numeric(2, true));
+
+        let (_, body) = check("func f(a: i8) -> i8 { var x: u16 = 7; y := 7; ret a; }");
+        let types = body
+            .statements
+            .iter()
+            .filter_map(|statement| match &**statement {
+                Node::VarDecl(decl) => Some(literal_type(&decl.value).clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(types, [numeric(2, false), numeric(4, true)]);
+    }
+
+    #[test]
+    fn literals_out_of_range_are_reported_and_checking_goes_on() {
+        let cases = [
+            ("var x: u8 = 255", 0),
+            ("var x: u8 = 256", 1),
+            ("var x: i8 = -128", 0),
+            ("var x: i8 = -129", 1),
+            ("var x: u32 = a + 300", 1),
+            ("x := a + -1", 1),
+            ("var x: f32 = 340000000000000000000000000000000000000.0", 0),
+            ("var x: f32 = 350000000000000000000000000000000000000.0", 1),
+            ("var x: u8 = 256; var y: i64 = 9223372036854775808", 2),
+        ];
+        for (statement, errors) in cases {
+            let (typecheck, _) = check(&format!("func f(a: u8) -> u8 {{ {}; ret a; }}", statement));
+            assert_eq!(typecheck.errors, errors, "{}", statement);
+        }
+
+        let (typecheck, _) = check("func f(a: u8) -> u8 { ret 256; }");
+        assert_eq!(typecheck.errors, 1);
+    }
 }